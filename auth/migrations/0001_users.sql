@@ -0,0 +1,10 @@
+-- databases built by hand before migrations already have this table
+CREATE TABLE IF NOT EXISTS users (
+    id SERIAL PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL
+);
+
+-- unix seconds, like every timestamp in this schema
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS created_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
@@ -0,0 +1,55 @@
+-- refresh tokens made before sessions existed were grouped by family, set them
+-- aside so each family can become a session and nobody is logged out by the upgrade
+DO $$
+BEGIN
+    IF to_regclass('refresh_tokens') IS NOT NULL THEN
+        CREATE TEMP TABLE legacy_refresh_tokens AS
+            SELECT token_hash, user_id, family, expires_at, revoked FROM refresh_tokens;
+        DROP TABLE refresh_tokens;
+    END IF;
+END $$;
+
+-- one per login, revoking it ends every refresh token issued in it
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at BIGINT NOT NULL,
+    last_used_at BIGINT NOT NULL,
+    revoked_at BIGINT
+);
+
+CREATE INDEX sessions_user_idx ON sessions (user_id);
+
+-- only hashes are stored, a token is spent on refresh and replaced by one in the same session
+CREATE TABLE refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at BIGINT NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX refresh_tokens_session_idx ON refresh_tokens (session_id);
+CREATE INDEX refresh_tokens_user_idx ON refresh_tokens (user_id);
+
+-- spent tokens come along too, replaying one still revokes its session
+DO $$
+DECLARE
+    migrated_at BIGINT := EXTRACT(EPOCH FROM now())::BIGINT;
+BEGIN
+    IF to_regclass('pg_temp.legacy_refresh_tokens') IS NOT NULL THEN
+        INSERT INTO sessions (id, user_id, created_at, last_used_at, revoked_at)
+        SELECT family, MIN(user_id), migrated_at, migrated_at,
+            CASE WHEN bool_and(revoked) THEN migrated_at END
+        FROM legacy_refresh_tokens
+        WHERE expires_at > migrated_at
+        GROUP BY family;
+
+        INSERT INTO refresh_tokens (token_hash, session_id, user_id, expires_at, revoked)
+        SELECT token_hash, family, user_id, expires_at, revoked
+        FROM legacy_refresh_tokens
+        WHERE expires_at > migrated_at;
+
+        DROP TABLE legacy_refresh_tokens;
+    END IF;
+END $$;
@@ -0,0 +1,11 @@
+-- account bans, a ban without expires_at is permanent
+CREATE TABLE bans (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    reason TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT,
+    lifted_at BIGINT
+);
+
+CREATE INDEX bans_user_idx ON bans (user_id);
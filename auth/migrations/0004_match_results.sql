@@ -0,0 +1,23 @@
+-- one row per finished match on a game server
+CREATE TABLE matches (
+    id BIGSERIAL PRIMARY KEY,
+    server_id TEXT NOT NULL,
+    room_id INTEGER NOT NULL,
+    mode TEXT NOT NULL,
+    started_at BIGINT NOT NULL,
+    ended_at BIGINT NOT NULL
+);
+
+CREATE INDEX matches_ended_idx ON matches (ended_at);
+
+-- how each account did in a match, bots are not recorded
+CREATE TABLE match_players (
+    match_id BIGINT NOT NULL REFERENCES matches (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    kills INTEGER NOT NULL,
+    deaths INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    PRIMARY KEY (match_id, user_id)
+);
+
+CREATE INDEX match_players_user_idx ON match_players (user_id);
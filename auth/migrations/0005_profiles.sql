@@ -0,0 +1,13 @@
+-- cosmetic choices, accounts without a row show their username and the default ship color
+CREATE TABLE profiles (
+    user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    display_name TEXT NOT NULL,
+    ship_color TEXT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+-- lifetime stats are summed from here
+ALTER TABLE match_players
+    ADD COLUMN asteroids_destroyed INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN seconds_played BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN won BOOLEAN NOT NULL DEFAULT FALSE;
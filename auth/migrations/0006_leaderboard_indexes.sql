@@ -0,0 +1,7 @@
+-- leaderboards pick the matches of a window by end time, then sum their players' results;
+-- both indexes cover what those queries read so they don't touch the tables
+DROP INDEX matches_ended_idx;
+CREATE INDEX matches_ended_idx ON matches (ended_at) INCLUDE (id);
+
+CREATE INDEX match_players_results_idx ON match_players (match_id)
+    INCLUDE (user_id, kills, deaths, score);
@@ -0,0 +1,28 @@
+-- usernames are unique regardless of case; accounts that clash with an older one
+-- get their id appended so the index can be built, and a counter on top when even
+-- that name is taken
+DO $$
+DECLARE
+    clash RECORD;
+    candidate TEXT;
+    attempt INTEGER;
+BEGIN
+    FOR clash IN
+        SELECT id, username FROM users
+        WHERE EXISTS (
+            SELECT 1 FROM users older
+            WHERE lower(older.username) = lower(users.username) AND older.id < users.id
+        )
+        ORDER BY id
+    LOOP
+        candidate := clash.username || '_' || clash.id;
+        attempt := 1;
+        WHILE EXISTS (SELECT 1 FROM users WHERE lower(username) = lower(candidate)) LOOP
+            attempt := attempt + 1;
+            candidate := clash.username || '_' || clash.id || '_' || attempt;
+        END LOOP;
+        UPDATE users SET username = candidate WHERE id = clash.id;
+    END LOOP;
+END $$;
+
+CREATE UNIQUE INDEX users_username_lower_idx ON users (lower(username));
@@ -0,0 +1,13 @@
+-- one row per pair of accounts, whoever asked first; a request until accepted_at is set
+CREATE TABLE friends (
+    requester_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    addressee_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at BIGINT NOT NULL,
+    accepted_at BIGINT,
+    PRIMARY KEY (requester_id, addressee_id),
+    CHECK (requester_id <> addressee_id)
+);
+
+CREATE UNIQUE INDEX friends_pair_idx
+    ON friends (LEAST(requester_id, addressee_id), GREATEST(requester_id, addressee_id));
+CREATE INDEX friends_addressee_idx ON friends (addressee_id);
@@ -0,0 +1,5 @@
+-- deleted accounts keep their row so bans and other players' match history stay;
+-- the username is cleared to free it, NULLs don't clash in the unique index
+ALTER TABLE users
+    ADD COLUMN deleted_at BIGINT,
+    ALTER COLUMN username DROP NOT NULL;
@@ -0,0 +1,224 @@
+//! Changing what an account logs in with, and deleting it.
+
+use axum::{
+    extract::{ConnectInfo, Json, State},
+    http::StatusCode,
+};
+use common::profile::Profile;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    AppState,
+    dto::{AuthResponse, ChangePasswordRequest, DeleteAccountRequest, RenameRequest},
+    models::{AppError, User},
+    tokens::AuthUser,
+};
+
+const USERNAME_LEN: RangeInclusive<usize> = 3..=16;
+/// bcrypt ignores everything past this many bytes.
+const MAX_PASSWORD_BYTES: usize = 72;
+/// Names players could take for staff or the game itself, compared in lowercase.
+const RESERVED_USERNAMES: &[&str] = &[
+    "admin",
+    "administrator",
+    "anonymous",
+    "bot",
+    "guest",
+    "moderator",
+    "null",
+    "root",
+    "server",
+    "staff",
+    "support",
+    "system",
+];
+
+/// What a password needs to be accepted. Existing passwords aren't checked again.
+#[derive(Debug, Clone)]
+pub struct PasswordRules {
+    pub min_length: usize,
+    /// Of lowercase letters, uppercase letters, digits and everything else.
+    pub min_classes: usize,
+}
+
+impl PasswordRules {
+    /// Reads the rules from the environment:
+    /// - `PASSWORD_MIN_LENGTH`, in characters, default 8
+    /// - `PASSWORD_MIN_CLASSES`, how many kinds of characters it mixes, 1 to 4, default 2
+    pub fn from_env() -> Self {
+        fn var(name: &str, default: usize) -> usize {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            min_length: var("PASSWORD_MIN_LENGTH", 8).max(1),
+            min_classes: var("PASSWORD_MIN_CLASSES", 2).clamp(1, 4),
+        }
+    }
+
+    pub fn check(&self, password: &str, username: &str) -> Result<(), AppError> {
+        if password.chars().count() < self.min_length {
+            return Err(AppError::BadRequest(format!(
+                "Password must be at least {} characters",
+                self.min_length
+            )));
+        }
+        if password.len() > MAX_PASSWORD_BYTES {
+            return Err(AppError::BadRequest(format!(
+                "Password must be at most {MAX_PASSWORD_BYTES} bytes"
+            )));
+        }
+
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password
+                .chars()
+                .any(|c| !c.is_lowercase() && !c.is_uppercase() && !c.is_ascii_digit()),
+        ];
+        if classes.iter().filter(|present| **present).count() < self.min_classes {
+            return Err(AppError::BadRequest(format!(
+                "Password must mix at least {} of lowercase letters, uppercase letters, digits and symbols",
+                self.min_classes
+            )));
+        }
+
+        if password.eq_ignore_ascii_case(username) {
+            return Err(AppError::BadRequest(
+                "Password can't be the username".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Usernames are ASCII letters, digits, `_` and `-`, starting with a letter.
+pub fn username(name: &str) -> Result<String, AppError> {
+    player_name(name, USERNAME_LEN, "Usernames")
+}
+
+/// The username rules with another length, for any name shown to other players,
+/// so none of them can pass for staff or use look-alike characters. `what` starts the error.
+pub fn player_name(name: &str, len: RangeInclusive<usize>, what: &str) -> Result<String, AppError> {
+    let valid_chars = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !len.contains(&name.len()) || !valid_chars {
+        return Err(AppError::BadRequest(format!(
+            "{what} are {} to {} letters, digits, _ or -, starting with a letter",
+            len.start(),
+            len.end()
+        )));
+    }
+    if RESERVED_USERNAMES.contains(&name.to_ascii_lowercase().as_str()) {
+        return Err(AppError::BadRequest(format!("The name {name} is reserved")));
+    }
+    Ok(name.to_string())
+}
+
+/// Changes the password and ends every session, the caller gets a fresh one.
+#[instrument(skip_all, fields(user_id = user.id))]
+pub async fn change_password(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    user: AuthUser,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<Json<AuthResponse>, AppError> {
+    let account = confirm_password(&state, peer.ip(), user.id, req.current_password).await?;
+    state
+        .password_rules
+        .check(&req.new_password, &account.username)?;
+
+    let password_hash = state.passwords.hash(req.new_password).await?;
+    let now = (common::utils::current_time_ms() / 1000) as i64;
+    if !state
+        .storage
+        .change_password(account.id, &password_hash, now)
+        .await?
+    {
+        return Err(AppError::NotFound("Unknown player".into()));
+    }
+
+    let refresh_token = state
+        .refresh_tokens
+        .issue(&*state.storage, account.id)
+        .await?;
+    info!("Password changed, other sessions revoked");
+    Ok(Json(crate::auth_response(&state, &account, refresh_token)?))
+}
+
+/// Access tokens carry the username, so the old one shows in game until they're refreshed.
+#[instrument(skip_all, fields(user_id = user.id))]
+pub async fn rename(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(req): Json<RenameRequest>,
+) -> Result<Json<Profile>, AppError> {
+    let new_name = username(&req.username)?;
+    if !state.storage.rename_user(user.id, &new_name).await? {
+        return Err(AppError::NotFound("Unknown player".into()));
+    }
+    info!(username = %new_name, "Account renamed");
+
+    state
+        .storage
+        .profile(user.id)
+        .await?
+        .map(Json)
+        .ok_or(AppError::NotFound("Unknown player".into()))
+}
+
+/// Deletes the account and frees its username. Access tokens already handed out stay
+/// valid until they expire: game servers verify them offline and still let their holder
+/// join a match in that window, which `TOKEN_TTL_SECS` keeps short.
+#[instrument(skip_all, fields(user_id = user.id))]
+pub async fn delete_account(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    user: AuthUser,
+    Json(req): Json<DeleteAccountRequest>,
+) -> Result<StatusCode, AppError> {
+    let account = confirm_password(&state, peer.ip(), user.id, req.password).await?;
+    let now = (common::utils::current_time_ms() / 1000) as i64;
+    if !state.storage.delete_user(account.id, now).await? {
+        return Err(AppError::NotFound("Unknown player".into()));
+    }
+    state.parties.forget(account.id);
+    info!("Account deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Checks the password of a logged in player, throttled like a login.
+async fn confirm_password(
+    state: &AppState,
+    ip: IpAddr,
+    user_id: i32,
+    password: String,
+) -> Result<User, AppError> {
+    let account = state
+        .storage
+        .user_by_id(user_id)
+        .await?
+        .ok_or(AppError::NotFound("Unknown player".into()))?;
+    crate::throttle_login(state, ip, Some(&account.username))?;
+
+    if !state
+        .passwords
+        .verify(password, account.password_hash.clone())
+        .await?
+    {
+        warn!(%ip, "Rejected account change with a wrong password");
+        state.login_throttle.failed(ip, &account.username);
+        return Err(AppError::Unauthorized("Wrong password".into()));
+    }
+    state.login_throttle.succeeded(ip, &account.username);
+    Ok(account)
+}
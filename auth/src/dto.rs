@@ -1,13 +1,6 @@
+use common::leaderboard::{LeaderboardStat, LeaderboardWindow};
 use serde::{Deserialize, Serialize};
 
-enum AppError {
-    BadRequest(String),
-    Unauthorized(String),
-    NotFound(String),
-    Conflict(String),
-    Internal(String),
-}
-
 #[derive(Deserialize, Serialize, Clone)]
 pub struct LoginRequest {
     pub username: String,
@@ -16,7 +9,19 @@ pub struct LoginRequest {
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct AuthResponse {
-    pub id: i32,    
+    pub id: i32,
+    /// Access token, presented to game servers in the handshake.
+    pub token: String,
+    /// Seconds until `token` expires, refresh before then.
+    pub expires_in: u64,
+    /// Single use, trade it at `/token/refresh` for a new pair.
+    pub refresh_token: String,
+    pub refresh_expires_in: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -25,7 +30,64 @@ pub struct RegisterReqeust {
     pub password: String
 }
 
-#[derive(Deserialize, Serialize, Clone)]
-pub struct GetServerListResponse {
+#[derive(Deserialize, Clone)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RenameRequest {
+    pub username: String,
+}
+
+/// The password again, so a stolen access token can't delete the account.
+#[derive(Deserialize, Clone)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+/// Query of `GET /servers`, every filter is optional.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ServerListQuery {
+    pub region: Option<String>,
+    pub mode: Option<String>,
+    /// Leave out servers without a free slot.
+    pub not_full: bool,
+    /// Also list servers that are draining or shutting down.
+    pub include_closed: bool,
+    pub sort: ServerSort,
+    pub order: SortOrder,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerSort {
+    #[default]
+    Players,
+    /// Free slots.
+    Free,
+    Name,
+    Region,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
 
+/// Query of `GET /leaderboard` and `GET /leaderboard/me`.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct LeaderboardQuery {
+    pub stat: LeaderboardStat,
+    pub window: LeaderboardWindow,
+    /// Page size, capped at 100.
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
 }
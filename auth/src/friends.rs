@@ -0,0 +1,124 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+};
+use common::social::{FriendRequest, FriendState, FriendsResponse};
+use common::utils::current_time_ms;
+use std::sync::Arc;
+use tracing::{info, instrument};
+
+use crate::{AppState, models::AppError, storage::FriendRequestOutcome, tokens::AuthUser};
+
+/// Friends and open requests one account may have, so requests can't be sprayed around.
+const MAX_FRIENDS: usize = 100;
+
+pub async fn list_friends(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<Json<FriendsResponse>, AppError> {
+    let mut friends = state.storage.friends(user.id).await?;
+
+    // requests don't get to see where someone plays
+    let ids: Vec<i32> = friends
+        .iter()
+        .filter(|friend| friend.state == FriendState::Friends)
+        .map(|friend| friend.user_id as i32)
+        .collect();
+    let mut presence = state.servers.presence(&ids);
+    for friend in &mut friends {
+        friend.presence = presence.remove(&(friend.user_id as i32));
+    }
+
+    Ok(Json(FriendsResponse { friends }))
+}
+
+#[instrument(skip_all, fields(user_id = user.id, to = %req.username))]
+pub async fn add_friend(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(req): Json<FriendRequest>,
+) -> Result<StatusCode, AppError> {
+    let other = state
+        .storage
+        .user_by_name(&req.username)
+        .await?
+        .ok_or(AppError::NotFound(format!(
+            "Unknown player {}",
+            req.username
+        )))?;
+    if other.id == user.id {
+        return Err(AppError::BadRequest("You can't befriend yourself".into()));
+    }
+
+    // a request between the two already counts against both lists
+    let own = state.storage.friends(user.id).await?;
+    if !own.iter().any(|friend| friend.user_id as i32 == other.id) {
+        if own.len() >= MAX_FRIENDS {
+            return Err(AppError::Conflict(format!(
+                "Friend lists are limited to {MAX_FRIENDS} players"
+            )));
+        }
+        if state.storage.friends(other.id).await?.len() >= MAX_FRIENDS {
+            return Err(AppError::Conflict(format!(
+                "{}'s friend list is full",
+                req.username
+            )));
+        }
+    }
+
+    let now = (current_time_ms() / 1000) as i64;
+    match state.storage.request_friend(user.id, other.id, now).await? {
+        FriendRequestOutcome::Sent => {
+            info!(friend_id = other.id, "Friend request sent");
+            Ok(StatusCode::CREATED)
+        }
+        FriendRequestOutcome::Accepted => {
+            info!(friend_id = other.id, "Friend request accepted");
+            Ok(StatusCode::OK)
+        }
+        FriendRequestOutcome::AlreadyFriends => Err(AppError::Conflict(format!(
+            "Already friends with {}",
+            req.username
+        ))),
+        FriendRequestOutcome::AlreadySent => Err(AppError::Conflict(format!(
+            "Already asked {}",
+            req.username
+        ))),
+    }
+}
+
+#[instrument(skip_all, fields(user_id = user.id, requester_id = requester_id))]
+pub async fn accept_friend(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(requester_id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    let now = (current_time_ms() / 1000) as i64;
+    if !state
+        .storage
+        .accept_friend(user.id, requester_id, now)
+        .await?
+    {
+        return Err(AppError::NotFound(format!(
+            "No friend request from player {requester_id}"
+        )));
+    }
+    info!("Friend request accepted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Unfriends, declines a request or withdraws one.
+#[instrument(skip_all, fields(user_id = user.id, other_id = other_id))]
+pub async fn remove_friend(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(other_id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    if !state.storage.remove_friend(user.id, other_id).await? {
+        return Err(AppError::NotFound(format!(
+            "Not friends with player {other_id}"
+        )));
+    }
+    info!("Friend removed");
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,91 @@
+use axum::extract::{Json, Query, State};
+use common::leaderboard::{
+    LeaderboardEntry, LeaderboardResponse, LeaderboardStat, LeaderboardWindow,
+};
+use common::utils::current_time_ms;
+use std::sync::Arc;
+
+use crate::{
+    AppState,
+    dto::LeaderboardQuery,
+    models::AppError,
+    storage::{LeaderboardCursor, LeaderboardScope},
+    tokens::AuthUser,
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+/// Ratios swing wildly over a couple of matches, so they need a few before a player ranks.
+const MIN_MATCHES_FOR_RATIOS: i64 = 3;
+
+const DAY_SECS: i64 = 24 * 3600;
+
+pub async fn leaderboard(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Json<LeaderboardResponse>, AppError> {
+    let after = query
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            LeaderboardCursor::decode(cursor).ok_or(AppError::BadRequest("Invalid cursor".into()))
+        })
+        .transpose()?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let entries = state
+        .storage
+        .leaderboard(&scope(&query), after, limit)
+        .await?;
+    // a full page may be followed by more, an empty next page is fine
+    let next_cursor = match entries.last() {
+        Some(last) if entries.len() == limit as usize => {
+            Some(LeaderboardCursor::after(last).encode())
+        }
+        _ => None,
+    };
+
+    Ok(Json(LeaderboardResponse {
+        stat: query.stat,
+        window: query.window,
+        entries,
+        next_cursor,
+    }))
+}
+
+/// The caller's own entry, on any page.
+pub async fn my_rank(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Json<LeaderboardEntry>, AppError> {
+    state
+        .storage
+        .leaderboard_rank(&scope(&query), user.id)
+        .await?
+        .map(Json)
+        .ok_or(AppError::NotFound(
+            "Not ranked on this leaderboard yet".into(),
+        ))
+}
+
+fn scope(query: &LeaderboardQuery) -> LeaderboardScope {
+    let now = (current_time_ms() / 1000) as i64;
+    let since = match query.window {
+        LeaderboardWindow::Day => now - DAY_SECS,
+        LeaderboardWindow::Week => now - 7 * DAY_SECS,
+        LeaderboardWindow::All => 0,
+    };
+    let min_matches = match query.stat {
+        LeaderboardStat::Kd | LeaderboardStat::Rating => MIN_MATCHES_FOR_RATIOS,
+        LeaderboardStat::Kills | LeaderboardStat::Score => 1,
+    };
+    LeaderboardScope {
+        stat: query.stat,
+        since,
+        min_matches,
+    }
+}
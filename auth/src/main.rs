@@ -1,100 +1,315 @@
+mod accounts;
 mod dto;
+mod friends;
+mod leaderboard;
+mod matchmaking;
 mod models;
+mod parties;
+mod passwords;
+mod profiles;
+mod servers;
+mod storage;
+mod throttle;
+mod tokens;
+
+#[cfg(all(test, feature = "memory"))]
+mod tests;
 
 use axum::{
     Router,
-    extract::{Json, State},
-    routing::post,
+    extract::{ConnectInfo, Json, State},
+    routing::{delete, get, post},
+};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
 };
-use bcrypt::{DEFAULT_COST, hash, verify};
-use sqlx::{PgPool, postgres::PgPoolOptions};
-use std::{io, sync::Arc};
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tracing::{Level, error, info, instrument, warn};
 
 use crate::{
-    dto::{AuthResponse, LoginRequest, RegisterReqeust},
+    accounts::PasswordRules,
+    dto::{AuthResponse, LoginRequest, RefreshRequest, RegisterReqeust},
     models::{AppError, User},
+    parties::PartyRegistry,
+    passwords::PasswordHasher,
+    servers::ServerRegistry,
+    storage::Storage,
+    throttle::{LoginThrottle, ThrottleConfig},
+    tokens::{RefreshTokens, TokenIssuer},
 };
 
+const DEFAULT_SERVER_TTL_SECS: u64 = 15;
+const DEFAULT_RESERVATION_TTL_SECS: u64 = 30;
+// game servers can't tell a token of a deleted account from a live one, keep them short-lived
+const DEFAULT_TOKEN_TTL_SECS: u64 = 300;
+const DEFAULT_REFRESH_TOKEN_TTL_SECS: u64 = 30 * 24 * 3600;
+
 #[derive(Clone)]
 struct AppState {
-    db: PgPool,
+    storage: Arc<dyn Storage>,
+    servers: Arc<ServerRegistry>,
+    parties: Arc<PartyRegistry>,
+    /// Shared secret game servers register with, registration is off without one.
+    registration_token: Option<String>,
+    tokens: TokenIssuer,
+    refresh_tokens: RefreshTokens,
+    passwords: Arc<PasswordHasher>,
+    password_rules: PasswordRules,
+    login_throttle: Arc<LoginThrottle>,
 }
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
     dotenvy::dotenv().ok();
+    common::telemetry::init_tracing();
+
+    let migrate_only = match std::env::args().nth(1).as_deref() {
+        Some("migrate") => true,
+        Some(command) => {
+            error!(command, "Unknown command, the only one is `migrate`");
+            return Err(io::Error::other(format!("unknown command {command}")));
+        }
+        None => false,
+    };
 
+    // `memory` keeps everything in this process, see the `storage` module
     let database_url = std::env::var("DATABASE_URL").expect("missing db url");
-    let pool = PgPoolOptions::new()
-        .max_connections(10)
-        .connect(&database_url)
+    let storage = storage::connect(&database_url)
         .await
-        .unwrap();
+        .map_err(io::Error::other)?;
 
-    println!("Connected to PostreSQL");
+    // `auth migrate` only updates the schema, for deployments that don't migrate on startup
+    if migrate_only {
+        return storage.migrate().await.map_err(io::Error::other);
+    }
+    let migrate_on_startup = std::env::var("MIGRATE_ON_STARTUP")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(true);
+    if migrate_on_startup {
+        storage.migrate().await.map_err(io::Error::other)?;
+    }
 
-    let app_state = Arc::new(AppState { db: pool });
+    let server_ttl = std::env::var("SERVER_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(DEFAULT_SERVER_TTL_SECS);
+    // a reserved slot stays held this long for its player to show up
+    let reservation_ttl = std::env::var("RESERVATION_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(DEFAULT_RESERVATION_TTL_SECS);
+    let servers = Arc::new(ServerRegistry::new(
+        Duration::from_secs(server_ttl.max(3)),
+        Duration::from_secs(reservation_ttl.max(1)),
+    ));
+    tokio::spawn(servers::run_expiry(servers.clone()));
 
-    let app = Router::new()
-        .route("/login", post(login))
-        .route("/register", post(register))
-        // .route("/servers", get(get_server_list))
-        .with_state(app_state);
+    let registration_token = std::env::var("SERVER_REGISTRATION_TOKEN")
+        .ok()
+        .filter(|t| !t.is_empty());
+    if registration_token.is_none() {
+        warn!("SERVER_REGISTRATION_TOKEN is not set, game servers can't register");
+    }
+
+    // shared with the game servers, which verify tokens without calling back here
+    let jwt_secret = std::env::var("JWT_SECRET").expect("missing JWT_SECRET");
+    let token_ttl = std::env::var("TOKEN_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(DEFAULT_TOKEN_TTL_SECS);
+    let refresh_token_ttl = std::env::var("REFRESH_TOKEN_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(DEFAULT_REFRESH_TOKEN_TTL_SECS);
+
+    // bcrypt is slow on purpose, more threads than cores only queue up inside the OS
+    let bcrypt_workers = std::env::var("BCRYPT_WORKERS")
+        .ok()
+        .and_then(|workers| workers.parse().ok())
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1);
+    let login_throttle = Arc::new(LoginThrottle::new(ThrottleConfig::from_env()));
+    tokio::spawn(throttle::run_cleanup(login_throttle.clone()));
+
+    let app_state = Arc::new(AppState {
+        storage,
+        servers,
+        parties: Arc::new(PartyRegistry::default()),
+        registration_token,
+        tokens: TokenIssuer::new(&jwt_secret, Duration::from_secs(token_ttl)),
+        refresh_tokens: RefreshTokens::new(Duration::from_secs(refresh_token_ttl)),
+        passwords: Arc::new(PasswordHasher::new(bcrypt_workers)),
+        password_rules: PasswordRules::from_env(),
+        login_throttle,
+    });
+
+    let app = router(app_state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    info!(addr = %listener.local_addr()?, "Auth service listening");
+    // peer addresses feed the login throttle
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 
     Ok(())
 }
 
+/// Every endpoint of the service, shared by `main` and the tests.
+fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/login", post(login))
+        .route("/register", post(register))
+        .route("/token/refresh", post(refresh))
+        .route("/servers", get(servers::list_servers))
+        .route("/matchmake", post(matchmaking::matchmake))
+        .route("/account", delete(accounts::delete_account))
+        .route("/account/password", post(accounts::change_password))
+        .route("/account/username", post(accounts::rename))
+        .route(
+            "/friends",
+            get(friends::list_friends).post(friends::add_friend),
+        )
+        .route("/friends/:user_id", delete(friends::remove_friend))
+        .route("/friends/:user_id/accept", post(friends::accept_friend))
+        .route(
+            "/party",
+            get(parties::get_party).post(parties::create_party),
+        )
+        .route("/party/invite", post(parties::invite))
+        .route("/party/join", post(parties::join))
+        .route("/party/leave", post(parties::leave))
+        .route("/profile/:user_id", get(profiles::get_profile))
+        .route("/me", get(profiles::me).patch(profiles::update_me))
+        .route("/leaderboard", get(leaderboard::leaderboard))
+        .route("/leaderboard/me", get(leaderboard::my_rank))
+        .route("/servers/register", post(servers::register_server))
+        .route("/servers/:server_id/heartbeat", post(servers::heartbeat))
+        .route("/servers/:server_id/matches", post(servers::record_match))
+        .route("/servers/:server_id", delete(servers::deregister_server))
+        .with_state(state)
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+}
+
+#[instrument(skip_all, fields(username = %req.username))]
 async fn login(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     Json(req): Json<LoginRequest>,
 ) -> Result<Json<AuthResponse>, AppError> {
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
-        .bind(&req.username)
-        .fetch_optional(&state.db)
-        .await
-        .map_err(|e| AppError::Internal(e.to_string()))?
-        .ok_or(AppError::Unauthorized("Invalid credentials".into()))?;
+    let ip = peer.ip();
+    throttle_login(&state, ip, Some(&req.username))?;
+
+    let Some(user) = state.storage.user_by_name(&req.username).await? else {
+        state.passwords.verify_nobody(req.password).await?;
+        state.login_throttle.failed(ip, &req.username);
+        return Err(AppError::Unauthorized("Invalid credentials".into()));
+    };
 
-    let valid = verify(&req.password, &user.password_hash)
-        .map_err(|_| AppError::Internal("Failed to verify password".into()))?;
+    let valid = state
+        .passwords
+        .verify(req.password, user.password_hash.clone())
+        .await?;
 
     if !valid {
+        warn!(%ip, "Rejected login with invalid password");
+        state.login_throttle.failed(ip, &req.username);
         return Err(AppError::Unauthorized("Invalid credentials".into()));
     }
+    state.login_throttle.succeeded(ip, &req.username);
+    ensure_not_banned(&state, user.id).await?;
 
-    Ok(Json(AuthResponse { id: user.id }))
+    let refresh_token = state.refresh_tokens.issue(&*state.storage, user.id).await?;
+    info!(user_id = user.id, "User logged in");
+    Ok(Json(auth_response(&state, &user, refresh_token)?))
 }
 
+#[instrument(skip_all, fields(username = %req.username))]
 async fn register(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     Json(req): Json<RegisterReqeust>,
 ) -> Result<Json<AuthResponse>, AppError> {
-    if req.username.len() < 3 || req.password.len() < 3 {
-        return Err(AppError::BadRequest(
-            "Username must be at least 3 characters and password at least 3".into(),
-        ));
-    }
+    // hashing costs as much as a login, so it shares the address's budget
+    throttle_login(&state, peer.ip(), None)?;
+    let username = accounts::username(&req.username)?;
+    state.password_rules.check(&req.password, &username)?;
 
-    let password_hash = hash(req.password, DEFAULT_COST)
-        .map_err(|_| AppError::Internal("Failed to hash password".into()))?;
+    let password_hash = state.passwords.hash(req.password).await?;
 
-    let user = sqlx::query_as::<_, User>(
-        "INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING *",
-    )
-    .bind(&req.username)
-    .bind(&password_hash)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|e| {
-        if e.to_string().contains("unique") {
-            AppError::Conflict("Username already exists".into())
-        } else {
-            AppError::Internal(e.to_string())
+    let user = state.storage.create_user(&username, &password_hash).await?;
+
+    let refresh_token = state.refresh_tokens.issue(&*state.storage, user.id).await?;
+    info!(user_id = user.id, "User registered");
+    Ok(Json(auth_response(&state, &user, refresh_token)?))
+}
+
+#[instrument(skip_all)]
+async fn refresh(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<Json<AuthResponse>, AppError> {
+    let (user_id, refresh_token) = state
+        .refresh_tokens
+        .rotate(&*state.storage, &req.refresh_token)
+        .await?;
+
+    let user = state
+        .storage
+        .user_by_id(user_id)
+        .await?
+        .ok_or(AppError::Unauthorized("Invalid refresh token".into()))?;
+    ensure_not_banned(&state, user.id).await?;
+
+    info!(user_id, "Tokens refreshed");
+    Ok(Json(auth_response(&state, &user, refresh_token)?))
+}
+
+fn throttle_login(state: &AppState, ip: IpAddr, username: Option<&str>) -> Result<(), AppError> {
+    state
+        .login_throttle
+        .check(ip, username)
+        .map_err(|retry_after| {
+            warn!(%ip, retry_after_secs = retry_after.as_secs(), "Throttled login attempt");
+            AppError::TooManyRequests("Too many attempts, try again later".into(), retry_after)
+        })
+}
+
+/// Refuses accounts with a ban that is neither lifted nor expired.
+async fn ensure_not_banned(state: &AppState, user_id: i32) -> Result<(), AppError> {
+    let now = (common::utils::current_time_ms() / 1000) as i64;
+    match state.storage.active_ban(user_id, now).await? {
+        Some(reason) => {
+            warn!(user_id, "Rejected banned account");
+            Err(AppError::Unauthorized(format!(
+                "Account is banned: {reason}"
+            )))
         }
-    })?;
+        None => Ok(()),
+    }
+}
 
-    Ok(Json(AuthResponse { id: user.id }))
+fn auth_response(
+    state: &AppState,
+    user: &User,
+    refresh_token: String,
+) -> Result<AuthResponse, AppError> {
+    Ok(AuthResponse {
+        id: user.id,
+        token: state.tokens.issue(user)?,
+        expires_in: state.tokens.ttl().as_secs(),
+        refresh_token,
+        refresh_expires_in: state.refresh_tokens.ttl().as_secs(),
+    })
 }
@@ -0,0 +1,89 @@
+use axum::extract::{Json, State};
+use common::registry::{MatchmakeRequest, MatchmakeResponse};
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+use crate::{AppState, models::AppError, parties::MAX_PARTY_SIZE, tokens::AuthUser};
+
+#[instrument(skip_all, fields(user_id = user.id, mode = %req.mode))]
+pub async fn matchmake(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(req): Json<MatchmakeRequest>,
+) -> Result<Json<MatchmakeResponse>, AppError> {
+    // only a party formed with /party gets slots held, its members all accepted an invite
+    let formed = state.parties.party_of(user.id);
+    let leads_party = formed
+        .as_ref()
+        .is_some_and(|formed| formed.leader_id == user.id);
+    let (assignment, party_size) = match formed {
+        // members take the slot their leader's matchmake holds for them, in the leader's
+        // mode even if they asked for another, anything else would split the party
+        Some(formed) if !leads_party => {
+            let assignment = state
+                .parties
+                .held_server(user.id)
+                .and_then(|server_id| state.servers.held(user.id, server_id))
+                .ok_or(AppError::Conflict(
+                    "Wait for your party leader to matchmake".into(),
+                ))?;
+            (assignment, formed.members.len())
+        }
+        formed => {
+            let mut party = formed.map(|formed| formed.members).unwrap_or_default();
+            party.retain(|&id| id != user.id);
+            if party.len() + 1 > MAX_PARTY_SIZE {
+                return Err(AppError::BadRequest(format!(
+                    "Parties are limited to {MAX_PARTY_SIZE} players"
+                )));
+            }
+
+            let Some(assignment) =
+                state
+                    .servers
+                    .reserve(user.id, &party, &req.mode, req.region.as_deref())
+            else {
+                warn!(party = party.len(), "No game server has room");
+                return Err(AppError::Unavailable(
+                    "No game server has room, try again later".into(),
+                ));
+            };
+
+            if leads_party {
+                let server = &assignment.server;
+                let members: Vec<i32> = std::iter::once(user.id)
+                    .chain(party.iter().copied())
+                    .collect();
+                state.parties.set_match(
+                    user.id,
+                    server.id,
+                    &server.info.name,
+                    &server.info.mode,
+                    &members,
+                    assignment.expires_in,
+                );
+            }
+            (assignment, party.len() + 1)
+        }
+    };
+
+    let server = &assignment.server;
+    let ticket = state.tokens.issue_ticket(
+        &user,
+        &server.info.udp_addr,
+        &assignment.ticket_id,
+        assignment.expires_in,
+    )?;
+    info!(server_id = %server.id, party = party_size, "Reserved a slot");
+
+    Ok(Json(MatchmakeResponse {
+        server_id: server.id.to_string(),
+        name: server.info.name.clone(),
+        room_id: server.info.room_id,
+        address: server.info.udp_addr.clone(),
+        region: server.info.region.clone(),
+        mode: server.info.mode.clone(),
+        ticket,
+        expires_in: assignment.expires_in.as_secs(),
+    }))
+}
@@ -1,8 +1,13 @@
-use axum::{Json, http::StatusCode, response::{IntoResponse, Response}};
-use serde::{Serialize, Deserialize};
-use sqlx::prelude::FromRow;
+use axum::{
+    Json,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+/// Columns of `users` the service reads, queries list them explicitly.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
     pub id: i32,
     pub username: String,
@@ -13,6 +18,9 @@ pub enum AppError {
     Unauthorized(String),
     NotFound(String),
     Conflict(String),
+    Unavailable(String),
+    /// Answered with a `Retry-After` of the given wait, in whole seconds.
+    TooManyRequests(String, Duration),
     Internal(String),
 }
 
@@ -23,6 +31,16 @@ impl IntoResponse for AppError {
             AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            AppError::Unavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            AppError::TooManyRequests(msg, retry_after) => {
+                let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, secs.max(1).to_string())],
+                    Json(serde_json::json!({ "error": msg })),
+                )
+                    .into_response();
+            }
             AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
         };
         
@@ -0,0 +1,336 @@
+//! Groups of friends that matchmake together. Parties only live in this process, like the
+//! server registry, and are gone after a restart.
+
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+};
+use common::social::{
+    FriendState, Party, PartyInvite, PartyInviteRequest, PartyJoinRequest, PartyMatch, PartyMember,
+    PartyResponse,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use crate::{AppState, models::AppError, tokens::AuthUser};
+
+/// Largest group that matchmakes together, the leader included.
+pub const MAX_PARTY_SIZE: usize = 8;
+
+#[derive(Default)]
+pub struct PartyRegistry {
+    inner: RwLock<Parties>,
+}
+
+#[derive(Default)]
+struct Parties {
+    parties: HashMap<Uuid, StoredParty>,
+    /// Which party each member is in, an account is in one at most.
+    member_of: HashMap<i32, Uuid>,
+}
+
+struct StoredParty {
+    leader_id: i32,
+    members: Vec<i32>,
+    invited: Vec<i32>,
+    current_match: Option<HeldMatch>,
+}
+
+/// Slots the leader's matchmake reserved for the party.
+struct HeldMatch {
+    server_id: Uuid,
+    name: String,
+    mode: String,
+    /// Members that have a slot, anyone who joined later waits for the next match.
+    members: Vec<i32>,
+    expires_at: Instant,
+}
+
+/// A party as the registry knows it, display names are up to the caller.
+pub struct PartyView {
+    pub id: Uuid,
+    pub leader_id: i32,
+    pub members: Vec<i32>,
+    pub invited: Vec<i32>,
+    pub current_match: Option<PartyMatch>,
+}
+
+impl PartyRegistry {
+    fn create(&self, leader_id: i32) -> Result<Uuid, AppError> {
+        let mut inner = self.inner.write().unwrap();
+        if inner.member_of.contains_key(&leader_id) {
+            return Err(AppError::Conflict("Leave your party first".into()));
+        }
+
+        let id = Uuid::new_v4();
+        inner.parties.insert(
+            id,
+            StoredParty {
+                leader_id,
+                members: vec![leader_id],
+                invited: Vec::new(),
+                current_match: None,
+            },
+        );
+        inner.member_of.insert(leader_id, id);
+        Ok(id)
+    }
+
+    pub fn party_of(&self, user_id: i32) -> Option<PartyView> {
+        let inner = self.inner.read().unwrap();
+        let id = *inner.member_of.get(&user_id)?;
+        let party = &inner.parties[&id];
+        let now = Instant::now();
+
+        Some(PartyView {
+            id,
+            leader_id: party.leader_id,
+            members: party.members.clone(),
+            invited: party.invited.clone(),
+            current_match: party
+                .current_match
+                .as_ref()
+                .filter(|held| held.expires_at > now)
+                .map(|held| PartyMatch {
+                    server_id: held.server_id.to_string(),
+                    name: held.name.clone(),
+                    mode: held.mode.clone(),
+                    expires_in: (held.expires_at - now).as_secs(),
+                }),
+        })
+    }
+
+    /// Parties `user_id` was invited to, with their leaders.
+    fn invites(&self, user_id: i32) -> Vec<(Uuid, i32)> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .parties
+            .iter()
+            .filter(|(_, party)| party.invited.contains(&user_id))
+            .map(|(id, party)| (*id, party.leader_id))
+            .collect()
+    }
+
+    fn invite(&self, leader_id: i32, user_id: i32) -> Result<(), AppError> {
+        let mut inner = self.inner.write().unwrap();
+        let party = led_by(&mut inner, leader_id)?;
+        if party.members.contains(&user_id) {
+            return Err(AppError::Conflict("Already in the party".into()));
+        }
+        if party.members.len() >= MAX_PARTY_SIZE {
+            return Err(AppError::Conflict(format!(
+                "Parties are limited to {MAX_PARTY_SIZE} players"
+            )));
+        }
+        if !party.invited.contains(&user_id) {
+            party.invited.push(user_id);
+        }
+        Ok(())
+    }
+
+    fn join(&self, user_id: i32, party_id: Uuid) -> Result<(), AppError> {
+        let mut inner = self.inner.write().unwrap();
+        if inner.member_of.contains_key(&user_id) {
+            return Err(AppError::Conflict("Leave your party first".into()));
+        }
+        let Some(party) = inner
+            .parties
+            .get_mut(&party_id)
+            .filter(|party| party.invited.contains(&user_id))
+        else {
+            return Err(AppError::NotFound("No invite to that party".into()));
+        };
+        if party.members.len() >= MAX_PARTY_SIZE {
+            return Err(AppError::Conflict("The party is full".into()));
+        }
+
+        party.invited.retain(|&id| id != user_id);
+        party.members.push(user_id);
+        inner.member_of.insert(user_id, party_id);
+        Ok(())
+    }
+
+    /// Takes `user_id` out of their party. The longest standing member leads once the
+    /// leader leaves, and the last one to leave ends the party.
+    pub fn leave(&self, user_id: i32) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        let Some(party_id) = inner.member_of.remove(&user_id) else {
+            return false;
+        };
+        let party = inner
+            .parties
+            .get_mut(&party_id)
+            .expect("members belong to a party");
+        party.members.retain(|&id| id != user_id);
+        match party.members.first() {
+            Some(&next) => {
+                if party.leader_id == user_id {
+                    party.leader_id = next;
+                }
+            }
+            None => {
+                inner.parties.remove(&party_id);
+            }
+        }
+        true
+    }
+
+    /// Drops every trace of an account that is going away.
+    pub fn forget(&self, user_id: i32) {
+        self.leave(user_id);
+        for party in self.inner.write().unwrap().parties.values_mut() {
+            party.invited.retain(|&id| id != user_id);
+        }
+    }
+
+    /// Remembers where the leader's matchmake put the party, for the members to follow.
+    pub fn set_match(
+        &self,
+        leader_id: i32,
+        server_id: Uuid,
+        name: &str,
+        mode: &str,
+        members: &[i32],
+        expires_in: Duration,
+    ) {
+        let mut inner = self.inner.write().unwrap();
+        if let Ok(party) = led_by(&mut inner, leader_id) {
+            party.current_match = Some(HeldMatch {
+                server_id,
+                name: name.to_string(),
+                mode: mode.to_string(),
+                members: members.to_vec(),
+                expires_at: Instant::now() + expires_in,
+            });
+        }
+    }
+
+    /// The server where the leader's last matchmake holds a slot for `user_id`.
+    pub fn held_server(&self, user_id: i32) -> Option<Uuid> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .member_of
+            .get(&user_id)
+            .and_then(|id| inner.parties[id].current_match.as_ref())
+            .filter(|held| held.expires_at > Instant::now() && held.members.contains(&user_id))
+            .map(|held| held.server_id)
+    }
+}
+
+fn led_by(inner: &mut Parties, leader_id: i32) -> Result<&mut StoredParty, AppError> {
+    let party = inner
+        .member_of
+        .get(&leader_id)
+        .and_then(|id| inner.parties.get_mut(id))
+        .ok_or(AppError::NotFound("You're not in a party".into()))?;
+    if party.leader_id != leader_id {
+        return Err(AppError::Conflict(
+            "Only the party leader can do that".into(),
+        ));
+    }
+    Ok(party)
+}
+
+pub async fn get_party(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<Json<PartyResponse>, AppError> {
+    let party = state.parties.party_of(user.id);
+    let invites = state.parties.invites(user.id);
+
+    let mut ids: Vec<i32> = invites.iter().map(|(_, leader_id)| *leader_id).collect();
+    if let Some(party) = &party {
+        ids.extend(&party.members);
+    }
+    let names: HashMap<i32, String> = state
+        .storage
+        .display_names(&ids)
+        .await?
+        .into_iter()
+        .collect();
+    let name_of = |id: i32| names.get(&id).cloned().unwrap_or_default();
+
+    Ok(Json(PartyResponse {
+        party: party.map(|party| Party {
+            id: party.id.to_string(),
+            leader_id: party.leader_id as u32,
+            members: party
+                .members
+                .iter()
+                .map(|&id| PartyMember {
+                    user_id: id as u32,
+                    display_name: name_of(id),
+                })
+                .collect(),
+            invited: party.invited.iter().map(|&id| id as u32).collect(),
+            current_match: party.current_match,
+        }),
+        invites: invites
+            .into_iter()
+            .map(|(party_id, leader_id)| PartyInvite {
+                party_id: party_id.to_string(),
+                leader_id: leader_id as u32,
+                leader_name: name_of(leader_id),
+            })
+            .collect(),
+    }))
+}
+
+#[instrument(skip_all, fields(user_id = user.id))]
+pub async fn create_party(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<StatusCode, AppError> {
+    let party_id = state.parties.create(user.id)?;
+    info!(%party_id, "Party created");
+    Ok(StatusCode::CREATED)
+}
+
+#[instrument(skip_all, fields(user_id = user.id, invited = req.user_id))]
+pub async fn invite(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(req): Json<PartyInviteRequest>,
+) -> Result<StatusCode, AppError> {
+    let is_friend = state
+        .storage
+        .friends(user.id)
+        .await?
+        .iter()
+        .any(|friend| friend.user_id == req.user_id && friend.state == FriendState::Friends);
+    if !is_friend {
+        return Err(AppError::BadRequest("Only friends can be invited".into()));
+    }
+
+    state.parties.invite(user.id, req.user_id as i32)?;
+    info!("Invited to the party");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(skip_all, fields(user_id = user.id, party_id = %req.party_id))]
+pub async fn join(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(req): Json<PartyJoinRequest>,
+) -> Result<StatusCode, AppError> {
+    let party_id = Uuid::parse_str(&req.party_id)
+        .map_err(|_| AppError::NotFound("No invite to that party".into()))?;
+    state.parties.join(user.id, party_id)?;
+    info!("Joined a party");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(skip_all, fields(user_id = user.id))]
+pub async fn leave(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<StatusCode, AppError> {
+    if !state.parties.leave(user.id) {
+        return Err(AppError::NotFound("You're not in a party".into()));
+    }
+    info!("Left the party");
+    Ok(StatusCode::NO_CONTENT)
+}
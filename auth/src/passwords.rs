@@ -0,0 +1,118 @@
+//! bcrypt on a bounded set of blocking threads, so hashing can't starve the runtime.
+
+use bcrypt::{DEFAULT_COST, hash, verify};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::models::AppError;
+
+/// How long a request waits for a free hashing thread before it's turned away.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Hash of a password no account has, made with `DEFAULT_COST` so checking it takes as long as a real one.
+const DUMMY_HASH: &str = "$2b$12$xW9PfcALHi.K1iuRaFf1dee.zrNoO.3hz8knyZC07YMZTnhOxfHZO";
+
+pub struct PasswordHasher {
+    workers: Arc<Semaphore>,
+    cost: u32,
+}
+
+impl PasswordHasher {
+    /// At most `workers` hashes run at once, the rest wait their turn.
+    pub fn new(workers: usize) -> Self {
+        Self {
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            cost: DEFAULT_COST,
+        }
+    }
+
+    /// Cheap hashes, so the API tests don't spend seconds on each account.
+    #[cfg(all(test, feature = "memory"))]
+    pub fn with_cost(workers: usize, cost: u32) -> Self {
+        Self {
+            cost,
+            ..Self::new(workers)
+        }
+    }
+
+    pub async fn hash(&self, password: String) -> Result<String, AppError> {
+        let cost = self.cost;
+        self.run(move || hash(password, cost))
+            .await?
+            .map_err(|_| AppError::Internal("Failed to hash password".into()))
+    }
+
+    pub async fn verify(&self, password: String, password_hash: String) -> Result<bool, AppError> {
+        self.run(move || verify(password, &password_hash))
+            .await?
+            .map_err(|_| AppError::Internal("Failed to verify password".into()))
+    }
+
+    /// Takes as long as `verify` for a username that doesn't exist, so the response
+    /// doesn't tell an unknown username from a wrong password.
+    pub async fn verify_nobody(&self, password: String) -> Result<(), AppError> {
+        self.verify(password, DUMMY_HASH.into()).await.map(|_| ())
+    }
+
+    /// The job keeps its thread slot until it finishes, even if the request that
+    /// started it is dropped, so aborted logins can't pile up hashes.
+    async fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, AppError> {
+        let permit = tokio::time::timeout(QUEUE_TIMEOUT, self.workers.clone().acquire_owned())
+            .await
+            .map_err(|_| AppError::Unavailable("Too many logins in progress, try again".into()))?
+            .expect("hashing semaphore is never closed");
+
+        tokio::task::spawn_blocking(move || {
+            let result = job();
+            drop(permit);
+            result
+        })
+        .await
+        .map_err(|_| AppError::Internal("Password hashing failed".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn cancelled_callers_keep_their_slot_until_the_hash_finishes() {
+        let hasher = Arc::new(PasswordHasher::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let call = || {
+            let hasher = hasher.clone();
+            let running = running.clone();
+            let peak = peak.clone();
+            tokio::spawn(async move {
+                hasher
+                    .run(move || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(100));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+            })
+        };
+
+        // clients that give up while their hash runs
+        let cancelled = [call(), call()];
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        for caller in &cancelled {
+            caller.abort();
+        }
+
+        let waiting: Vec<_> = (0..4).map(|_| call()).collect();
+        for caller in waiting {
+            assert!(caller.await.unwrap().is_ok());
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,66 @@
+use axum::extract::{Json, Path, State};
+use common::profile::{Profile, UpdateProfileRequest};
+use common::utils::current_time_ms;
+use std::sync::Arc;
+use tracing::{info, instrument};
+
+use crate::{AppState, accounts, models::AppError, tokens::AuthUser};
+
+const DISPLAY_NAME_LEN: std::ops::RangeInclusive<usize> = 3..=24;
+
+pub async fn get_profile(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i32>,
+) -> Result<Json<Profile>, AppError> {
+    find_profile(&state, user_id).await.map(Json)
+}
+
+pub async fn me(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<Json<Profile>, AppError> {
+    find_profile(&state, user.id).await.map(Json)
+}
+
+#[instrument(skip_all, fields(user_id = user.id))]
+pub async fn update_me(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(req): Json<UpdateProfileRequest>,
+) -> Result<Json<Profile>, AppError> {
+    let update = UpdateProfileRequest {
+        display_name: req.display_name.as_deref().map(display_name).transpose()?,
+        ship_color: req.ship_color.as_deref().map(ship_color).transpose()?,
+    };
+
+    let now = (current_time_ms() / 1000) as i64;
+    if !state.storage.update_profile(user.id, &update, now).await? {
+        return Err(AppError::NotFound("Unknown player".into()));
+    }
+    info!("Profile updated");
+    find_profile(&state, user.id).await.map(Json)
+}
+
+async fn find_profile(state: &AppState, user_id: i32) -> Result<Profile, AppError> {
+    state
+        .storage
+        .profile(user_id)
+        .await?
+        .ok_or(AppError::NotFound(format!("Unknown player {user_id}")))
+}
+
+fn display_name(name: &str) -> Result<String, AppError> {
+    accounts::player_name(name.trim(), DISPLAY_NAME_LEN, "Display names")
+}
+
+/// Colors are `#rrggbb`, stored in lowercase.
+fn ship_color(color: &str) -> Result<String, AppError> {
+    match color.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(color.to_ascii_lowercase())
+        }
+        _ => Err(AppError::BadRequest(
+            "Ship color must look like #rrggbb".into(),
+        )),
+    }
+}
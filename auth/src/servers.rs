@@ -0,0 +1,430 @@
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+};
+use common::registry::{
+    HeartbeatRequest, MatchResultRequest, RegisterServerRequest, RegisterServerResponse,
+    ServerListEntry, ServerListResponse,
+};
+use common::social::Presence;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    dto::{ServerListQuery, ServerSort, SortOrder},
+    models::AppError,
+};
+
+/// Game servers that registered and are still sending heartbeats.
+pub struct ServerRegistry {
+    servers: RwLock<HashMap<Uuid, RegisteredServer>>,
+    ttl: Duration,
+    reservation_ttl: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegisteredServer {
+    pub id: Uuid,
+    pub info: RegisterServerRequest,
+    pub accepting_players: bool,
+    pub last_heartbeat: Instant,
+    /// Slots held by matchmaking, keyed by the id of the ticket that fills them.
+    pub reservations: HashMap<String, Reservation>,
+    /// Accounts the server reported connected in its last heartbeat.
+    pub online_players: Vec<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reservation {
+    pub player_id: i32,
+    pub expires_at: Instant,
+}
+
+/// A slot matchmaking reserved for one player.
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub server: RegisteredServer,
+    pub ticket_id: String,
+    /// Time left on the reservation.
+    pub expires_in: Duration,
+}
+
+impl ServerRegistry {
+    pub fn new(ttl: Duration, reservation_ttl: Duration) -> Self {
+        Self {
+            servers: RwLock::new(HashMap::new()),
+            ttl,
+            reservation_ttl,
+        }
+    }
+
+    /// A third of the TTL, so one lost heartbeat doesn't drop a server.
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.ttl / 3
+    }
+
+    fn register(&self, info: RegisterServerRequest) -> Uuid {
+        let id = Uuid::new_v4();
+        let server = RegisteredServer {
+            id,
+            info,
+            accepting_players: true,
+            last_heartbeat: Instant::now(),
+            reservations: HashMap::new(),
+            online_players: Vec::new(),
+        };
+        self.servers.write().unwrap().insert(id, server);
+        id
+    }
+
+    /// Returns false for servers that expired or never registered.
+    fn heartbeat(&self, id: Uuid, heartbeat: &HeartbeatRequest) -> bool {
+        let mut servers = self.servers.write().unwrap();
+        let Some(server) = servers.get_mut(&id) else {
+            return false;
+        };
+        server.info.players = heartbeat.players;
+        server.accepting_players = heartbeat.accepting_players;
+        server.last_heartbeat = Instant::now();
+        server.online_players = heartbeat.online_players.clone();
+        for ticket_id in &heartbeat.redeemed_tickets {
+            server.reservations.remove(ticket_id);
+        }
+        true
+    }
+
+    /// Reserves a slot for `player_id` and one for each party member on the best live server
+    /// for `mode`: in `region` if one there has room, filling the fullest rooms first.
+    /// A player whose slot was already reserved, by a party leader or an earlier call, gets that one.
+    pub fn reserve(
+        &self,
+        player_id: i32,
+        party: &[i32],
+        mode: &str,
+        region: Option<&str>,
+    ) -> Option<Assignment> {
+        let mut servers = self.servers.write().unwrap();
+        let now = Instant::now();
+        for server in servers.values_mut() {
+            server.reservations.retain(|_, held| held.expires_at > now);
+        }
+
+        let held = servers.values().find_map(|server| {
+            let (ticket_id, held) = server
+                .reservations
+                .iter()
+                .find(|(_, held)| held.player_id == player_id)?;
+            Some((server.id, ticket_id.clone(), held.expires_at))
+        });
+        if let Some((server_id, ticket_id, expires_at)) = held {
+            let server = &servers[&server_id];
+            if party.is_empty() && self.joinable(server, mode) {
+                return Some(Assignment {
+                    server: server.clone(),
+                    ticket_id,
+                    expires_in: expires_at - now,
+                });
+            }
+        }
+
+        // whatever the group held before is given up for the new match
+        let members: Vec<i32> = std::iter::once(player_id)
+            .chain(party.iter().copied())
+            .collect();
+        for server in servers.values_mut() {
+            server
+                .reservations
+                .retain(|_, held| !members.contains(&held.player_id));
+        }
+
+        let server = servers
+            .values_mut()
+            .filter(|server| self.joinable(server, mode))
+            .filter(|server| open_slots(server) >= members.len() as u32)
+            .min_by_key(|server| {
+                let elsewhere =
+                    region.is_some_and(|region| !server.info.region.eq_ignore_ascii_case(region));
+                (elsewhere, open_slots(server), server.id)
+            })?;
+
+        let expires_at = now + self.reservation_ttl;
+        let mut ticket_id = String::new();
+        for &member in &members {
+            let id = Uuid::new_v4().to_string();
+            if member == player_id {
+                ticket_id = id.clone();
+            }
+            server.reservations.insert(
+                id,
+                Reservation {
+                    player_id: member,
+                    expires_at,
+                },
+            );
+        }
+
+        Some(Assignment {
+            server: server.clone(),
+            ticket_id,
+            expires_in: self.reservation_ttl,
+        })
+    }
+
+    /// The slot held for `player_id` on `server_id`, whether or not the server would take
+    /// anyone new right now. Party members follow their leader with this.
+    pub fn held(&self, player_id: i32, server_id: Uuid) -> Option<Assignment> {
+        let servers = self.servers.read().unwrap();
+        let server = servers.get(&server_id)?;
+        let now = Instant::now();
+        let (ticket_id, held) = server
+            .reservations
+            .iter()
+            .find(|(_, held)| held.player_id == player_id && held.expires_at > now)?;
+        Some(Assignment {
+            server: server.clone(),
+            ticket_id: ticket_id.clone(),
+            expires_in: held.expires_at - now,
+        })
+    }
+
+    /// Where each of `user_ids` is playing, leaving out everyone not on a live server.
+    pub fn presence(&self, user_ids: &[i32]) -> HashMap<i32, Presence> {
+        let servers = self.servers.read().unwrap();
+        let mut presence = HashMap::new();
+        for server in servers
+            .values()
+            .filter(|server| server.last_heartbeat.elapsed() <= self.ttl)
+        {
+            for &player_id in &server.online_players {
+                let user_id = player_id as i32;
+                if user_ids.contains(&user_id) {
+                    presence.insert(
+                        user_id,
+                        Presence {
+                            server_id: server.id.to_string(),
+                            server_name: server.info.name.clone(),
+                            room_id: server.info.room_id,
+                            mode: server.info.mode.clone(),
+                        },
+                    );
+                }
+            }
+        }
+        presence
+    }
+
+    fn joinable(&self, server: &RegisteredServer, mode: &str) -> bool {
+        server.accepting_players
+            && server.last_heartbeat.elapsed() <= self.ttl
+            && server.info.mode.eq_ignore_ascii_case(mode)
+    }
+
+    /// Servers matching the query, skipping any that expired but weren't swept yet.
+    fn list(&self, query: &ServerListQuery) -> Vec<ServerListEntry> {
+        let servers = self.servers.read().unwrap();
+        let mut listed: Vec<&RegisteredServer> = servers
+            .values()
+            .filter(|server| server.last_heartbeat.elapsed() <= self.ttl)
+            .filter(|server| query.include_closed || server.accepting_players)
+            .filter(|server| !query.not_full || server.info.players < server.info.capacity)
+            .filter(|server| {
+                query
+                    .region
+                    .as_ref()
+                    .is_none_or(|region| server.info.region.eq_ignore_ascii_case(region))
+            })
+            .filter(|server| {
+                query
+                    .mode
+                    .as_ref()
+                    .is_none_or(|mode| server.info.mode.eq_ignore_ascii_case(mode))
+            })
+            .collect();
+
+        listed.sort_by(|a, b| {
+            let ordering = match query.sort {
+                ServerSort::Players => a.info.players.cmp(&b.info.players),
+                ServerSort::Free => free_slots(a).cmp(&free_slots(b)),
+                ServerSort::Name => a.info.name.cmp(&b.info.name),
+                ServerSort::Region => a.info.region.cmp(&b.info.region),
+            };
+            // ties keep a stable order between requests
+            let ordering = ordering.then_with(|| a.id.cmp(&b.id));
+            match query.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+
+        listed
+            .into_iter()
+            .map(|server| ServerListEntry {
+                id: server.id.to_string(),
+                name: server.info.name.clone(),
+                room_id: server.info.room_id,
+                address: server.info.udp_addr.clone(),
+                region: server.info.region.clone(),
+                mode: server.info.mode.clone(),
+                players: server.info.players,
+                capacity: server.info.capacity,
+                accepting_players: server.accepting_players,
+            })
+            .collect()
+    }
+
+    fn remove(&self, id: Uuid) -> Option<RegisteredServer> {
+        self.servers.write().unwrap().remove(&id)
+    }
+
+    /// Drops servers whose last heartbeat is older than the TTL.
+    pub fn expire(&self) -> Vec<RegisteredServer> {
+        let mut servers = self.servers.write().unwrap();
+        let expired: Vec<Uuid> = servers
+            .values()
+            .filter(|server| server.last_heartbeat.elapsed() > self.ttl)
+            .map(|server| server.id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| servers.remove(&id))
+            .collect()
+    }
+}
+
+fn free_slots(server: &RegisteredServer) -> u32 {
+    server.info.capacity.saturating_sub(server.info.players)
+}
+
+/// Free slots that matchmaking hasn't promised to anyone yet.
+fn open_slots(server: &RegisteredServer) -> u32 {
+    free_slots(server).saturating_sub(server.reservations.len() as u32)
+}
+
+/// Periodically removes game servers that stopped sending heartbeats.
+pub async fn run_expiry(registry: Arc<ServerRegistry>) {
+    let mut interval = tokio::time::interval(registry.heartbeat_interval());
+    loop {
+        interval.tick().await;
+        for server in registry.expire() {
+            warn!(server_id = %server.id, name = %server.info.name, "Game server expired");
+        }
+    }
+}
+
+/// Game servers authenticate with the shared `SERVER_REGISTRATION_TOKEN`.
+fn authorize_server(state: &AppState, headers: &HeaderMap) -> Result<(), AppError> {
+    let Some(expected) = &state.registration_token else {
+        return Err(AppError::Unauthorized(
+            "Server registration is disabled".into(),
+        ));
+    };
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    // constant time, so response timing can't be used to guess the token byte by byte
+    let valid = token.is_some_and(|token| bool::from(token.as_bytes().ct_eq(expected.as_bytes())));
+    if !valid {
+        warn!("Rejected game server with an invalid token");
+        return Err(AppError::Unauthorized("Invalid server token".into()));
+    }
+    Ok(())
+}
+
+pub async fn list_servers(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ServerListQuery>,
+) -> Json<ServerListResponse> {
+    Json(ServerListResponse {
+        servers: state.servers.list(&query),
+    })
+}
+
+#[instrument(skip_all, fields(name = %req.name, region = %req.region))]
+pub async fn register_server(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<RegisterServerRequest>,
+) -> Result<Json<RegisterServerResponse>, AppError> {
+    authorize_server(&state, &headers)?;
+
+    let udp_addr = req.udp_addr.clone();
+    let server_id = state.servers.register(req);
+    info!(%server_id, %udp_addr, "Game server registered");
+
+    Ok(Json(RegisterServerResponse {
+        server_id: server_id.to_string(),
+        heartbeat_interval_secs: state.servers.heartbeat_interval().as_secs().max(1),
+    }))
+}
+
+pub async fn heartbeat(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(server_id): Path<Uuid>,
+    Json(req): Json<HeartbeatRequest>,
+) -> Result<StatusCode, AppError> {
+    authorize_server(&state, &headers)?;
+
+    if !state.servers.heartbeat(server_id, &req) {
+        // the game server registers again when it sees this
+        return Err(AppError::NotFound(format!("Unknown server {server_id}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stores the results of a finished match. The server may already have deregistered,
+/// shutting down ends its last match.
+#[instrument(skip_all, fields(%server_id, players = req.players.len()))]
+pub async fn record_match(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(server_id): Path<Uuid>,
+    Json(req): Json<MatchResultRequest>,
+) -> Result<StatusCode, AppError> {
+    authorize_server(&state, &headers)?;
+
+    if req.ended_at < req.started_at {
+        return Err(AppError::BadRequest("Match ended before it started".into()));
+    }
+    let mut seen = HashSet::new();
+    if !req
+        .players
+        .iter()
+        .all(|player| i32::try_from(player.user_id).is_ok() && seen.insert(player.user_id))
+    {
+        return Err(AppError::BadRequest(
+            "Players must be distinct accounts".into(),
+        ));
+    }
+
+    let match_id = state
+        .storage
+        .record_match(&server_id.to_string(), &req)
+        .await?;
+    info!(match_id, "Match recorded");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn deregister_server(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(server_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    authorize_server(&state, &headers)?;
+
+    match state.servers.remove(server_id) {
+        Some(server) => {
+            info!(%server_id, name = %server.info.name, "Game server deregistered");
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err(AppError::NotFound(format!("Unknown server {server_id}"))),
+    }
+}
@@ -0,0 +1,536 @@
+use axum::async_trait;
+use common::leaderboard::{LeaderboardEntry, LeaderboardStat};
+use common::profile::{DEFAULT_SHIP_COLOR, PlayerStats, Profile, UpdateProfileRequest};
+use common::registry::{MatchPlayerResult, MatchResultRequest};
+use common::social::{Friend, FriendState};
+use common::utils::current_time_ms;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use super::{
+    DELETED_DISPLAY_NAME, FriendRequestOutcome, LeaderboardCursor, LeaderboardScope, NewSession,
+    Rotation, Storage, StorageError,
+};
+use crate::models::User;
+
+/// Keeps everything in process, for running the service without PostgreSQL.
+/// Bans are only managed in the database, so nobody is banned here.
+#[derive(Default)]
+pub struct MemoryStorage {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    users: BTreeMap<i32, User>,
+    /// Deleted accounts, whose ids still show up in match results.
+    deleted_users: BTreeSet<i32>,
+    next_user_id: i32,
+    sessions: HashMap<String, Session>,
+    /// Keyed by token hash.
+    refresh_tokens: HashMap<String, RefreshToken>,
+    /// One per user, made with the account.
+    profiles: HashMap<i32, StoredProfile>,
+    next_match_id: i64,
+    match_players: Vec<RecordedResult>,
+    /// One per pair of accounts, like the `friends` table.
+    friends: Vec<FriendLink>,
+}
+
+struct FriendLink {
+    requester_id: i32,
+    addressee_id: i32,
+    created_at: i64,
+    accepted_at: Option<i64>,
+}
+
+impl FriendLink {
+    fn between(&self, a: i32, b: i32) -> bool {
+        (self.requester_id, self.addressee_id) == (a, b)
+            || (self.requester_id, self.addressee_id) == (b, a)
+    }
+}
+
+struct RecordedResult {
+    ended_at: i64,
+    result: MatchPlayerResult,
+}
+
+struct StoredProfile {
+    created_at: i64,
+    display_name: Option<String>,
+    ship_color: Option<String>,
+}
+
+struct Session {
+    user_id: i32,
+    revoked: bool,
+}
+
+struct RefreshToken {
+    session_id: String,
+    user_id: i32,
+    expires_at: i64,
+    revoked: bool,
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn migrate(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    async fn user_by_name(&self, username: &str) -> Result<Option<User>, StorageError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .users
+            .values()
+            .find(|user| user.username.eq_ignore_ascii_case(username))
+            .cloned())
+    }
+
+    async fn user_by_id(&self, id: i32) -> Result<Option<User>, StorageError> {
+        Ok(self.inner.lock().unwrap().users.get(&id).cloned())
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<User, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.username_taken(username, None) {
+            return Err(StorageError::Conflict("Username".into()));
+        }
+
+        // ids start at 1 like a SERIAL column
+        inner.next_user_id += 1;
+        let user = User {
+            id: inner.next_user_id,
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+        };
+        inner.users.insert(user.id, user.clone());
+        inner.profiles.insert(
+            user.id,
+            StoredProfile {
+                created_at: (current_time_ms() / 1000) as i64,
+                display_name: None,
+                ship_color: None,
+            },
+        );
+        Ok(user)
+    }
+
+    async fn change_password(
+        &self,
+        user_id: i32,
+        password_hash: &str,
+        _now: i64,
+    ) -> Result<bool, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(user) = inner.users.get_mut(&user_id) else {
+            return Ok(false);
+        };
+        user.password_hash = password_hash.to_string();
+        for session in inner.sessions.values_mut() {
+            if session.user_id == user_id {
+                session.revoked = true;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn rename_user(&self, user_id: i32, username: &str) -> Result<bool, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.username_taken(username, Some(user_id)) {
+            return Err(StorageError::Conflict("Username".into()));
+        }
+        let Some(user) = inner.users.get_mut(&user_id) else {
+            return Ok(false);
+        };
+        user.username = username.to_string();
+        Ok(true)
+    }
+
+    async fn delete_user(&self, user_id: i32, _now: i64) -> Result<bool, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.users.remove(&user_id).is_none() {
+            return Ok(false);
+        }
+        inner.deleted_users.insert(user_id);
+        inner
+            .sessions
+            .retain(|_, session| session.user_id != user_id);
+        inner
+            .refresh_tokens
+            .retain(|_, token| token.user_id != user_id);
+        inner.profiles.remove(&user_id);
+        inner
+            .friends
+            .retain(|link| link.requester_id != user_id && link.addressee_id != user_id);
+        Ok(true)
+    }
+
+    async fn display_names(&self, ids: &[i32]) -> Result<Vec<(i32, String)>, StorageError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| Some((*id, inner.display_name(*id)?)))
+            .collect())
+    }
+
+    async fn friends(&self, user_id: i32) -> Result<Vec<Friend>, StorageError> {
+        let inner = self.inner.lock().unwrap();
+        let mut friends: Vec<Friend> = inner
+            .friends
+            .iter()
+            .filter_map(|link| {
+                let (other_id, state) = if link.requester_id == user_id {
+                    (link.addressee_id, FriendState::Outgoing)
+                } else if link.addressee_id == user_id {
+                    (link.requester_id, FriendState::Incoming)
+                } else {
+                    return None;
+                };
+                Some(Friend {
+                    user_id: other_id as u32,
+                    display_name: inner.display_name(other_id)?,
+                    state: if link.accepted_at.is_some() {
+                        FriendState::Friends
+                    } else {
+                        state
+                    },
+                    since: link.accepted_at.unwrap_or(link.created_at) as u64,
+                    presence: None,
+                })
+            })
+            .collect();
+        friends.sort_by(|a, b| {
+            a.display_name
+                .cmp(&b.display_name)
+                .then(a.user_id.cmp(&b.user_id))
+        });
+        Ok(friends)
+    }
+
+    async fn request_friend(
+        &self,
+        from: i32,
+        to: i32,
+        now: i64,
+    ) -> Result<FriendRequestOutcome, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(link) = inner.friends.iter_mut().find(|link| link.between(from, to)) else {
+            inner.friends.push(FriendLink {
+                requester_id: from,
+                addressee_id: to,
+                created_at: now,
+                accepted_at: None,
+            });
+            return Ok(FriendRequestOutcome::Sent);
+        };
+
+        Ok(if link.accepted_at.is_some() {
+            FriendRequestOutcome::AlreadyFriends
+        } else if link.requester_id == from {
+            FriendRequestOutcome::AlreadySent
+        } else {
+            link.accepted_at = Some(now);
+            FriendRequestOutcome::Accepted
+        })
+    }
+
+    async fn accept_friend(
+        &self,
+        user_id: i32,
+        requester_id: i32,
+        now: i64,
+    ) -> Result<bool, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.friends.iter_mut().find(|link| {
+            link.requester_id == requester_id
+                && link.addressee_id == user_id
+                && link.accepted_at.is_none()
+        }) {
+            Some(link) => {
+                link.accepted_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn remove_friend(&self, user_id: i32, other_id: i32) -> Result<bool, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.friends.len();
+        inner
+            .friends
+            .retain(|link| !link.between(user_id, other_id));
+        Ok(inner.friends.len() < before)
+    }
+
+    async fn active_ban(&self, _user_id: i32, _now: i64) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+
+    async fn profile(&self, user_id: i32) -> Result<Option<Profile>, StorageError> {
+        let inner = self.inner.lock().unwrap();
+        let (Some(user), Some(profile)) = (inner.users.get(&user_id), inner.profiles.get(&user_id))
+        else {
+            return Ok(None);
+        };
+
+        let mut stats = PlayerStats::default();
+        for RecordedResult { result, .. } in inner
+            .match_players
+            .iter()
+            .filter(|recorded| recorded.result.user_id as i32 == user_id)
+        {
+            stats.kills += result.kills as u64;
+            stats.deaths += result.deaths as u64;
+            stats.asteroids_destroyed += result.asteroids_destroyed as u64;
+            stats.matches_played += 1;
+            stats.matches_won += result.won as u64;
+            stats.seconds_played += result.seconds_played;
+        }
+
+        Ok(Some(Profile {
+            id: user.id as u32,
+            username: user.username.clone(),
+            display_name: profile
+                .display_name
+                .clone()
+                .unwrap_or_else(|| user.username.clone()),
+            ship_color: profile
+                .ship_color
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SHIP_COLOR.to_string()),
+            created_at: profile.created_at as u64,
+            stats,
+        }))
+    }
+
+    async fn update_profile(
+        &self,
+        user_id: i32,
+        update: &UpdateProfileRequest,
+        _now: i64,
+    ) -> Result<bool, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(profile) = inner.profiles.get_mut(&user_id) else {
+            return Ok(false);
+        };
+        if let Some(display_name) = &update.display_name {
+            profile.display_name = Some(display_name.clone());
+        }
+        if let Some(ship_color) = &update.ship_color {
+            profile.ship_color = Some(ship_color.clone());
+        }
+        Ok(true)
+    }
+
+    async fn record_match(
+        &self,
+        _server_id: &str,
+        result: &MatchResultRequest,
+    ) -> Result<i64, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_match_id += 1;
+        let known: Vec<RecordedResult> = result
+            .players
+            .iter()
+            .filter(|player| inner.users.contains_key(&(player.user_id as i32)))
+            .map(|player| RecordedResult {
+                ended_at: result.ended_at as i64,
+                result: player.clone(),
+            })
+            .collect();
+        inner.match_players.extend(known);
+        Ok(inner.next_match_id)
+    }
+
+    async fn leaderboard(
+        &self,
+        scope: &LeaderboardScope,
+        after: Option<LeaderboardCursor>,
+        limit: u32,
+    ) -> Result<Vec<LeaderboardEntry>, StorageError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .ranked(scope)
+            .into_iter()
+            .filter(|entry| {
+                after.is_none_or(|cursor| {
+                    entry.value < cursor.value
+                        || (entry.value == cursor.value && (entry.user_id as i32) > cursor.user_id)
+                })
+            })
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn leaderboard_rank(
+        &self,
+        scope: &LeaderboardScope,
+        user_id: i32,
+    ) -> Result<Option<LeaderboardEntry>, StorageError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .ranked(scope)
+            .into_iter()
+            .find(|entry| entry.user_id as i32 == user_id))
+    }
+
+    async fn create_session(&self, session: NewSession, now: i64) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner {
+            sessions,
+            refresh_tokens,
+            ..
+        } = &mut *inner;
+
+        refresh_tokens
+            .retain(|_, token| token.user_id != session.user_id || token.expires_at > now);
+        sessions.retain(|id, held| {
+            held.user_id != session.user_id
+                || refresh_tokens.values().any(|token| &token.session_id == id)
+        });
+
+        sessions.insert(
+            session.id.clone(),
+            Session {
+                user_id: session.user_id,
+                revoked: false,
+            },
+        );
+        refresh_tokens.insert(
+            session.token_hash,
+            RefreshToken {
+                session_id: session.id,
+                user_id: session.user_id,
+                expires_at: session.token_expires_at,
+                revoked: false,
+            },
+        );
+        Ok(())
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        token_hash: &str,
+        replacement_hash: &str,
+        replacement_expires_at: i64,
+        now: i64,
+    ) -> Result<Rotation, StorageError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner {
+            sessions,
+            refresh_tokens,
+            ..
+        } = &mut *inner;
+
+        let Some(token) = refresh_tokens.get_mut(token_hash) else {
+            return Ok(Rotation::Unknown);
+        };
+        let Some(session) = sessions.get_mut(&token.session_id) else {
+            return Ok(Rotation::Unknown);
+        };
+        if session.revoked {
+            return Ok(Rotation::Unknown);
+        }
+        if token.revoked {
+            session.revoked = true;
+            return Ok(Rotation::Reused);
+        }
+        if token.expires_at <= now {
+            return Ok(Rotation::Expired);
+        }
+
+        token.revoked = true;
+        let replacement = RefreshToken {
+            session_id: token.session_id.clone(),
+            user_id: token.user_id,
+            expires_at: replacement_expires_at,
+            revoked: false,
+        };
+        let user_id = token.user_id;
+        refresh_tokens.insert(replacement_hash.to_string(), replacement);
+        Ok(Rotation::Rotated { user_id })
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    kills: u64,
+    deaths: u64,
+    score: u64,
+    matches: u64,
+}
+
+impl Inner {
+    /// What the PostgreSQL backend's `COALESCE(profiles.display_name, users.username, ..)` gives.
+    fn display_name(&self, user_id: i32) -> Option<String> {
+        if self.deleted_users.contains(&user_id) {
+            return Some(DELETED_DISPLAY_NAME.into());
+        }
+        let user = self.users.get(&user_id)?;
+        Some(
+            self.profiles
+                .get(&user_id)
+                .and_then(|profile| profile.display_name.clone())
+                .unwrap_or_else(|| user.username.clone()),
+        )
+    }
+
+    /// Usernames are unique regardless of case, `except` is the account being renamed.
+    fn username_taken(&self, username: &str, except: Option<i32>) -> bool {
+        self.users
+            .values()
+            .any(|user| Some(user.id) != except && user.username.eq_ignore_ascii_case(username))
+    }
+
+    /// The whole leaderboard in order, what the PostgreSQL backend computes in its query.
+    fn ranked(&self, scope: &LeaderboardScope) -> Vec<LeaderboardEntry> {
+        let mut totals: BTreeMap<i32, Totals> = BTreeMap::new();
+        for recorded in self
+            .match_players
+            .iter()
+            .filter(|recorded| recorded.ended_at >= scope.since)
+        {
+            let totals = totals.entry(recorded.result.user_id as i32).or_default();
+            totals.kills += recorded.result.kills as u64;
+            totals.deaths += recorded.result.deaths as u64;
+            totals.score += recorded.result.score as u64;
+            totals.matches += 1;
+        }
+
+        let mut entries: Vec<LeaderboardEntry> = totals
+            .into_iter()
+            .filter(|(_, totals)| totals.matches as i64 >= scope.min_matches)
+            .filter_map(|(user_id, totals)| {
+                let display_name = self.display_name(user_id)?;
+                let value = match scope.stat {
+                    LeaderboardStat::Kills => totals.kills as f64,
+                    LeaderboardStat::Kd => totals.kills as f64 / totals.deaths.max(1) as f64,
+                    LeaderboardStat::Score => totals.score as f64,
+                    LeaderboardStat::Rating => totals.score as f64 / totals.matches as f64,
+                };
+                Some(LeaderboardEntry {
+                    rank: 0,
+                    user_id: user_id as u32,
+                    display_name,
+                    value,
+                    matches: totals.matches,
+                })
+            })
+            .collect();
+
+        entries.sort_by(|a, b| b.value.total_cmp(&a.value).then(a.user_id.cmp(&b.user_id)));
+        // ties share the rank of the first of them
+        for i in 0..entries.len() {
+            entries[i].rank = if i > 0 && entries[i].value == entries[i - 1].value {
+                entries[i - 1].rank
+            } else {
+                i as u64 + 1
+            };
+        }
+        entries
+    }
+}
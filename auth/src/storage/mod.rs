@@ -0,0 +1,242 @@
+//! Everything the auth service keeps between requests, behind [`Storage`].
+//! PostgreSQL is the real backend, the in-memory one (cargo feature `memory`) runs
+//! the service without a database and forgets everything on exit.
+
+#[cfg(feature = "memory")]
+mod memory;
+mod postgres;
+
+use axum::async_trait;
+use common::leaderboard::{LeaderboardEntry, LeaderboardStat};
+use common::profile::{Profile, UpdateProfileRequest};
+use common::registry::MatchResultRequest;
+use common::social::Friend;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::models::{AppError, User};
+
+#[cfg(feature = "memory")]
+pub use memory::MemoryStorage;
+pub use postgres::PgStorage;
+
+/// `DATABASE_URL` value that selects the in-memory backend.
+pub const MEMORY_URL: &str = "memory";
+/// Shown in place of a deleted account's name, in leaderboards and other players' history.
+pub const DELETED_DISPLAY_NAME: &str = "Deleted player";
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// A unique value, like a username, is already taken.
+    Conflict(String),
+    Backend(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Conflict(what) => write!(f, "{what} already exists"),
+            StorageError::Backend(message) => write!(f, "storage failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<StorageError> for AppError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Conflict(what) => AppError::Conflict(format!("{what} already exists")),
+            StorageError::Backend(message) => AppError::Internal(message),
+        }
+    }
+}
+
+/// A login, holding the refresh token it starts with.
+#[derive(Debug, Clone)]
+pub struct NewSession {
+    pub id: String,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub token_expires_at: i64,
+}
+
+/// What became of a refresh token presented for rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// Spent, and the replacement now belongs to the same session.
+    Rotated {
+        user_id: i32,
+    },
+    Expired,
+    /// Already spent, so the session it belongs to was revoked.
+    Reused,
+    Unknown,
+}
+
+/// What asking someone to be friends did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FriendRequestOutcome {
+    Sent,
+    /// They had already asked, so now they're friends.
+    Accepted,
+    AlreadyFriends,
+    AlreadySent,
+}
+
+/// Which leaderboard to read.
+#[derive(Debug, Clone, Copy)]
+pub struct LeaderboardScope {
+    pub stat: LeaderboardStat,
+    /// Only matches that ended at or after this count.
+    pub since: i64,
+    /// Players with fewer matches in the window are left out.
+    pub min_matches: i64,
+}
+
+/// Where a leaderboard page ended. Entries are sorted by value, then by user id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeaderboardCursor {
+    pub value: f64,
+    pub user_id: i32,
+}
+
+impl LeaderboardCursor {
+    pub fn after(entry: &LeaderboardEntry) -> Self {
+        Self {
+            value: entry.value,
+            user_id: entry.user_id as i32,
+        }
+    }
+
+    /// Opaque to clients, they only hand it back.
+    pub fn encode(&self) -> String {
+        hex::encode(format!("{}:{}", self.value, self.user_id))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let decoded = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+        let (value, user_id) = decoded.split_once(':')?;
+        Some(Self {
+            value: value.parse().ok().filter(|value: &f64| value.is_finite())?,
+            user_id: user_id.parse().ok()?,
+        })
+    }
+}
+
+/// Accounts, sessions, bans, profiles and match results. Timestamps are unix seconds.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Brings the schema up to date, nothing to do for backends without one.
+    async fn migrate(&self) -> Result<(), StorageError>;
+
+    /// Usernames are matched regardless of case.
+    async fn user_by_name(&self, username: &str) -> Result<Option<User>, StorageError>;
+
+    async fn user_by_id(&self, id: i32) -> Result<Option<User>, StorageError>;
+
+    /// Fails with [`StorageError::Conflict`] if the username is taken, in any case.
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<User, StorageError>;
+
+    /// Replaces the password and revokes every session of the account.
+    /// Returns false for unknown accounts.
+    async fn change_password(
+        &self,
+        user_id: i32,
+        password_hash: &str,
+        now: i64,
+    ) -> Result<bool, StorageError>;
+
+    /// Fails with [`StorageError::Conflict`] if another account has the username.
+    async fn rename_user(&self, user_id: i32, username: &str) -> Result<bool, StorageError>;
+
+    /// Deletes the account with its sessions, profile and friends, freeing the username.
+    /// Its bans and match results stay, shown under [`DELETED_DISPLAY_NAME`].
+    async fn delete_user(&self, user_id: i32, now: i64) -> Result<bool, StorageError>;
+
+    /// Display names of the accounts among `ids` that exist or existed.
+    async fn display_names(&self, ids: &[i32]) -> Result<Vec<(i32, String)>, StorageError>;
+
+    /// Friends and open requests in either direction, without presence.
+    async fn friends(&self, user_id: i32) -> Result<Vec<Friend>, StorageError>;
+
+    async fn request_friend(
+        &self,
+        from: i32,
+        to: i32,
+        now: i64,
+    ) -> Result<FriendRequestOutcome, StorageError>;
+
+    /// Accepts the open request `requester_id` sent, returning false if there's none.
+    async fn accept_friend(
+        &self,
+        user_id: i32,
+        requester_id: i32,
+        now: i64,
+    ) -> Result<bool, StorageError>;
+
+    /// Ends a friendship, or declines or withdraws a request, whichever the two have.
+    async fn remove_friend(&self, user_id: i32, other_id: i32) -> Result<bool, StorageError>;
+
+    /// Reason of a ban in force at `now`, if there is one.
+    async fn active_ban(&self, user_id: i32, now: i64) -> Result<Option<String>, StorageError>;
+
+    /// Profile with lifetime stats summed over every recorded match.
+    async fn profile(&self, user_id: i32) -> Result<Option<Profile>, StorageError>;
+
+    /// Changes the fields that are set, returning false for unknown accounts.
+    async fn update_profile(
+        &self,
+        user_id: i32,
+        update: &UpdateProfileRequest,
+        now: i64,
+    ) -> Result<bool, StorageError>;
+
+    /// Stores a match a game server finished, skipping accounts that no longer exist.
+    /// Returns the id of the match.
+    async fn record_match(
+        &self,
+        server_id: &str,
+        result: &MatchResultRequest,
+    ) -> Result<i64, StorageError>;
+
+    /// Up to `limit` leaderboard entries, starting right after `after`.
+    async fn leaderboard(
+        &self,
+        scope: &LeaderboardScope,
+        after: Option<LeaderboardCursor>,
+        limit: u32,
+    ) -> Result<Vec<LeaderboardEntry>, StorageError>;
+
+    /// Where a player stands, `None` if they aren't on the leaderboard.
+    async fn leaderboard_rank(
+        &self,
+        scope: &LeaderboardScope,
+        user_id: i32,
+    ) -> Result<Option<LeaderboardEntry>, StorageError>;
+
+    /// Opens a session, dropping the user's sessions whose tokens all expired.
+    async fn create_session(&self, session: NewSession, now: i64) -> Result<(), StorageError>;
+
+    /// Spends the token with `token_hash` and puts `replacement_hash` in its place.
+    async fn rotate_refresh_token(
+        &self,
+        token_hash: &str,
+        replacement_hash: &str,
+        replacement_expires_at: i64,
+        now: i64,
+    ) -> Result<Rotation, StorageError>;
+}
+
+/// Picks the backend `database_url` names.
+pub async fn connect(database_url: &str) -> Result<Arc<dyn Storage>, StorageError> {
+    if database_url == MEMORY_URL {
+        #[cfg(feature = "memory")]
+        return Ok(Arc::new(MemoryStorage::default()));
+        #[cfg(not(feature = "memory"))]
+        return Err(StorageError::Backend(
+            "in-memory storage needs the `memory` feature".into(),
+        ));
+    }
+    Ok(Arc::new(PgStorage::connect(database_url).await?))
+}
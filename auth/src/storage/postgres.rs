@@ -0,0 +1,659 @@
+use axum::async_trait;
+use common::leaderboard::{LeaderboardEntry, LeaderboardStat};
+use common::profile::{DEFAULT_SHIP_COLOR, PlayerStats, Profile, UpdateProfileRequest};
+use common::registry::MatchResultRequest;
+use common::social::{Friend, FriendState};
+use sqlx::migrate::Migrator;
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use tracing::info;
+
+use super::{
+    DELETED_DISPLAY_NAME, FriendRequestOutcome, LeaderboardCursor, LeaderboardScope, NewSession,
+    Rotation, Storage, StorageError,
+};
+use crate::models::User;
+
+/// The SQL files in `auth/migrations`, compiled into the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub struct PgStorage {
+    pool: PgPool,
+}
+
+impl PgStorage {
+    pub async fn connect(database_url: &str) -> Result<Self, StorageError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(10)
+            .connect(database_url)
+            .await
+            .map_err(backend)?;
+        info!("Connected to PostgreSQL");
+        Ok(Self { pool })
+    }
+}
+
+fn backend(e: impl std::fmt::Display) -> StorageError {
+    StorageError::Backend(e.to_string())
+}
+
+fn username_conflict(e: sqlx::Error) -> StorageError {
+    match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => StorageError::Conflict("Username".into()),
+        _ => backend(e),
+    }
+}
+
+/// Stats are reported as `u32`, the columns are `INTEGER`.
+fn to_i32(value: u32) -> i32 {
+    i32::try_from(value).unwrap_or(i32::MAX)
+}
+
+/// Ranks everyone with results in the window. `$1` is when the window starts and
+/// `$2` the matches a player needs in it, callers add their filter and order.
+fn ranked_query(stat: LeaderboardStat) -> String {
+    let value = match stat {
+        LeaderboardStat::Kills => "kills::FLOAT8",
+        LeaderboardStat::Kd => "kills::FLOAT8 / GREATEST(deaths, 1)",
+        LeaderboardStat::Score => "score::FLOAT8",
+        LeaderboardStat::Rating => "score::FLOAT8 / matches",
+    };
+    format!(
+        "WITH totals AS ( \
+             SELECT match_players.user_id, SUM(match_players.kills) AS kills, \
+             SUM(match_players.deaths) AS deaths, SUM(match_players.score) AS score, \
+             COUNT(*) AS matches \
+             FROM matches JOIN match_players ON match_players.match_id = matches.id \
+             WHERE matches.ended_at >= $1 \
+             GROUP BY match_players.user_id \
+         ), ranked AS ( \
+             SELECT user_id, matches, {value} AS value, \
+             RANK() OVER (ORDER BY {value} DESC) AS rank \
+             FROM totals WHERE matches >= $2 \
+         ) \
+         SELECT ranked.rank, ranked.user_id, \
+         COALESCE(profiles.display_name, users.username, '{DELETED_DISPLAY_NAME}') AS display_name, \
+         ranked.value, ranked.matches \
+         FROM ranked \
+         JOIN users ON users.id = ranked.user_id \
+         LEFT JOIN profiles ON profiles.user_id = ranked.user_id"
+    )
+}
+
+#[derive(sqlx::FromRow)]
+struct LeaderboardRow {
+    rank: i64,
+    user_id: i32,
+    display_name: String,
+    value: f64,
+    matches: i64,
+}
+
+impl From<LeaderboardRow> for LeaderboardEntry {
+    fn from(row: LeaderboardRow) -> Self {
+        LeaderboardEntry {
+            rank: row.rank as u64,
+            user_id: row.user_id as u32,
+            display_name: row.display_name,
+            value: row.value,
+            matches: row.matches as u64,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct FriendRow {
+    user_id: i32,
+    display_name: String,
+    requested: bool,
+    created_at: i64,
+    accepted_at: Option<i64>,
+}
+
+impl From<FriendRow> for Friend {
+    fn from(row: FriendRow) -> Self {
+        let state = match (row.accepted_at, row.requested) {
+            (Some(_), _) => FriendState::Friends,
+            (None, true) => FriendState::Outgoing,
+            (None, false) => FriendState::Incoming,
+        };
+        Friend {
+            user_id: row.user_id as u32,
+            display_name: row.display_name,
+            state,
+            since: row.accepted_at.unwrap_or(row.created_at) as u64,
+            presence: None,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ProfileRow {
+    id: i32,
+    username: String,
+    display_name: String,
+    ship_color: String,
+    created_at: i64,
+    kills: i64,
+    deaths: i64,
+    asteroids_destroyed: i64,
+    matches_played: i64,
+    matches_won: i64,
+    seconds_played: i64,
+}
+
+impl From<ProfileRow> for Profile {
+    fn from(row: ProfileRow) -> Self {
+        Profile {
+            id: row.id as u32,
+            username: row.username,
+            display_name: row.display_name,
+            ship_color: row.ship_color,
+            created_at: row.created_at as u64,
+            stats: PlayerStats {
+                kills: row.kills as u64,
+                deaths: row.deaths as u64,
+                asteroids_destroyed: row.asteroids_destroyed as u64,
+                matches_played: row.matches_played as u64,
+                matches_won: row.matches_won as u64,
+                seconds_played: row.seconds_played as u64,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    /// Applies every migration the database hasn't seen yet.
+    async fn migrate(&self) -> Result<(), StorageError> {
+        let latest = MIGRATOR.iter().map(|migration| migration.version).max();
+        MIGRATOR.run(&self.pool).await.map_err(backend)?;
+        info!(version = latest, "Database schema is up to date");
+        Ok(())
+    }
+
+    async fn user_by_name(&self, username: &str) -> Result<Option<User>, StorageError> {
+        sqlx::query_as::<_, User>(
+            "SELECT id, username, password_hash FROM users \
+             WHERE lower(username) = lower($1) AND deleted_at IS NULL",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(backend)
+    }
+
+    async fn user_by_id(&self, id: i32) -> Result<Option<User>, StorageError> {
+        sqlx::query_as::<_, User>(
+            "SELECT id, username, password_hash FROM users WHERE id = $1 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(backend)
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<User, StorageError> {
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (username, password_hash) VALUES ($1, $2) \
+             RETURNING id, username, password_hash",
+        )
+        .bind(username)
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(username_conflict)
+    }
+
+    async fn change_password(
+        &self,
+        user_id: i32,
+        password_hash: &str,
+        now: i64,
+    ) -> Result<bool, StorageError> {
+        let mut tx = self.pool.begin().await.map_err(backend)?;
+
+        let updated = sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(password_hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(backend)?;
+        sqlx::query(
+            "UPDATE sessions SET revoked_at = $2 WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(backend)?;
+
+        tx.commit().await.map_err(backend)?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    async fn rename_user(&self, user_id: i32, username: &str) -> Result<bool, StorageError> {
+        let updated =
+            sqlx::query("UPDATE users SET username = $2 WHERE id = $1 AND deleted_at IS NULL")
+                .bind(user_id)
+                .bind(username)
+                .execute(&self.pool)
+                .await
+                .map_err(username_conflict)?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    async fn delete_user(&self, user_id: i32, now: i64) -> Result<bool, StorageError> {
+        let mut tx = self.pool.begin().await.map_err(backend)?;
+
+        // the row stays for bans and match history, what identifies the player goes
+        let deleted = sqlx::query(
+            "UPDATE users SET username = NULL, password_hash = '', deleted_at = $2 \
+             WHERE id = $1 AND deleted_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(backend)?;
+        if deleted.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        for statement in [
+            "DELETE FROM sessions WHERE user_id = $1",
+            "DELETE FROM profiles WHERE user_id = $1",
+            "DELETE FROM friends WHERE requester_id = $1 OR addressee_id = $1",
+        ] {
+            sqlx::query(statement)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(backend)?;
+        }
+
+        tx.commit().await.map_err(backend)?;
+        Ok(true)
+    }
+
+    async fn display_names(&self, ids: &[i32]) -> Result<Vec<(i32, String)>, StorageError> {
+        sqlx::query_as::<_, (i32, String)>(
+            "SELECT users.id, COALESCE(profiles.display_name, users.username, $2) \
+             FROM users LEFT JOIN profiles ON profiles.user_id = users.id \
+             WHERE users.id = ANY($1)",
+        )
+        .bind(ids)
+        .bind(DELETED_DISPLAY_NAME)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(backend)
+    }
+
+    async fn friends(&self, user_id: i32) -> Result<Vec<Friend>, StorageError> {
+        let rows = sqlx::query_as::<_, FriendRow>(
+            "SELECT other.id AS user_id, \
+             COALESCE(profiles.display_name, other.username) AS display_name, \
+             friends.requester_id = $1 AS requested, friends.created_at, friends.accepted_at \
+             FROM friends \
+             JOIN users other ON other.id = CASE WHEN friends.requester_id = $1 \
+             THEN friends.addressee_id ELSE friends.requester_id END \
+             LEFT JOIN profiles ON profiles.user_id = other.id \
+             WHERE friends.requester_id = $1 OR friends.addressee_id = $1 \
+             ORDER BY display_name, other.id",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(backend)?;
+        Ok(rows.into_iter().map(Friend::from).collect())
+    }
+
+    async fn request_friend(
+        &self,
+        from: i32,
+        to: i32,
+        now: i64,
+    ) -> Result<FriendRequestOutcome, StorageError> {
+        let mut tx = self.pool.begin().await.map_err(backend)?;
+
+        let existing = sqlx::query_as::<_, (i32, Option<i64>)>(
+            "SELECT requester_id, accepted_at FROM friends \
+             WHERE (requester_id = $1 AND addressee_id = $2) \
+             OR (requester_id = $2 AND addressee_id = $1) \
+             FOR UPDATE",
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(backend)?;
+
+        let outcome = match existing {
+            Some((_, Some(_))) => FriendRequestOutcome::AlreadyFriends,
+            Some((requester_id, None)) if requester_id == from => FriendRequestOutcome::AlreadySent,
+            Some((requester_id, None)) => {
+                sqlx::query(
+                    "UPDATE friends SET accepted_at = $3 \
+                     WHERE requester_id = $1 AND addressee_id = $2",
+                )
+                .bind(requester_id)
+                .bind(from)
+                .bind(now)
+                .execute(&mut *tx)
+                .await
+                .map_err(backend)?;
+                FriendRequestOutcome::Accepted
+            }
+            None => {
+                // the other one may have asked at the same moment
+                let inserted = sqlx::query(
+                    "INSERT INTO friends (requester_id, addressee_id, created_at) \
+                     VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                )
+                .bind(from)
+                .bind(to)
+                .bind(now)
+                .execute(&mut *tx)
+                .await
+                .map_err(backend)?;
+                if inserted.rows_affected() > 0 {
+                    FriendRequestOutcome::Sent
+                } else {
+                    FriendRequestOutcome::AlreadySent
+                }
+            }
+        };
+
+        tx.commit().await.map_err(backend)?;
+        Ok(outcome)
+    }
+
+    async fn accept_friend(
+        &self,
+        user_id: i32,
+        requester_id: i32,
+        now: i64,
+    ) -> Result<bool, StorageError> {
+        let accepted = sqlx::query(
+            "UPDATE friends SET accepted_at = $3 \
+             WHERE requester_id = $2 AND addressee_id = $1 AND accepted_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(requester_id)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map_err(backend)?;
+        Ok(accepted.rows_affected() > 0)
+    }
+
+    async fn remove_friend(&self, user_id: i32, other_id: i32) -> Result<bool, StorageError> {
+        let removed = sqlx::query(
+            "DELETE FROM friends \
+             WHERE (requester_id = $1 AND addressee_id = $2) \
+             OR (requester_id = $2 AND addressee_id = $1)",
+        )
+        .bind(user_id)
+        .bind(other_id)
+        .execute(&self.pool)
+        .await
+        .map_err(backend)?;
+        Ok(removed.rows_affected() > 0)
+    }
+
+    async fn active_ban(&self, user_id: i32, now: i64) -> Result<Option<String>, StorageError> {
+        let ban = sqlx::query_as::<_, (String,)>(
+            "SELECT reason FROM bans \
+             WHERE user_id = $1 AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > $2) \
+             LIMIT 1",
+        )
+        .bind(user_id)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(backend)?;
+        Ok(ban.map(|(reason,)| reason))
+    }
+
+    async fn profile(&self, user_id: i32) -> Result<Option<Profile>, StorageError> {
+        let row = sqlx::query_as::<_, ProfileRow>(
+            "SELECT users.id, users.username, \
+             COALESCE(profiles.display_name, users.username) AS display_name, \
+             COALESCE(profiles.ship_color, $2) AS ship_color, \
+             users.created_at, \
+             COALESCE(SUM(match_players.kills), 0)::BIGINT AS kills, \
+             COALESCE(SUM(match_players.deaths), 0)::BIGINT AS deaths, \
+             COALESCE(SUM(match_players.asteroids_destroyed), 0)::BIGINT AS asteroids_destroyed, \
+             COUNT(match_players.match_id) AS matches_played, \
+             COUNT(match_players.match_id) FILTER (WHERE match_players.won) AS matches_won, \
+             COALESCE(SUM(match_players.seconds_played), 0)::BIGINT AS seconds_played \
+             FROM users \
+             LEFT JOIN profiles ON profiles.user_id = users.id \
+             LEFT JOIN match_players ON match_players.user_id = users.id \
+             WHERE users.id = $1 AND users.deleted_at IS NULL \
+             GROUP BY users.id, profiles.user_id",
+        )
+        .bind(user_id)
+        .bind(DEFAULT_SHIP_COLOR)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(backend)?;
+        Ok(row.map(Profile::from))
+    }
+
+    async fn update_profile(
+        &self,
+        user_id: i32,
+        update: &UpdateProfileRequest,
+        now: i64,
+    ) -> Result<bool, StorageError> {
+        let updated = sqlx::query(
+            "INSERT INTO profiles (user_id, display_name, ship_color, updated_at) \
+             SELECT id, COALESCE($2, username), COALESCE($3, $4), $5 FROM users \
+             WHERE id = $1 AND deleted_at IS NULL \
+             ON CONFLICT (user_id) DO UPDATE SET \
+             display_name = COALESCE($2, profiles.display_name), \
+             ship_color = COALESCE($3, profiles.ship_color), \
+             updated_at = $5",
+        )
+        .bind(user_id)
+        .bind(&update.display_name)
+        .bind(&update.ship_color)
+        .bind(DEFAULT_SHIP_COLOR)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map_err(backend)?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    async fn record_match(
+        &self,
+        server_id: &str,
+        result: &MatchResultRequest,
+    ) -> Result<i64, StorageError> {
+        let mut tx = self.pool.begin().await.map_err(backend)?;
+
+        let (match_id,) = sqlx::query_as::<_, (i64,)>(
+            "INSERT INTO matches (server_id, room_id, mode, started_at, ended_at) \
+             VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        )
+        .bind(server_id)
+        .bind(result.room_id as i32)
+        .bind(&result.mode)
+        .bind(result.started_at as i64)
+        .bind(result.ended_at as i64)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(backend)?;
+
+        for player in &result.players {
+            sqlx::query(
+                "INSERT INTO match_players \
+                 (match_id, user_id, kills, deaths, score, asteroids_destroyed, seconds_played, won) \
+                 SELECT $1, id, $3, $4, $5, $6, $7, $8 FROM users \
+                 WHERE id = $2 AND deleted_at IS NULL",
+            )
+            .bind(match_id)
+            .bind(player.user_id as i32)
+            .bind(to_i32(player.kills))
+            .bind(to_i32(player.deaths))
+            .bind(to_i32(player.score))
+            .bind(to_i32(player.asteroids_destroyed))
+            .bind(player.seconds_played as i64)
+            .bind(player.won)
+            .execute(&mut *tx)
+            .await
+            .map_err(backend)?;
+        }
+
+        tx.commit().await.map_err(backend)?;
+        Ok(match_id)
+    }
+
+    async fn leaderboard(
+        &self,
+        scope: &LeaderboardScope,
+        after: Option<LeaderboardCursor>,
+        limit: u32,
+    ) -> Result<Vec<LeaderboardEntry>, StorageError> {
+        let query = format!(
+            "{} WHERE $3::FLOAT8 IS NULL OR ranked.value < $3 \
+             OR (ranked.value = $3 AND ranked.user_id > $4) \
+             ORDER BY ranked.value DESC, ranked.user_id LIMIT $5",
+            ranked_query(scope.stat)
+        );
+        let rows = sqlx::query_as::<_, LeaderboardRow>(&query)
+            .bind(scope.since)
+            .bind(scope.min_matches)
+            .bind(after.map(|cursor| cursor.value))
+            .bind(after.map_or(0, |cursor| cursor.user_id))
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(backend)?;
+        Ok(rows.into_iter().map(LeaderboardEntry::from).collect())
+    }
+
+    async fn leaderboard_rank(
+        &self,
+        scope: &LeaderboardScope,
+        user_id: i32,
+    ) -> Result<Option<LeaderboardEntry>, StorageError> {
+        let query = format!("{} WHERE ranked.user_id = $3", ranked_query(scope.stat));
+        let row = sqlx::query_as::<_, LeaderboardRow>(&query)
+            .bind(scope.since)
+            .bind(scope.min_matches)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(backend)?;
+        Ok(row.map(LeaderboardEntry::from))
+    }
+
+    async fn create_session(&self, session: NewSession, now: i64) -> Result<(), StorageError> {
+        let mut tx = self.pool.begin().await.map_err(backend)?;
+
+        sqlx::query("DELETE FROM refresh_tokens WHERE user_id = $1 AND expires_at <= $2")
+            .bind(session.user_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(backend)?;
+        sqlx::query(
+            "DELETE FROM sessions WHERE user_id = $1 \
+             AND NOT EXISTS (SELECT 1 FROM refresh_tokens WHERE session_id = sessions.id)",
+        )
+        .bind(session.user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(backend)?;
+
+        sqlx::query(
+            "INSERT INTO sessions (id, user_id, created_at, last_used_at) VALUES ($1, $2, $3, $3)",
+        )
+        .bind(&session.id)
+        .bind(session.user_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(backend)?;
+        sqlx::query(
+            "INSERT INTO refresh_tokens (token_hash, session_id, user_id, expires_at) \
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(&session.token_hash)
+        .bind(&session.id)
+        .bind(session.user_id)
+        .bind(session.token_expires_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(backend)?;
+
+        tx.commit().await.map_err(backend)
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        token_hash: &str,
+        replacement_hash: &str,
+        replacement_expires_at: i64,
+        now: i64,
+    ) -> Result<Rotation, StorageError> {
+        let mut tx = self.pool.begin().await.map_err(backend)?;
+
+        let spent = sqlx::query_as::<_, (i32, String, i64)>(
+            "UPDATE refresh_tokens SET revoked = TRUE \
+             FROM sessions \
+             WHERE refresh_tokens.token_hash = $1 AND NOT refresh_tokens.revoked \
+             AND sessions.id = refresh_tokens.session_id AND sessions.revoked_at IS NULL \
+             RETURNING refresh_tokens.user_id, refresh_tokens.session_id, refresh_tokens.expires_at",
+        )
+        .bind(token_hash)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(backend)?;
+
+        let Some((user_id, session_id, expires_at)) = spent else {
+            drop(tx);
+            let revoked = sqlx::query(
+                "UPDATE sessions SET revoked_at = $2 \
+                 WHERE id = (SELECT session_id FROM refresh_tokens WHERE token_hash = $1) \
+                 AND revoked_at IS NULL",
+            )
+            .bind(token_hash)
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .map_err(backend)?;
+            return Ok(if revoked.rows_affected() > 0 {
+                Rotation::Reused
+            } else {
+                Rotation::Unknown
+            });
+        };
+        if expires_at <= now {
+            return Ok(Rotation::Expired);
+        }
+
+        sqlx::query("UPDATE sessions SET last_used_at = $2 WHERE id = $1")
+            .bind(&session_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(backend)?;
+        sqlx::query(
+            "INSERT INTO refresh_tokens (token_hash, session_id, user_id, expires_at) \
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(replacement_hash)
+        .bind(&session_id)
+        .bind(user_id)
+        .bind(replacement_expires_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(backend)?;
+
+        tx.commit().await.map_err(backend)?;
+        Ok(Rotation::Rotated { user_id })
+    }
+}
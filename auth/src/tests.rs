@@ -0,0 +1,710 @@
+//! The whole router on the in-memory backend, so every endpoint is covered without PostgreSQL.
+//! The backend has to behave like the PostgreSQL one for these to mean anything.
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Method, Request, StatusCode, header},
+};
+use common::utils::current_time_ms;
+use serde_json::{Value, json};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tower::ServiceExt;
+
+use crate::{
+    AppState,
+    accounts::PasswordRules,
+    parties::PartyRegistry,
+    passwords::PasswordHasher,
+    router,
+    servers::ServerRegistry,
+    storage::{DELETED_DISPLAY_NAME, MemoryStorage},
+    throttle::{LoginThrottle, ThrottleConfig},
+    tokens::{RefreshTokens, TokenIssuer},
+};
+
+const SERVER_TOKEN: &str = "server-secret";
+const PASSWORD: &str = "Correct-horse-1";
+/// bcrypt's lowest cost, the real one takes over a second per hash in a debug build.
+const TEST_BCRYPT_COST: u32 = 4;
+
+struct TestApp {
+    router: Router,
+}
+
+/// What registering or logging in hands back.
+struct Login {
+    id: i64,
+    token: String,
+    refresh_token: String,
+}
+
+impl TestApp {
+    fn new() -> Self {
+        let state = Arc::new(AppState {
+            storage: Arc::new(MemoryStorage::default()),
+            servers: Arc::new(ServerRegistry::new(
+                Duration::from_secs(60),
+                Duration::from_secs(30),
+            )),
+            parties: Arc::new(PartyRegistry::default()),
+            registration_token: Some(SERVER_TOKEN.into()),
+            tokens: TokenIssuer::new("jwt-secret", Duration::from_secs(900)),
+            refresh_tokens: RefreshTokens::new(Duration::from_secs(3600)),
+            passwords: Arc::new(PasswordHasher::with_cost(4, TEST_BCRYPT_COST)),
+            password_rules: PasswordRules {
+                min_length: 8,
+                min_classes: 2,
+            },
+            login_throttle: Arc::new(LoginThrottle::new(ThrottleConfig {
+                attempts_per_minute: 1000,
+                max_failures: 5,
+                lockout: Duration::from_secs(30),
+                max_lockout: Duration::from_secs(3600),
+            })),
+        });
+        Self {
+            router: router(state),
+        }
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let mut request = request.body(body).unwrap();
+        // what `into_make_service_with_connect_info` adds for real connections
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 50000))));
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap()
+        };
+        (status, body)
+    }
+
+    async fn get(&self, uri: &str, token: Option<&str>) -> (StatusCode, Value) {
+        self.send(Method::GET, uri, token, None).await
+    }
+
+    async fn post(&self, uri: &str, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+        self.send(Method::POST, uri, token, Some(body)).await
+    }
+
+    async fn register(&self, username: &str) -> Login {
+        let (status, body) = self
+            .post(
+                "/register",
+                None,
+                json!({ "username": username, "password": PASSWORD }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        login_of(&body)
+    }
+
+    async fn login(&self, username: &str, password: &str) -> (StatusCode, Value) {
+        self.post(
+            "/login",
+            None,
+            json!({ "username": username, "password": password }),
+        )
+        .await
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> (StatusCode, Value) {
+        self.post(
+            "/token/refresh",
+            None,
+            json!({ "refresh_token": refresh_token }),
+        )
+        .await
+    }
+
+    /// Registers a game server the way one does on startup, returning its id.
+    async fn register_server(&self, name: &str, mode: &str, capacity: u32) -> String {
+        let (status, body) = self
+            .post(
+                "/servers/register",
+                Some(SERVER_TOKEN),
+                json!({
+                    "name": name,
+                    "room_id": 1,
+                    "udp_addr": format!("{name}.example:8080"),
+                    "region": "eu",
+                    "mode": mode,
+                    "capacity": capacity,
+                    "players": 0,
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body["server_id"].as_str().unwrap().to_string()
+    }
+
+    /// Reports a finished match with `(user id, kills)` for each player, kills are the score too.
+    async fn record_match(&self, server_id: &str, kills: &[(i64, u32)]) {
+        let now = current_time_ms() / 1000;
+        let players: Vec<Value> = kills
+            .iter()
+            .map(|&(user_id, kills)| {
+                json!({
+                    "user_id": user_id,
+                    "kills": kills,
+                    "deaths": 1,
+                    "asteroids_destroyed": 0,
+                    "score": kills,
+                    "seconds_played": 60,
+                    "won": false,
+                })
+            })
+            .collect();
+        let (status, body) = self
+            .post(
+                &format!("/servers/{server_id}/matches"),
+                Some(SERVER_TOKEN),
+                json!({
+                    "room_id": 1,
+                    "mode": "deathmatch",
+                    "started_at": now - 60,
+                    "ended_at": now,
+                    "players": players,
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::NO_CONTENT, "{body}");
+    }
+
+    /// Makes the two friends, `a` asking and `b` accepting.
+    async fn befriend(&self, a: &Login, b: &Login, b_name: &str) {
+        let (status, _) = self
+            .post("/friends", Some(&a.token), json!({ "username": b_name }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = self
+            .send(
+                Method::POST,
+                &format!("/friends/{}/accept", a.id),
+                Some(&b.token),
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+}
+
+fn login_of(body: &Value) -> Login {
+    Login {
+        id: body["id"].as_i64().unwrap(),
+        token: body["token"].as_str().unwrap().to_string(),
+        refresh_token: body["refresh_token"].as_str().unwrap().to_string(),
+    }
+}
+
+#[tokio::test]
+async fn usernames_ignore_case() {
+    let app = TestApp::new();
+    let alice = app.register("Alice").await;
+
+    let (status, _) = app
+        .post(
+            "/register",
+            None,
+            json!({ "username": "alice", "password": PASSWORD }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, body) = app.login("ALICE", PASSWORD).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"].as_i64(), Some(alice.id));
+}
+
+#[tokio::test]
+async fn login_rejects_bad_credentials_alike() {
+    let app = TestApp::new();
+    app.register("alice").await;
+
+    let (status, wrong_password) = app.login("alice", "Wrong-horse-1").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, unknown_user) = app.login("nobody", PASSWORD).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(wrong_password, unknown_user);
+
+    let (status, _) = app
+        .post(
+            "/register",
+            None,
+            json!({ "username": "admin", "password": PASSWORD }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app
+        .post(
+            "/register",
+            None,
+            json!({ "username": "bob", "password": "password" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn refresh_rotates_and_reuse_revokes_the_session() {
+    let app = TestApp::new();
+    let first = app.register("alice").await;
+    let (status, body) = app.login("alice", PASSWORD).await;
+    assert_eq!(status, StatusCode::OK);
+    let other_session = login_of(&body);
+
+    let (status, body) = app.refresh(&first.refresh_token).await;
+    assert_eq!(status, StatusCode::OK);
+    let rotated = login_of(&body);
+    assert_eq!(rotated.id, first.id);
+    assert_ne!(rotated.refresh_token, first.refresh_token);
+    let (status, _) = app.get("/me", Some(&rotated.token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // replaying the spent token ends the session, the token it was traded for too
+    let (status, _) = app.refresh(&first.refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.refresh(&rotated.refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app.refresh(&other_session.refresh_token).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.refresh("not-a-token").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn password_change_ends_other_sessions() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+
+    let (status, body) = app
+        .post(
+            "/account/password",
+            Some(&alice.token),
+            json!({ "current_password": PASSWORD, "new_password": "Battery-staple-2" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.refresh(&alice.refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.refresh(&login_of(&body).refresh_token).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app.login("alice", PASSWORD).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.login("alice", "Battery-staple-2").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn profiles_show_display_names_and_stats() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+
+    let (status, me) = app.get("/me", Some(&alice.token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["display_name"], "alice");
+    let (status, _) = app.get("/me", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, updated) = app
+        .send(
+            Method::PATCH,
+            "/me",
+            Some(&alice.token),
+            Some(json!({ "display_name": "Ace_Pilot", "ship_color": "#AABBCC" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["display_name"], "Ace_Pilot");
+    assert_eq!(updated["ship_color"], "#aabbcc");
+    for invalid in [
+        json!({ "display_name": "Admin" }),
+        json!({ "display_name": "a b" }),
+        json!({ "ship_color": "red" }),
+    ] {
+        let (status, _) = app
+            .send(Method::PATCH, "/me", Some(&alice.token), Some(invalid))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    let server_id = app.register_server("eu-1", "deathmatch", 8).await;
+    app.record_match(&server_id, &[(alice.id, 4)]).await;
+    app.record_match(&server_id, &[(alice.id, 2)]).await;
+    let (status, profile) = app.get(&format!("/profile/{}", alice.id), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(profile["display_name"], "Ace_Pilot");
+    assert_eq!(profile["stats"]["kills"], 6);
+    assert_eq!(profile["stats"]["matches_played"], 2);
+
+    let (status, _) = app.get("/profile/999", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn friend_requests_are_accepted_and_removed() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+
+    let (status, _) = app
+        .post("/friends", Some(&alice.token), json!({ "username": "Bob" }))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = app
+        .post("/friends", Some(&alice.token), json!({ "username": "bob" }))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = app
+        .post(
+            "/friends",
+            Some(&alice.token),
+            json!({ "username": "alice" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app
+        .post(
+            "/friends",
+            Some(&alice.token),
+            json!({ "username": "carol" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, friends) = app.get("/friends", Some(&bob.token)).await;
+    assert_eq!(friends["friends"][0]["user_id"].as_i64(), Some(alice.id));
+    assert_eq!(friends["friends"][0]["state"], "incoming");
+
+    let accept = format!("/friends/{}/accept", alice.id);
+    let (status, _) = app
+        .send(Method::POST, &accept, Some(&bob.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = app
+        .send(Method::POST, &accept, Some(&bob.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, friends) = app.get("/friends", Some(&alice.token)).await;
+    assert_eq!(friends["friends"][0]["state"], "friends");
+    assert_eq!(friends["friends"][0]["display_name"], "bob");
+
+    let remove = format!("/friends/{}", bob.id);
+    let (status, _) = app
+        .send(Method::DELETE, &remove, Some(&alice.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = app
+        .send(Method::DELETE, &remove, Some(&alice.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, friends) = app.get("/friends", Some(&bob.token)).await;
+    assert_eq!(friends["friends"], json!([]));
+}
+
+#[tokio::test]
+async fn leaderboard_shares_ranks_on_ties_and_pages_with_cursors() {
+    let app = TestApp::new();
+    let mut players = Vec::new();
+    for name in ["alice", "bob", "carol", "dave", "erin"] {
+        players.push(app.register(name).await.id);
+    }
+    let server_id = app.register_server("eu-1", "deathmatch", 8).await;
+    app.record_match(
+        &server_id,
+        &[
+            (players[0], 3),
+            (players[1], 7),
+            (players[2], 7),
+            (players[3], 1),
+            (players[4], 3),
+        ],
+    )
+    .await;
+
+    let mut pages = Vec::new();
+    let mut uri = "/leaderboard?stat=kills&limit=2".to_string();
+    loop {
+        let (status, page) = app.get(&uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let entries = page["entries"].as_array().unwrap().clone();
+        pages.push(entries.len());
+        for entry in entries {
+            let rank = entry["rank"].as_u64().unwrap();
+            let user_id = entry["user_id"].as_i64().unwrap();
+            let expected = match user_id {
+                id if id == players[1] || id == players[2] => 1,
+                id if id == players[0] || id == players[4] => 3,
+                _ => 5,
+            };
+            assert_eq!(rank, expected, "{entry}");
+        }
+        match page["next_cursor"].as_str() {
+            Some(cursor) => uri = format!("/leaderboard?stat=kills&limit=2&cursor={cursor}"),
+            None => break,
+        }
+    }
+    assert_eq!(pages, [2, 2, 1]);
+
+    // ties are ordered by user id, and the cursor picks up between tied players
+    let (_, first) = app.get("/leaderboard?stat=kills&limit=3", None).await;
+    let ids: Vec<i64> = first["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["user_id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, [players[1], players[2], players[0]]);
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let (_, rest) = app
+        .get(&format!("/leaderboard?stat=kills&cursor={cursor}"), None)
+        .await;
+    assert_eq!(rest["entries"][0]["user_id"].as_i64(), Some(players[4]));
+    assert_eq!(rest["next_cursor"], Value::Null);
+
+    let (status, _) = app.get("/leaderboard?cursor=zz", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = app.login("erin", PASSWORD).await;
+    assert_eq!(status, StatusCode::OK);
+    let erin = login_of(&body);
+    let (status, mine) = app
+        .get("/leaderboard/me?stat=kills", Some(&erin.token))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mine["rank"], 3);
+    // one match isn't enough for a ratio
+    let (status, _) = app.get("/leaderboard/me?stat=kd", Some(&erin.token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn matchmaking_reserves_slots_for_the_whole_party() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let carol = app.register("carol").await;
+    let matchmake = json!({ "mode": "deathmatch" });
+
+    let (status, _) = app
+        .post("/matchmake", Some(&carol.token), matchmake.clone())
+        .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    let server_id = app.register_server("eu-1", "deathmatch", 3).await;
+    let (status, _) = app
+        .post("/matchmake", Some(&carol.token), json!({ "mode": "ctf" }))
+        .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let (status, solo) = app
+        .post("/matchmake", Some(&carol.token), matchmake.clone())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(solo["server_id"], server_id.as_str());
+    assert_eq!(solo["address"], "eu-1.example:8080");
+
+    app.befriend(&alice, &bob, "bob").await;
+    let (status, _) = app.post("/party", Some(&alice.token), json!({})).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, party) = app.get("/party", Some(&alice.token)).await;
+    let party_id = party["party"]["id"].as_str().unwrap().to_string();
+    let (status, _) = app
+        .post(
+            "/party/invite",
+            Some(&alice.token),
+            json!({ "user_id": bob.id }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = app
+        .post(
+            "/party/join",
+            Some(&bob.token),
+            json!({ "party_id": party_id }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // members follow their leader
+    let (status, _) = app
+        .post("/matchmake", Some(&bob.token), matchmake.clone())
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, leader) = app
+        .post("/matchmake", Some(&alice.token), matchmake.clone())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, member) = app
+        .post("/matchmake", Some(&bob.token), matchmake.clone())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(member["server_id"], leader["server_id"]);
+    assert_ne!(member["ticket"], leader["ticket"]);
+
+    // carol's slot and the party's two fill the server
+    let dave = app.register("dave").await;
+    let (status, _) = app.post("/matchmake", Some(&dave.token), matchmake).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn party_members_get_the_held_slot_whatever_mode_they_ask_for() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let deathmatch = app.register_server("eu-1", "deathmatch", 8).await;
+    app.register_server("eu-2", "ctf", 8).await;
+
+    app.befriend(&alice, &bob, "bob").await;
+    app.post("/party", Some(&alice.token), json!({})).await;
+    let (_, party) = app.get("/party", Some(&alice.token)).await;
+    let party_id = party["party"]["id"].as_str().unwrap().to_string();
+    app.post(
+        "/party/invite",
+        Some(&alice.token),
+        json!({ "user_id": bob.id }),
+    )
+    .await;
+    let (status, _) = app
+        .post(
+            "/party/join",
+            Some(&bob.token),
+            json!({ "party_id": party_id }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _) = app
+        .post(
+            "/matchmake",
+            Some(&alice.token),
+            json!({ "mode": "deathmatch" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, member) = app
+        .post("/matchmake", Some(&bob.token), json!({ "mode": "ctf" }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(member["server_id"], deathmatch.as_str());
+    assert_eq!(member["mode"], "deathmatch");
+
+    // asking again hands out the same slot rather than a second one
+    let (status, again) = app
+        .post("/matchmake", Some(&bob.token), json!({ "mode": "ctf" }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(again["server_id"], deathmatch.as_str());
+}
+
+#[tokio::test]
+async fn deleted_accounts_free_their_name_and_keep_their_results() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    app.befriend(&alice, &bob, "bob").await;
+    let server_id = app.register_server("eu-1", "deathmatch", 8).await;
+    app.record_match(&server_id, &[(alice.id, 5), (bob.id, 2)])
+        .await;
+
+    let (status, _) = app
+        .send(
+            Method::DELETE,
+            "/account",
+            Some(&alice.token),
+            Some(json!({ "password": "Wrong-horse-1" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .send(
+            Method::DELETE,
+            "/account",
+            Some(&alice.token),
+            Some(json!({ "password": PASSWORD })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _) = app.login("alice", PASSWORD).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.refresh(&alice.refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.get(&format!("/profile/{}", alice.id), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, friends) = app.get("/friends", Some(&bob.token)).await;
+    assert_eq!(friends["friends"], json!([]));
+
+    let (_, board) = app.get("/leaderboard", None).await;
+    assert_eq!(board["entries"][0]["user_id"].as_i64(), Some(alice.id));
+    assert_eq!(board["entries"][0]["display_name"], DELETED_DISPLAY_NAME);
+    assert_eq!(board["entries"][1]["display_name"], "bob");
+
+    let again = app.register("alice").await;
+    assert_ne!(again.id, alice.id);
+    let (_, board) = app.get("/leaderboard", None).await;
+    assert_eq!(board["entries"][0]["display_name"], DELETED_DISPLAY_NAME);
+}
+
+#[tokio::test]
+async fn game_servers_need_the_registration_token() {
+    let app = TestApp::new();
+    let (status, _) = app
+        .post(
+            "/servers/register",
+            Some("guess"),
+            json!({
+                "name": "eu-1",
+                "room_id": 1,
+                "udp_addr": "eu-1.example:8080",
+                "region": "eu",
+                "mode": "deathmatch",
+                "capacity": 8,
+                "players": 0,
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let server_id = app.register_server("eu-1", "deathmatch", 8).await;
+    let (_, listed) = app.get("/servers", None).await;
+    assert_eq!(listed["servers"][0]["id"], server_id.as_str());
+    let (status, _) = app
+        .send(
+            Method::DELETE,
+            &format!("/servers/{server_id}"),
+            Some(SERVER_TOKEN),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, listed) = app.get("/servers", None).await;
+    assert_eq!(listed["servers"], json!([]));
+}
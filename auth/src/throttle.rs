@@ -0,0 +1,352 @@
+//! Brute-force protection for the endpoints that check passwords.
+//!
+//! Every address gets a budget of attempts per minute, and repeated failures lock out
+//! both the address and the username, for twice as long with every further failure.
+//! Addresses come from the TCP connection, a reverse proxy in front of the service
+//! would put every player behind the same one.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Addresses are shared by players behind the same NAT, so they get more failures.
+const IP_FAILURES_FACTOR: u32 = 4;
+
+pub struct ThrottleConfig {
+    pub attempts_per_minute: u32,
+    pub max_failures: u32,
+    pub lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+impl ThrottleConfig {
+    /// Reads the limits from the environment:
+    /// - `LOGIN_ATTEMPTS_PER_MINUTE`, attempts one address may make, default 20
+    /// - `LOGIN_MAX_FAILURES`, failures in a row before a username is locked out, default 5
+    /// - `LOGIN_LOCKOUT_SECS`, the first lockout, doubled for every further failure, default 30
+    /// - `LOGIN_MAX_LOCKOUT_SECS`, the longest lockout, default 3600
+    pub fn from_env() -> Self {
+        fn var(name: &str, default: u64) -> u64 {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let lockout = Duration::from_secs(var("LOGIN_LOCKOUT_SECS", 30).max(1));
+        Self {
+            attempts_per_minute: var("LOGIN_ATTEMPTS_PER_MINUTE", 20).max(1) as u32,
+            max_failures: var("LOGIN_MAX_FAILURES", 5).max(1) as u32,
+            lockout,
+            max_lockout: Duration::from_secs(var("LOGIN_MAX_LOCKOUT_SECS", 3600)).max(lockout),
+        }
+    }
+}
+
+pub struct LoginThrottle {
+    config: ThrottleConfig,
+    state: Mutex<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    attempts: HashMap<IpAddr, Attempts>,
+    ip_failures: HashMap<IpAddr, Failures>,
+    username_failures: HashMap<String, Failures>,
+}
+
+struct Attempts {
+    window_start: Instant,
+    count: u32,
+}
+
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    /// Time left on the lockout, if any.
+    fn locked_for(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Counts a failure, returns the lockout it starts once past `max_failures`.
+    fn add(
+        &mut self,
+        config: &ThrottleConfig,
+        max_failures: u32,
+        now: Instant,
+    ) -> Option<Duration> {
+        self.count += 1;
+        self.last_failure = now;
+        if self.count < max_failures {
+            return None;
+        }
+        let doublings = (self.count - max_failures).min(16);
+        let lockout = (config.lockout * 2u32.pow(doublings)).min(config.max_lockout);
+        self.locked_until = Some(now + lockout);
+        Some(lockout)
+    }
+}
+
+impl LoginThrottle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(Counters::default()),
+        }
+    }
+
+    /// Counts an attempt from `ip`, refused with the time to wait when the address is
+    /// over its budget or locked out, or when `username` is locked out.
+    pub fn check(&self, ip: IpAddr, username: Option<&str>) -> Result<(), Duration> {
+        self.check_at(ip, username, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, username: Option<&str>, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+
+        let locked_for = state
+            .ip_failures
+            .get(&ip)
+            .and_then(|failures| failures.locked_for(now))
+            .or_else(|| {
+                username
+                    .and_then(|username| state.username_failures.get(&key(username)))
+                    .and_then(|failures| failures.locked_for(now))
+            });
+        if let Some(locked_for) = locked_for {
+            return Err(locked_for);
+        }
+
+        let attempts = state.attempts.entry(ip).or_insert(Attempts {
+            window_start: now,
+            count: 0,
+        });
+        if now - attempts.window_start >= RATE_WINDOW {
+            attempts.window_start = now;
+            attempts.count = 0;
+        }
+        if attempts.count >= self.config.attempts_per_minute {
+            return Err(attempts.window_start + RATE_WINDOW - now);
+        }
+        attempts.count += 1;
+        Ok(())
+    }
+
+    /// Records a wrong password, or a username that doesn't exist.
+    pub fn failed(&self, ip: IpAddr, username: &str) {
+        self.failed_at(ip, username, Instant::now());
+    }
+
+    fn failed_at(&self, ip: IpAddr, username: &str, now: Instant) {
+        let config = &self.config;
+        let mut state = self.state.lock().unwrap();
+        let new_failures = || Failures {
+            count: 0,
+            last_failure: now,
+            locked_until: None,
+        };
+
+        let username_failures = state
+            .username_failures
+            .entry(key(username))
+            .or_insert_with(new_failures);
+        if let Some(lockout) = username_failures.add(config, config.max_failures, now) {
+            warn!(
+                username,
+                lockout_secs = lockout.as_secs(),
+                "Username locked out"
+            );
+        }
+
+        let ip_failures = state.ip_failures.entry(ip).or_insert_with(new_failures);
+        let max_failures = config.max_failures * IP_FAILURES_FACTOR;
+        if let Some(lockout) = ip_failures.add(config, max_failures, now) {
+            warn!(%ip, lockout_secs = lockout.as_secs(), "Address locked out");
+        }
+    }
+
+    /// A correct password clears the failures of the address and the username.
+    pub fn succeeded(&self, ip: IpAddr, username: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.ip_failures.remove(&ip);
+        state.username_failures.remove(&key(username));
+    }
+
+    /// Forgets counters that no longer limit anything. Failures count towards a
+    /// lockout until `max_lockout` passes without another one.
+    fn prune(&self, now: Instant) {
+        let forget_after = self.config.max_lockout;
+        let stale = |failures: &Failures| {
+            failures.locked_for(now).is_none() && now - failures.last_failure >= forget_after
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .attempts
+            .retain(|_, attempts| now - attempts.window_start < RATE_WINDOW);
+        state.ip_failures.retain(|_, failures| !stale(failures));
+        state
+            .username_failures
+            .retain(|_, failures| !stale(failures));
+    }
+}
+
+/// Periodically drops the counters of addresses and usernames that went quiet.
+pub async fn run_cleanup(throttle: Arc<LoginThrottle>) {
+    let mut interval = tokio::time::interval(RATE_WINDOW);
+    loop {
+        interval.tick().await;
+        throttle.prune(Instant::now());
+    }
+}
+
+/// Usernames are throttled regardless of case, so `Alice` and `alice` share a lockout.
+fn key(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(ThrottleConfig {
+            attempts_per_minute: 100,
+            max_failures: 3,
+            lockout: 30 * SEC,
+            max_lockout: 300 * SEC,
+        })
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[test]
+    fn address_over_its_budget_waits_for_the_next_window() {
+        let throttle = LoginThrottle::new(ThrottleConfig {
+            attempts_per_minute: 3,
+            ..throttle().config
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(throttle.check_at(ip(1), Some("ann"), start).is_ok());
+        }
+        let later = start + 20 * SEC;
+        assert_eq!(throttle.check_at(ip(1), Some("ann"), later), Err(40 * SEC));
+        assert!(throttle.check_at(ip(2), Some("ann"), later).is_ok());
+        assert!(throttle.check_at(ip(1), None, start + RATE_WINDOW).is_ok());
+    }
+
+    #[test]
+    fn username_locks_out_after_max_failures_from_any_address() {
+        let throttle = throttle();
+        let now = Instant::now();
+        throttle.failed_at(ip(1), "ann", now);
+        throttle.failed_at(ip(2), "Ann", now);
+        assert!(throttle.check_at(ip(3), Some("ann"), now).is_ok());
+
+        throttle.failed_at(ip(3), "ANN ", now);
+        assert_eq!(throttle.check_at(ip(4), Some("ann"), now), Err(30 * SEC));
+        assert_eq!(
+            throttle.check_at(ip(4), Some("ann"), now + 10 * SEC),
+            Err(20 * SEC)
+        );
+        // the address isn't locked, other accounts still work from it
+        assert!(throttle.check_at(ip(3), Some("ben"), now).is_ok());
+        assert!(
+            throttle
+                .check_at(ip(4), Some("ann"), now + 30 * SEC)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn address_locks_out_after_more_failures_than_a_username() {
+        let throttle = throttle();
+        let now = Instant::now();
+        let limit = 3 * IP_FAILURES_FACTOR;
+        for n in 0..limit - 1 {
+            throttle.failed_at(ip(1), &format!("user{n}"), now);
+        }
+        assert!(throttle.check_at(ip(1), Some("ann"), now).is_ok());
+
+        throttle.failed_at(ip(1), "another", now);
+        assert_eq!(throttle.check_at(ip(1), None, now), Err(30 * SEC));
+        assert!(throttle.check_at(ip(2), Some("ann"), now).is_ok());
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_maximum() {
+        let throttle = throttle();
+        let mut now = Instant::now();
+        for expected in [30, 60, 120, 240, 300, 300] {
+            // failures keep coming in as each lockout runs out
+            loop {
+                throttle.failed_at(ip(1), "ann", now);
+                if throttle.check_at(ip(9), Some("ann"), now).is_err() {
+                    break;
+                }
+            }
+            assert_eq!(
+                throttle.check_at(ip(9), Some("ann"), now),
+                Err(expected * SEC)
+            );
+            now += expected * SEC;
+        }
+    }
+
+    #[test]
+    fn success_clears_failures() {
+        let throttle = throttle();
+        let now = Instant::now();
+        throttle.failed_at(ip(1), "ann", now);
+        throttle.failed_at(ip(1), "ann", now);
+        throttle.succeeded(ip(1), "ann");
+        throttle.failed_at(ip(1), "ann", now);
+        throttle.failed_at(ip(1), "ann", now);
+        assert!(throttle.check_at(ip(1), Some("ann"), now).is_ok());
+
+        throttle.failed_at(ip(1), "ann", now);
+        assert!(throttle.check_at(ip(1), Some("ann"), now).is_err());
+        // a lockout in force isn't lifted by someone else logging in from elsewhere
+        throttle.succeeded(ip(2), "ben");
+        assert!(throttle.check_at(ip(1), Some("ann"), now).is_err());
+    }
+
+    #[test]
+    fn prune_forgets_quiet_counters_only() {
+        let throttle = throttle();
+        let now = Instant::now();
+        throttle.check_at(ip(1), Some("ann"), now).unwrap();
+        for _ in 0..3 {
+            throttle.failed_at(ip(1), "ann", now);
+        }
+        throttle.failed_at(ip(2), "ben", now + 200 * SEC);
+
+        throttle.prune(now + 299 * SEC);
+        {
+            let state = throttle.state.lock().unwrap();
+            assert!(state.attempts.is_empty());
+            assert_eq!(state.username_failures.len(), 2);
+        }
+
+        throttle.prune(now + 300 * SEC);
+        let state = throttle.state.lock().unwrap();
+        assert!(!state.username_failures.contains_key("ann"));
+        assert!(state.username_failures.contains_key("ben"));
+        assert!(!state.ip_failures.contains_key(&ip(1)));
+        assert!(state.ip_failures.contains_key(&ip(2)));
+    }
+}
@@ -0,0 +1,199 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+use common::auth::Claims;
+use common::utils::current_time_ms;
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::models::{AppError, User};
+use crate::storage::{NewSession, Rotation, Storage};
+
+/// Signs the short-lived access tokens game servers check during the handshake,
+/// and the join tickets matchmaking hands out.
+#[derive(Clone)]
+pub struct TokenIssuer {
+    key: EncodingKey,
+    decoding_key: DecodingKey,
+    ttl: Duration,
+}
+
+impl TokenIssuer {
+    pub fn new(secret: &str, ttl: Duration) -> Self {
+        Self {
+            key: EncodingKey::from_secret(secret.as_bytes()),
+            decoding_key: DecodingKey::from_secret(secret.as_bytes()),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn issue(&self, user: &User) -> Result<String, AppError> {
+        let now = current_time_ms() / 1000;
+        let claims = Claims {
+            sub: user.id.to_string(),
+            username: user.username.clone(),
+            iat: now,
+            exp: now + self.ttl.as_secs(),
+            aud: None,
+            jti: None,
+        };
+        self.sign(&claims)
+    }
+
+    /// Signs a ticket that only the game server at `address` accepts, once.
+    pub fn issue_ticket(
+        &self,
+        user: &AuthUser,
+        address: &str,
+        ticket_id: &str,
+        ttl: Duration,
+    ) -> Result<String, AppError> {
+        let now = current_time_ms() / 1000;
+        let claims = Claims {
+            sub: user.id.to_string(),
+            username: user.username.clone(),
+            iat: now,
+            exp: now + ttl.as_secs(),
+            aud: Some(address.to_string()),
+            jti: Some(ticket_id.to_string()),
+        };
+        self.sign(&claims)
+    }
+
+    /// Checks an access token. Join tickets are refused since they name an audience.
+    pub fn verify(&self, token: &str) -> Result<Claims, AppError> {
+        let mut validation = Validation::default();
+        validation.set_required_spec_claims(&["exp", "sub"]);
+
+        let claims = decode::<Claims>(token, &self.decoding_key, &validation)
+            .map_err(|_| AppError::Unauthorized("Invalid or expired token".into()))?
+            .claims;
+        if claims.aud.is_some() || claims.jti.is_some() {
+            return Err(AppError::Unauthorized("Invalid or expired token".into()));
+        }
+        Ok(claims)
+    }
+
+    fn sign(&self, claims: &Claims) -> Result<String, AppError> {
+        encode(&Header::default(), claims, &self.key)
+            .map_err(|e| AppError::Internal(format!("Failed to sign token: {e}")))
+    }
+}
+
+/// The player a request's `Authorization: Bearer` access token belongs to.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i32,
+    pub username: String,
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AppError::Unauthorized("Missing access token".into()))?;
+
+        let claims = state.tokens.verify(token)?;
+        let id = claims
+            .sub
+            .parse()
+            .map_err(|_| AppError::Unauthorized("Invalid or expired token".into()))?;
+        Ok(AuthUser {
+            id,
+            username: claims.username,
+        })
+    }
+}
+
+/// Opaque, single-use tokens that trade for a new access token. Only their hashes are stored.
+/// Each login opens a session, and each refresh replaces the token with a new one in it.
+#[derive(Clone)]
+pub struct RefreshTokens {
+    ttl: Duration,
+}
+
+impl RefreshTokens {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Opens a session for a fresh login and returns its first token.
+    pub async fn issue(&self, storage: &dyn Storage, user_id: i32) -> Result<String, AppError> {
+        let now = unix_now();
+        let token = new_token();
+        let session = NewSession {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            token_hash: hash(&token),
+            token_expires_at: now + self.ttl.as_secs() as i64,
+        };
+        storage.create_session(session, now).await?;
+        Ok(token)
+    }
+
+    /// Spends `token` and returns the owner together with its replacement.
+    /// Presenting a token that was already spent revokes its whole session, since
+    /// either the client or whoever stole the token is replaying it.
+    pub async fn rotate(
+        &self,
+        storage: &dyn Storage,
+        token: &str,
+    ) -> Result<(i32, String), AppError> {
+        let now = unix_now();
+        let replacement = new_token();
+        let rotation = storage
+            .rotate_refresh_token(
+                &hash(token),
+                &hash(&replacement),
+                now + self.ttl.as_secs() as i64,
+                now,
+            )
+            .await?;
+
+        match rotation {
+            Rotation::Rotated { user_id } => Ok((user_id, replacement)),
+            Rotation::Expired => Err(AppError::Unauthorized("Refresh token expired".into())),
+            Rotation::Reused => {
+                warn!("Spent refresh token presented again, revoked its session");
+                Err(AppError::Unauthorized("Invalid refresh token".into()))
+            }
+            Rotation::Unknown => Err(AppError::Unauthorized("Invalid refresh token".into())),
+        }
+    }
+}
+
+fn new_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn unix_now() -> i64 {
+    (current_time_ms() / 1000) as i64
+}
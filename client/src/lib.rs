@@ -19,6 +19,8 @@ use godot::{
 use crate::net::NetworkClient;
 use crate::net::async_runtime::AsyncRuntime;
 
+const DEFAULT_MATCHMAKING_MODE: &str = "deathmatch";
+
 struct MyExtension;
 
 #[gdextension]
@@ -35,16 +37,29 @@ unsafe impl ExtensionLibrary for MyExtension {
 
         if err == Error::OK {
             let game_server_udp = conf.get_value("GameServer", "address_udp").to_string();
-            let game_server_tcp = conf.get_value("GameServer", "address_tcp").to_string();
             let auth_server = conf.get_value("AuthServer", "address").to_string();
             // godot_print!("Hello, GameServer: {game_server} | AuthServer: {auth_server}");
-            instance.bind_mut().set_config(game_server_udp, game_server_tcp, auth_server);
+            instance.bind_mut().set_config(game_server_udp, auth_server);
+
+            let mode = if conf.has_section_key("Matchmaking", "mode") {
+                conf.get_value("Matchmaking", "mode").to_string()
+            } else {
+                String::from(DEFAULT_MATCHMAKING_MODE)
+            };
+            let region = if conf.has_section_key("Matchmaking", "region") {
+                Some(conf.get_value("Matchmaking", "region").to_string()).filter(|r| !r.is_empty())
+            } else {
+                None
+            };
+            instance.bind_mut().set_matchmaking(mode, region);
         } else {
             let game_server_udp = String::from("127.0.0.1:8080");
-            let game_server_tcp = String::from("127.0.0.1:8081");
             let auth_server = String::from("127.0.0.1:3000");
             // godot_print!("Going to defauls, GameServer: {game_server} | AuthServer: {auth_server}");
-            instance.bind_mut().set_config(game_server_udp, game_server_tcp, auth_server);
+            instance.bind_mut().set_config(game_server_udp, auth_server);
+            instance
+                .bind_mut()
+                .set_matchmaking(String::from(DEFAULT_MATCHMAKING_MODE), None);
         }
 
         Engine::singleton().register_singleton("NetworkClient", &instance);
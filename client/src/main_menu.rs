@@ -1,6 +1,16 @@
-use godot::{classes::{Engine, LineEdit}, prelude::*};
+use godot::{
+    classes::{CanvasItem, CheckBox, Engine, ItemList, Label, LineEdit, OptionButton, Timer},
+    prelude::*,
+};
 
-use crate::{entry::EntryNode, net::{NetworkClient, async_runtime::AsyncRuntime, client::NetworkAPI}};
+use crate::{
+    entry::EntryNode,
+    net::{NetworkClient, async_runtime::AsyncRuntime, client::NetworkAPI},
+};
+
+/// Query values behind the leaderboard pickers, in the order of their items.
+const LEADERBOARD_STATS: [&str; 4] = ["kills", "kd", "score", "rating"];
+const LEADERBOARD_WINDOWS: [&str; 3] = ["day", "week", "all"];
 
 #[derive(GodotClass)]
 #[class(base=Node2D)]
@@ -95,9 +105,33 @@ impl MainMenuNode {
     #[func]
     pub fn on_register_success(&mut self, user_id: u32) {}
 
+    /// The network API already picked the closest server with room, so the list is only shown.
     #[func]
     pub fn on_get_servers_success(&mut self, server_list: Array<GString>) {
+        for server in server_list.iter_shared() {
+            godot_print!("{server}");
+        }
+        self.enter_game();
+    }
+
+    fn enter_game(&mut self) {
+        let parent = match self.base().get_parent() {
+            Some(p) => p,
+            None => {
+                godot_error!("unable to find parent");
+                return;
+            }
+        };
+
+        let mut entry = match parent.try_cast::<EntryNode>() {
+            Err(_) => {
+                godot_error!("Unable to cast to Entry node");
+                return;
+            }
+            Ok(n) => n,
+        };
 
+        entry.bind_mut().navigate_to_game_scene();
     }
 
     #[func]
@@ -115,24 +149,220 @@ impl MainMenuNode {
         };
         client.bind_mut().set_controller_id(id);
 
-        let parent = match self.base().get_parent() {
-            Some(p) => p,
-            None => {
-                godot_error!("unable to find parent");
-                return;
-            }
-        };
+        // stats first, the player picks when to join
+        self.base()
+            .get_node_as::<CanvasItem>("CanvasLayer/WelcomeScreen")
+            .set_visible(false);
+        self.base()
+            .get_node_as::<CanvasItem>("CanvasLayer/Profile")
+            .set_visible(true);
+        network_api.bind().fetch_profile();
+        self.on_social_poll();
+        self.base()
+            .get_node_as::<Timer>("CanvasLayer/Profile/SocialPoll")
+            .start();
+    }
 
-        let mut entry = match parent.try_cast::<EntryNode>() {
-            Err(_) => {
-                godot_error!("Unable to cast to Entry node");
-                return;
+    /// Friends' presence and the party change on their own, so both are reloaded now and then.
+    #[func]
+    pub fn on_social_poll(&mut self) {
+        let network_api = self.base().get_node_as::<NetworkAPI>("NetworkAPI");
+
+        network_api.bind().get_friends();
+        network_api.bind().get_party();
+    }
+
+    #[func]
+    pub fn on_friends_loaded(&mut self, labels: Array<GString>, user_ids: Array<i64>) {
+        let mut list = self
+            .base()
+            .get_node_as::<ItemList>("CanvasLayer/Profile/Friends");
+        let selected = self.selected_friend();
+
+        list.clear();
+        for (label, user_id) in labels.iter_shared().zip(user_ids.iter_shared()) {
+            let index = list.add_item(&label);
+            list.set_item_metadata(index, &user_id.to_variant());
+            // keep the selection across polls
+            if selected == Some(user_id as u32) {
+                list.select(index);
             }
-            Ok(n) => n,
+        }
+    }
+
+    /// Account id of the friend picked in the list.
+    fn selected_friend(&self) -> Option<u32> {
+        let list = self
+            .base()
+            .get_node_as::<ItemList>("CanvasLayer/Profile/Friends");
+        let index = *list.get_selected_items().as_slice().first()?;
+        list.get_item_metadata(index)
+            .try_to::<i64>()
+            .ok()
+            .map(|id| id as u32)
+    }
+
+    #[func]
+    pub fn on_add_friend_click(&mut self) {
+        let mut name_node = self
+            .base()
+            .get_node_as::<LineEdit>("CanvasLayer/Profile/FriendName");
+        let username = name_node.get_text().to_string();
+        if username.is_empty() {
+            return;
+        }
+        name_node.clear();
+
+        self.base()
+            .get_node_as::<NetworkAPI>("NetworkAPI")
+            .bind()
+            .add_friend(&username);
+    }
+
+    #[func]
+    pub fn on_accept_friend_click(&mut self) {
+        if let Some(user_id) = self.selected_friend() {
+            self.base()
+                .get_node_as::<NetworkAPI>("NetworkAPI")
+                .bind()
+                .accept_friend(user_id);
+        }
+    }
+
+    #[func]
+    pub fn on_remove_friend_click(&mut self) {
+        if let Some(user_id) = self.selected_friend() {
+            self.base()
+                .get_node_as::<NetworkAPI>("NetworkAPI")
+                .bind()
+                .remove_friend(user_id);
+        }
+    }
+
+    #[func]
+    pub fn on_invite_click(&mut self) {
+        if let Some(user_id) = self.selected_friend() {
+            self.base()
+                .get_node_as::<NetworkAPI>("NetworkAPI")
+                .bind()
+                .invite_to_party(user_id);
+        }
+    }
+
+    #[func]
+    pub fn on_create_party_click(&mut self) {
+        self.base()
+            .get_node_as::<NetworkAPI>("NetworkAPI")
+            .bind()
+            .create_party();
+    }
+
+    #[func]
+    pub fn on_join_party_click(&mut self) {
+        self.base()
+            .get_node_as::<NetworkAPI>("NetworkAPI")
+            .bind()
+            .join_party();
+    }
+
+    #[func]
+    pub fn on_leave_party_click(&mut self) {
+        self.base()
+            .get_node_as::<NetworkAPI>("NetworkAPI")
+            .bind()
+            .leave_party();
+    }
+
+    #[func]
+    pub fn on_party_loaded(&mut self, summary: GString) {
+        self.base()
+            .get_node_as::<Label>("CanvasLayer/Profile/PartyStatus")
+            .set_text(&summary);
+    }
+
+    #[func]
+    pub fn on_social_notice(&mut self, message: GString) {
+        self.base()
+            .get_node_as::<Label>("CanvasLayer/Profile/SocialNotice")
+            .set_text(&message);
+    }
+
+    /// The leader found a match with a slot held for us, matchmaking hands it over.
+    #[func]
+    pub fn on_party_match_ready(&mut self) {
+        self.on_play_click();
+    }
+
+    #[func]
+    pub fn on_profile_loaded(&mut self, summary: GString) {
+        self.base()
+            .get_node_as::<Label>("CanvasLayer/Profile/Stats")
+            .set_text(&summary);
+    }
+
+    #[func]
+    pub fn on_leaderboard_click(&mut self) {
+        let picked = |path: &str| {
+            self.base()
+                .get_node_as::<OptionButton>(path)
+                .get_selected()
+                .max(0) as usize
         };
+        let stat = LEADERBOARD_STATS
+            [picked("CanvasLayer/Profile/StatPick").min(LEADERBOARD_STATS.len() - 1)];
+        let window = LEADERBOARD_WINDOWS
+            [picked("CanvasLayer/Profile/WindowPick").min(LEADERBOARD_WINDOWS.len() - 1)];
 
-        entry.bind_mut().navigate_to_game_scene();
+        self.base()
+            .get_node_as::<NetworkAPI>("NetworkAPI")
+            .bind()
+            .get_leaderboard(stat, window);
+    }
 
-        // network_api.bind().get_servers();
+    #[func]
+    pub fn on_leaderboard_loaded(&mut self, lines: Array<GString>) {
+        let text: Vec<String> = lines.iter_shared().map(|line| line.to_string()).collect();
+        self.base()
+            .get_node_as::<Label>("CanvasLayer/Profile/Leaderboard")
+            .set_text(&text.join("\n"));
+    }
+
+    #[func]
+    pub fn on_play_click(&mut self) {
+        let network_api = self.base().get_node_as::<NetworkAPI>("NetworkAPI");
+
+        let spectate = self
+            .base()
+            .get_node_as::<CheckBox>("CanvasLayer/WelcomeScreen/Spectate")
+            .is_pressed();
+        if let Some(mut client) = Engine::singleton()
+            .get_singleton("NetworkClient")
+            .and_then(|s| s.try_cast::<NetworkClient>().ok())
+        {
+            client.bind_mut().set_spectate(spectate);
+        }
+
+        // spectators don't take a slot, any listed server will do
+        if spectate {
+            network_api.bind().get_servers();
+        } else {
+            network_api.bind().matchmake();
+        }
+    }
+
+    #[func]
+    pub fn on_matchmake_done(&mut self, found: bool) {
+        self.base()
+            .get_node_as::<Timer>("CanvasLayer/Profile/SocialPoll")
+            .stop();
+        if found {
+            self.enter_game();
+        } else {
+            // no reservation, join the closest listed server with the access token
+            self.base()
+                .get_node_as::<NetworkAPI>("NetworkAPI")
+                .bind()
+                .get_servers();
+        }
     }
 }
@@ -2,27 +2,60 @@ pub mod async_runtime;
 pub mod client;
 pub mod packets;
 
-use common::game_world::GameWorld;
-use common::packet::InputAction;
+use common::handshake::{
+    self, CONNECT_PADDED_LEN, HandshakeRequest, HandshakeResponse, MAX_HANDSHAKE_LEN, RejectReason,
+};
+use common::packet::{ClientPacket, InputAction, PROTOCOL_VERSION, ServerPacket, WIRE_CONFIG};
+use common::secure::{Direction, KeyExchange, PacketOpener, PacketSealer, SessionKey};
 use godot::prelude::*;
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
 use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
 
 use crate::game_world::GameWorldWrapper;
 use crate::net::async_runtime::AsyncRuntime;
 
+/// Times a handshake datagram is sent before giving up, and times a login is retried.
+const HANDSHAKE_ATTEMPTS: usize = 3;
+const HANDSHAKE_RETRY: Duration = Duration::from_millis(500);
+/// The server never answers with more than it got, so logins leave room for the session key.
+const MIN_LOGIN_LEN: usize = 128;
+
+/// Refresh this long before the access token runs out.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// What the server let us in as.
+pub enum Joined {
+    Player(u32),
+    Spectator,
+}
+
 #[derive(GodotClass)]
 #[class(init, base=Object)]
 pub struct NetworkClient {
     base: Base<Object>,
     socket: Option<Arc<UdpSocket>>,
     game_server_address_udp: String,
-    game_server_address_tcp: String,
     controller_id: u32,
+    /// Token from the last login, sent in the handshake.
+    auth_token: String,
+    /// Ticket from matchmaking, used once instead of the access token.
+    join_ticket: Option<String>,
+    /// Game mode and preferred region asked for when matchmaking.
+    pub matchmaking_mode: String,
+    pub matchmaking_region: Option<String>,
+    /// Trades for a new access token, taken while a refresh is in flight.
+    refresh_token: Option<String>,
+    refresh_at: Option<Instant>,
+    /// Ask to watch instead of play on the next handshake.
+    spectate: bool,
+    /// Seals inputs for the session the last handshake set up.
+    sealer: Option<Arc<Mutex<PacketSealer>>>,
+    /// Handed to the listening task once it starts.
+    opener: Option<PacketOpener>,
     pub auth_server_address: String,
-    pub snapshot_rx: Option<UnboundedReceiver<GameWorld>>,
+    pub snapshot_rx: Option<UnboundedReceiver<ServerPacket>>,
 }
 
 #[godot_api]
@@ -31,25 +64,32 @@ impl NetworkClient {
     pub fn new_snapshot(world: Gd<GameWorldWrapper>);
 
     // #[func]
-    pub fn start_listening(&mut self) -> Option<UnboundedReceiver<GameWorld>> {
+    pub fn start_listening(&mut self) -> Option<UnboundedReceiver<ServerPacket>> {
         let Some(socket) = &self.socket else {
             godot_error!("Not listening");
             return None;
         };
+        let Some(mut opener) = self.opener.take() else {
+            godot_error!("No session, handshake first");
+            return None;
+        };
 
         let listen_sock = socket.clone();
         let (tx, rx) = unbounded_channel();
 
         AsyncRuntime::spawn(async move {
             let mut buf = [0u8; 4096];
-            let config = bincode::config::standard();
             loop {
                 match listen_sock.recv(&mut buf).await {
                     Ok(len) => {
-                        if let Ok((world, _)) =
-                            bincode::decode_from_slice::<GameWorld, _>(&buf[..len], config)
+                        // anything not sealed with our session key is dropped
+                        let Ok(payload) = opener.open(&buf[..len]) else {
+                            continue;
+                        };
+                        if let Ok((packet, _)) =
+                            bincode::decode_from_slice::<ServerPacket, _>(&payload, WIRE_CONFIG)
                         {
-                            if tx.send(world).is_err() {
+                            if tx.send(packet).is_err() {
                                 break;
                             }
                         }
@@ -65,40 +105,36 @@ impl NetworkClient {
         Some(rx)
     }
 
-    // #[func]
-    pub fn connect_to_server(&mut self) {
-        let addr = &self.game_server_address_udp;
-        let sock_future = async move {
-            match UdpSocket::bind("0.0.0.0:0").await {
-                Ok(sock) => {
-                    if let Err(e) = sock.connect(addr).await {
-                        godot_error!("Failed to connect to server: {}", e);
-                        return None;
-                    }
-                    Some(Arc::new(sock))
-                }
-                Err(_) => {
-                    godot_error!("Oopsie");
-                    return None;
-                }
-            }
-        };
-        let socket = AsyncRuntime::block_on(sock_future);
-        self.socket = socket;
-    }
-
     #[func]
     pub fn send_input(&self, id: u32, seq: u32, action_code: u32) {
-        let socket = self.socket.clone();
         let input = common::packet::PlayerInput {
             id,
             seq,
             action: InputAction::get_action_from_code(action_code),
         };
+        self.send_packet(&ClientPacket::Input(input));
+    }
+
+    /// Tells the server we're still here, sessions that go quiet are ended.
+    pub fn keep_alive(&self) {
+        self.send_packet(&ClientPacket::KeepAlive);
+    }
+
+    /// Ends the session, so the server doesn't wait for it to time out.
+    pub fn leave(&mut self) {
+        self.send_packet(&ClientPacket::Leave);
+        self.sealer = None;
+    }
+
+    fn send_packet(&self, packet: &ClientPacket) {
+        let (Some(socket), Some(sealer)) = (self.socket.clone(), self.sealer.clone()) else {
+            return;
+        };
 
-        let input_bytes = bincode::encode_to_vec(input, bincode::config::standard()).unwrap();
+        let bytes = bincode::encode_to_vec(packet, WIRE_CONFIG).unwrap();
+        let datagram = sealer.lock().unwrap().seal(&bytes);
         AsyncRuntime::spawn(async move {
-            let _ = socket.unwrap().send(&input_bytes).await;
+            let _ = socket.send(&datagram).await;
         });
     }
 
@@ -110,27 +146,158 @@ impl NetworkClient {
         self.controller_id
     }
 
-    pub fn set_config(&mut self, game_server_address_udp: String, game_server_address_tcp: String, auth_server_address: String) {
+    pub fn auth_token(&self) -> &str {
+        &self.auth_token
+    }
+
+    pub fn set_tokens(&mut self, token: String, refresh_token: String, expires_in: Duration) {
+        self.auth_token = token;
+        self.refresh_token = Some(refresh_token);
+        let lead = expires_in
+            .saturating_sub(TOKEN_REFRESH_MARGIN)
+            .max(expires_in / 2);
+        self.refresh_at = Some(Instant::now() + lead);
+    }
+
+    /// The refresh token once it is time to use it. Refresh tokens are single use,
+    /// so it is handed out once and comes back with the next [`Self::set_tokens`].
+    pub fn take_due_refresh(&mut self) -> Option<String> {
+        if self.refresh_at? > Instant::now() {
+            return None;
+        }
+        self.refresh_at = None;
+        self.refresh_token.take()
+    }
+
+    pub fn set_spectate(&mut self, spectate: bool) {
+        self.spectate = spectate;
+    }
+
+    /// Points the next handshake at another game server.
+    pub fn set_game_server_address(&mut self, game_server_address_udp: String) {
+        self.game_server_address_udp = game_server_address_udp;
+    }
+
+    /// Joins the next game server with a matchmaking ticket instead of the access token.
+    pub fn set_join_ticket(&mut self, ticket: String) {
+        self.join_ticket = Some(ticket);
+    }
+
+    pub fn set_matchmaking(&mut self, mode: String, region: Option<String>) {
+        self.matchmaking_mode = mode;
+        self.matchmaking_region = region;
+    }
+
+    pub fn set_config(&mut self, game_server_address_udp: String, auth_server_address: String) {
         self.auth_server_address = auth_server_address;
         self.game_server_address_udp = game_server_address_udp;
-        self.game_server_address_tcp = game_server_address_tcp;
     }
 
-    pub async fn send_handshake(&mut self) -> Result<u32, std::io::Error> {
-        let auth_address = &self.game_server_address_tcp;
-        let mut stream = TcpStream::connect(auth_address).await?;
+    /// Runs the UDP handshake and keeps the socket it ran on for the session.
+    pub async fn send_handshake(&mut self) -> Result<Joined, std::io::Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(&self.game_server_address_udp).await?;
+        self.socket = Some(Arc::new(socket));
+        let token = self
+            .join_ticket
+            .take()
+            .unwrap_or_else(|| self.auth_token.clone());
 
-        let id = self.controller_id;
+        // a stale cookie only costs one more round trip
+        for _ in 0..HANDSHAKE_ATTEMPTS {
+            let connect = HandshakeRequest::Connect {
+                protocol_version: PROTOCOL_VERSION,
+            };
+            let cookie = match self.exchange(&connect, CONNECT_PADDED_LEN).await? {
+                HandshakeResponse::Challenge(cookie) => cookie,
+                HandshakeResponse::Rejected(reason) => {
+                    return Err(std::io::Error::other(format!("server refused: {reason}")));
+                }
+                _ => return Err(std::io::Error::other("unexpected handshake response")),
+            };
 
-        let request = self.controller_id.to_be_bytes();
-        stream.write_all(&request).await?;
+            let exchange = KeyExchange::new();
+            let login = HandshakeRequest::Login {
+                protocol_version: PROTOCOL_VERSION,
+                cookie,
+                token: token.clone(),
+                spectate: self.spectate,
+                public_key: exchange.public_key(),
+            };
+            let derive = |public_key| {
+                exchange
+                    .session_key(public_key, &cookie.mac)
+                    .ok_or_else(|| std::io::Error::other("server sent an invalid key"))
+            };
+            match self.exchange(&login, MIN_LOGIN_LEN).await? {
+                HandshakeResponse::Accepted {
+                    player_id,
+                    session_id,
+                    public_key,
+                    encrypted,
+                } => {
+                    self.start_session(&derive(public_key)?, session_id, encrypted);
+                    return Ok(Joined::Player(player_id));
+                }
+                HandshakeResponse::Spectating {
+                    session_id,
+                    public_key,
+                    encrypted,
+                } => {
+                    self.start_session(&derive(public_key)?, session_id, encrypted);
+                    return Ok(Joined::Spectator);
+                }
+                HandshakeResponse::Rejected(RejectReason::InvalidCookie) => continue,
+                HandshakeResponse::Rejected(reason) => {
+                    return Err(std::io::Error::other(format!("server refused: {reason}")));
+                }
+                HandshakeResponse::Challenge(_) => {
+                    return Err(std::io::Error::other("unexpected handshake response"));
+                }
+            }
+        }
+        Err(std::io::Error::other("handshake kept expiring"))
+    }
 
-        let mut buffer = [0u8; 4];
-        stream.read_exact(&mut buffer).await?;
+    /// Sends a handshake message and waits for the answer, resending a few times
+    /// since either datagram can get lost.
+    async fn exchange(
+        &self,
+        request: &HandshakeRequest,
+        min_len: usize,
+    ) -> Result<HandshakeResponse, std::io::Error> {
+        let Some(socket) = &self.socket else {
+            return Err(std::io::Error::other("not connected"));
+        };
+        let datagram =
+            handshake::encode_datagram(request, min_len).map_err(std::io::Error::other)?;
 
-        let player_id = u32::from_be_bytes(buffer);
-        godot_print!("{player_id}");
-        Ok(player_id)
+        let mut buf = [0u8; MAX_HANDSHAKE_LEN];
+        for _ in 0..HANDSHAKE_ATTEMPTS {
+            socket.send(&datagram).await?;
+            let deadline = tokio::time::Instant::now() + HANDSHAKE_RETRY;
+            while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await
+            {
+                let len = received?;
+                if let Some(response) = handshake::decode_datagram(&buf[..len]) {
+                    return Ok(response);
+                }
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "game server did not answer",
+        ))
     }
 
+    fn start_session(&mut self, key: &SessionKey, session_id: u32, encrypted: bool) {
+        let sealer = PacketSealer::new(key, session_id, Direction::ClientToServer, encrypted);
+        self.sealer = Some(Arc::new(Mutex::new(sealer)));
+        self.opener = Some(PacketOpener::new(
+            key,
+            session_id,
+            Direction::ServerToClient,
+            encrypted,
+        ));
+    }
 }
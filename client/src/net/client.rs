@@ -1,8 +1,21 @@
+use common::handshake::{
+    self, CONNECT_PADDED_LEN, HandshakeRequest, HandshakeResponse, MAX_HANDSHAKE_LEN,
+};
+use common::leaderboard::{LeaderboardEntry, LeaderboardResponse};
+use common::packet::PROTOCOL_VERSION;
+use common::profile::Profile;
+use common::registry::{MatchmakeRequest, MatchmakeResponse, ServerListEntry, ServerListResponse};
+use common::social::{
+    Friend, FriendRequest, FriendState, FriendsResponse, PartyInviteRequest, PartyJoinRequest,
+    PartyResponse,
+};
 use core::panic;
 use godot::{classes::Engine, prelude::*};
-use reqwest::Client;
+use reqwest::{Client, Method};
 use serde::{Deserialize, Serialize};
 use std::thread;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 
 use crate::net::NetworkClient;
@@ -16,19 +29,72 @@ struct AuthRequest {
 #[derive(Deserialize, Clone)]
 struct AuthResponse {
     id: u32,
+    token: String,
+    expires_in: u64,
+    refresh_token: String,
 }
 
-#[derive(Deserialize, Clone)]
-struct GetServersResponse {
-    servers: Vec<String>,
+#[derive(Serialize)]
+struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// Servers slower than this to answer are treated as unreachable.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A listed game server and how long it took to answer a handshake `Connect`.
+#[derive(Clone)]
+struct ListedServer {
+    entry: ServerListEntry,
+    ping: Option<Duration>,
+}
+
+impl ListedServer {
+    fn label(&self) -> String {
+        let ping = match self.ping {
+            Some(ping) => format!("{} ms", ping.as_millis()),
+            None => "-".into(),
+        };
+        format!(
+            "{} [{}] {} {}/{} {}",
+            self.entry.name,
+            self.entry.region,
+            self.entry.mode,
+            self.entry.players,
+            self.entry.capacity,
+            ping
+        )
+    }
+
+    fn joinable(&self) -> bool {
+        self.entry.accepting_players && self.entry.players < self.entry.capacity
+    }
 }
 
 #[derive(Clone)]
 enum RequestResult {
     LoginOk(AuthResponse),
     RegisterOk(AuthResponse),
+    RefreshOk(AuthResponse),
+    RefreshFailed(String),
     Error(String),
-    GetServersOk(GetServersResponse),
+    GetServersOk(Vec<ListedServer>),
+    GetServersFailed(String),
+    MatchmakeOk(MatchmakeResponse),
+    MatchmakeFailed(String),
+    ProfileOk(Profile),
+    ProfileFailed(String),
+    LeaderboardOk {
+        board: LeaderboardResponse,
+        /// The player's own entry, unset when unranked.
+        me: Option<LeaderboardEntry>,
+    },
+    LeaderboardFailed(String),
+    FriendsOk(FriendsResponse),
+    PartyOk(PartyResponse),
+    /// A friend or party change went through, both lists are loaded again.
+    SocialChanged,
+    SocialFailed(String),
 }
 
 #[derive(GodotClass)]
@@ -39,6 +105,10 @@ pub struct NetworkAPI {
     rx: Option<mpsc::Receiver<RequestResult>>,
     last_message: Option<RequestResult>,
     server_address: String,
+    /// The first party invite waiting for an answer.
+    party_invite: Option<String>,
+    /// Server of the last party match this player followed the leader into.
+    followed_match: Option<String>,
 }
 
 #[godot_api]
@@ -59,28 +129,155 @@ impl INode for NetworkAPI {
             rx: Some(rx),
             last_message: None,
             server_address,
+            party_invite: None,
+            followed_match: None,
         }
     }
 
     fn ready(&mut self) {}
 
     fn process(&mut self, _delta: f64) {
+        if let Some(refresh_token) =
+            network_client().and_then(|mut client| client.bind_mut().take_due_refresh())
+        {
+            self.refresh(refresh_token);
+        }
+
         // poll messages (non-blocking)
         if let Some(rx) = &mut self.rx {
             match rx.try_recv() {
                 Ok(msg) => {
                     match &msg {
                         RequestResult::LoginOk(r) => {
+                            self.store_tokens(r);
                             self.base_mut()
                                 .emit_signal("login_response_arrived", &[Variant::from(r.id)]);
                             godot_print!("Login success: {}", r.id)
                         }
                         RequestResult::RegisterOk(r) => {
+                            self.store_tokens(r);
                             godot_print!("Register success: {}", r.id)
                         }
-                        RequestResult::GetServersOk(r) => {
-                            let servers = r.servers.clone();
-                            let arr = Array::from_iter(servers.iter().map(|s| GString::from(s)));
+                        RequestResult::RefreshOk(r) => self.store_tokens(r),
+                        RequestResult::RefreshFailed(e) => {
+                            // the next handshake fails with an expired token and sends the player back to login
+                            godot_print!("Token refresh failed: {}", e)
+                        }
+                        RequestResult::GetServersOk(servers) => {
+                            // join the closest server that has room, the configured one otherwise
+                            let best = servers
+                                .iter()
+                                .filter(|server| server.joinable())
+                                .filter_map(|server| Some((server.ping?, server)))
+                                .min_by_key(|(ping, _)| *ping);
+                            if let Some((_, best)) = best
+                                && let Some(mut client) = network_client()
+                            {
+                                client
+                                    .bind_mut()
+                                    .set_game_server_address(best.entry.address.clone());
+                            }
+
+                            let arr = Array::from_iter(
+                                servers.iter().map(|server| GString::from(&server.label())),
+                            );
+                            self.base_mut()
+                                .emit_signal("get_servers_response_arrived", &[arr.to_variant()]);
+                        }
+                        RequestResult::MatchmakeOk(r) => {
+                            if let Some(mut client) = network_client() {
+                                let mut client = client.bind_mut();
+                                client.set_game_server_address(r.address.clone());
+                                client.set_join_ticket(r.ticket.clone());
+                            }
+                            godot_print!("Matched to {} ({})", r.name, r.region);
+                            self.base_mut()
+                                .emit_signal("matchmake_response_arrived", &[Variant::from(true)]);
+                        }
+                        RequestResult::MatchmakeFailed(e) => {
+                            godot_print!("Matchmaking failed: {}", e);
+                            self.base_mut()
+                                .emit_signal("matchmake_response_arrived", &[Variant::from(false)]);
+                        }
+                        RequestResult::ProfileOk(profile) => {
+                            let summary = GString::from(&profile_summary(profile));
+                            self.base_mut()
+                                .emit_signal("profile_response_arrived", &[summary.to_variant()]);
+                        }
+                        RequestResult::ProfileFailed(e) => {
+                            godot_print!("Profile unavailable: {}", e);
+                            let summary = GString::from("Stats unavailable");
+                            self.base_mut()
+                                .emit_signal("profile_response_arrived", &[summary.to_variant()]);
+                        }
+                        RequestResult::LeaderboardOk { board, me } => {
+                            let mut lines: Vec<String> =
+                                board.entries.iter().map(entry_line).collect();
+                            if lines.is_empty() {
+                                lines.push("Nobody ranked yet".into());
+                            }
+                            lines.push(match me {
+                                Some(me) => format!("You: {}", entry_line(&me)),
+                                None => "You: not ranked".into(),
+                            });
+                            let arr = Array::from_iter(lines.iter().map(GString::from));
+                            self.base_mut()
+                                .emit_signal("leaderboard_response_arrived", &[arr.to_variant()]);
+                        }
+                        RequestResult::LeaderboardFailed(e) => {
+                            godot_print!("Leaderboard unavailable: {}", e);
+                            let arr = Array::from_iter([GString::from("Leaderboard unavailable")]);
+                            self.base_mut()
+                                .emit_signal("leaderboard_response_arrived", &[arr.to_variant()]);
+                        }
+                        RequestResult::FriendsOk(response) => {
+                            let labels = Array::from_iter(
+                                response
+                                    .friends
+                                    .iter()
+                                    .map(|friend| GString::from(&friend_line(friend))),
+                            );
+                            let ids = Array::from_iter(
+                                response.friends.iter().map(|friend| friend.user_id as i64),
+                            );
+                            self.base_mut().emit_signal(
+                                "friends_response_arrived",
+                                &[labels.to_variant(), ids.to_variant()],
+                            );
+                        }
+                        RequestResult::PartyOk(response) => {
+                            let own_id =
+                                network_client().map(|client| client.bind().controller_id());
+                            self.party_invite = response
+                                .invites
+                                .first()
+                                .map(|invite| invite.party_id.clone());
+                            let summary = party_summary(response);
+                            self.base_mut()
+                                .emit_signal("party_response_arrived", &[summary.to_variant()]);
+
+                            // members follow the leader into the match it found
+                            if let Some(party) = &response.party
+                                && let Some(current_match) = &party.current_match
+                                && Some(party.leader_id) != own_id
+                                && self.followed_match.as_ref() != Some(&current_match.server_id)
+                            {
+                                self.followed_match = Some(current_match.server_id.clone());
+                                self.base_mut().emit_signal("party_match_ready", &[]);
+                            }
+                        }
+                        RequestResult::SocialChanged => {
+                            self.get_friends();
+                            self.get_party();
+                        }
+                        RequestResult::SocialFailed(e) => {
+                            let message = GString::from(e);
+                            self.base_mut()
+                                .emit_signal("social_notice_arrived", &[message.to_variant()]);
+                        }
+                        RequestResult::GetServersFailed(e) => {
+                            godot_print!("Server list unavailable: {}", e);
+                            let arr = Array::<GString>::new();
                             self.base_mut()
                                 .emit_signal("get_servers_response_arrived", &[arr.to_variant()]);
                         }
@@ -105,6 +302,30 @@ impl NetworkAPI {
     #[signal]
     pub fn get_servers_response_arrived(data: Array<GString>);
 
+    #[signal]
+    pub fn matchmake_response_arrived(found: bool);
+
+    #[signal]
+    pub fn profile_response_arrived(summary: GString);
+
+    #[signal]
+    pub fn leaderboard_response_arrived(lines: Array<GString>);
+
+    /// One line per friend or open request, with the account ids in the same order.
+    #[signal]
+    pub fn friends_response_arrived(labels: Array<GString>, user_ids: Array<i64>);
+
+    #[signal]
+    pub fn party_response_arrived(summary: GString);
+
+    /// The party leader matchmade, this member should matchmake to take the held slot.
+    #[signal]
+    pub fn party_match_ready();
+
+    /// Why a friend or party request was refused.
+    #[signal]
+    pub fn social_notice_arrived(message: GString);
+
     #[func]
     pub fn login(&self, username: GString, password: GString) {
         let tx = self.tx.clone();
@@ -155,7 +376,8 @@ impl NetworkAPI {
                             Err(_) => RequestResult::Error("Invalid JSON".into()),
                         }
                     }
-                    Ok(resp) => RequestResult::Error(format!("HTTP {}", resp.status())),
+                    // tells which username or password rule was broken
+                    Ok(resp) => RequestResult::Error(error_message(resp).await),
                     Err(err) => RequestResult::Error(err.to_string()),
                 };
 
@@ -164,9 +386,289 @@ impl NetworkAPI {
         });
     }
 
+    /// Trades the refresh token for a new pair, the old one can't be used again.
+    fn refresh(&self, refresh_token: String) {
+        let tx = self.tx.clone();
+        let server_address = format!("http://{}/token/refresh", self.server_address.clone());
+
+        thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move {
+                let client = Client::new();
+                let payload = RefreshRequest { refresh_token };
+
+                let result = match client.post(server_address).json(&payload).send().await {
+                    Ok(resp) if resp.status().is_success() => {
+                        match resp.json::<AuthResponse>().await {
+                            Ok(r) => RequestResult::RefreshOk(r),
+                            Err(_) => RequestResult::RefreshFailed("Invalid JSON".into()),
+                        }
+                    }
+                    Ok(resp) => RequestResult::RefreshFailed(format!("HTTP {}", resp.status())),
+                    Err(err) => RequestResult::RefreshFailed(err.to_string()),
+                };
+
+                let _ = tx.send(result).await;
+            });
+        });
+    }
+
+    /// Keeps the tokens on the game client, which outlives this node.
+    fn store_tokens(&self, response: &AuthResponse) {
+        if let Some(mut client) = network_client() {
+            client.bind_mut().set_tokens(
+                response.token.clone(),
+                response.refresh_token.clone(),
+                Duration::from_secs(response.expires_in),
+            );
+        }
+    }
+
+    /// Asks the auth service for a server with room, the ticket it returns is used by the next handshake.
+    pub fn matchmake(&self) {
+        let Some(client) = network_client() else {
+            return;
+        };
+        let (token, request) = {
+            let client = client.bind();
+            let request = MatchmakeRequest {
+                mode: client.matchmaking_mode.clone(),
+                region: client.matchmaking_region.clone(),
+            };
+            (client.auth_token().to_string(), request)
+        };
+        let tx = self.tx.clone();
+        let server_address = format!("http://{}/matchmake", self.server_address.clone());
+
+        thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move {
+                let client = Client::new();
+
+                let result = match client
+                    .post(server_address)
+                    .bearer_auth(token)
+                    .json(&request)
+                    .send()
+                    .await
+                {
+                    Ok(resp) if resp.status().is_success() => {
+                        match resp.json::<MatchmakeResponse>().await {
+                            Ok(r) => RequestResult::MatchmakeOk(r),
+                            Err(_) => RequestResult::MatchmakeFailed("Invalid JSON".into()),
+                        }
+                    }
+                    Ok(resp) => RequestResult::MatchmakeFailed(format!("HTTP {}", resp.status())),
+                    Err(err) => RequestResult::MatchmakeFailed(err.to_string()),
+                };
+
+                let _ = tx.send(result).await;
+            });
+        });
+    }
+
+    /// Loads the logged in player's profile and lifetime stats.
+    pub fn fetch_profile(&self) {
+        let Some(client) = network_client() else {
+            return;
+        };
+        let token = client.bind().auth_token().to_string();
+        let tx = self.tx.clone();
+        let server_address = format!("http://{}/me", self.server_address.clone());
+
+        thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move {
+                let client = Client::new();
+
+                let result = match client.get(server_address).bearer_auth(token).send().await {
+                    Ok(resp) if resp.status().is_success() => match resp.json::<Profile>().await {
+                        Ok(r) => RequestResult::ProfileOk(r),
+                        Err(_) => RequestResult::ProfileFailed("Invalid JSON".into()),
+                    },
+                    Ok(resp) => RequestResult::ProfileFailed(format!("HTTP {}", resp.status())),
+                    Err(err) => RequestResult::ProfileFailed(err.to_string()),
+                };
+
+                let _ = tx.send(result).await;
+            });
+        });
+    }
+
+    /// Loads the top of a leaderboard together with the player's own rank on it.
+    /// `stat` and `window` are passed as is, the auth service lists the valid ones.
+    pub fn get_leaderboard(&self, stat: &str, window: &str) {
+        let Some(client) = network_client() else {
+            return;
+        };
+        let token = client.bind().auth_token().to_string();
+        let tx = self.tx.clone();
+        let query = format!("stat={stat}&window={window}");
+        let board_address = format!("http://{}/leaderboard?{query}", self.server_address);
+        let rank_address = format!("http://{}/leaderboard/me?{query}", self.server_address);
+
+        thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move {
+                let client = Client::new();
+
+                let result = match client.get(board_address).send().await {
+                    Ok(resp) if resp.status().is_success() => {
+                        match resp.json::<LeaderboardResponse>().await {
+                            Ok(board) => {
+                                // unranked players get a 404, nothing to show for them
+                                let me = match client
+                                    .get(rank_address)
+                                    .bearer_auth(token)
+                                    .send()
+                                    .await
+                                {
+                                    Ok(resp) if resp.status().is_success() => {
+                                        resp.json::<LeaderboardEntry>().await.ok()
+                                    }
+                                    _ => None,
+                                };
+                                RequestResult::LeaderboardOk { board, me }
+                            }
+                            Err(_) => RequestResult::LeaderboardFailed("Invalid JSON".into()),
+                        }
+                    }
+                    Ok(resp) => RequestResult::LeaderboardFailed(format!("HTTP {}", resp.status())),
+                    Err(err) => RequestResult::LeaderboardFailed(err.to_string()),
+                };
+
+                let _ = tx.send(result).await;
+            });
+        });
+    }
+
+    pub fn get_friends(&self) {
+        self.social_get("/friends", |body| {
+            serde_json::from_slice(&body).map(RequestResult::FriendsOk)
+        });
+    }
+
+    pub fn get_party(&self) {
+        self.social_get("/party", |body| {
+            serde_json::from_slice(&body).map(RequestResult::PartyOk)
+        });
+    }
+
+    /// Sends a friend request, or accepts theirs if they asked first.
+    pub fn add_friend(&self, username: &str) {
+        let request = FriendRequest {
+            username: username.to_string(),
+        };
+        self.social_change(Method::POST, "/friends".into(), Some(request));
+    }
+
+    pub fn accept_friend(&self, user_id: u32) {
+        self.social_change(
+            Method::POST,
+            format!("/friends/{user_id}/accept"),
+            None::<()>,
+        );
+    }
+
+    /// Unfriends, or declines or withdraws a request.
+    pub fn remove_friend(&self, user_id: u32) {
+        self.social_change(Method::DELETE, format!("/friends/{user_id}"), None::<()>);
+    }
+
+    pub fn create_party(&self) {
+        self.social_change(Method::POST, "/party".into(), None::<()>);
+    }
+
+    pub fn invite_to_party(&self, user_id: u32) {
+        let request = PartyInviteRequest { user_id };
+        self.social_change(Method::POST, "/party/invite".into(), Some(request));
+    }
+
+    /// Joins the party of the first invite that came in, if there is one.
+    pub fn join_party(&self) {
+        let Some(party_id) = self.party_invite.clone() else {
+            return;
+        };
+        self.social_change(
+            Method::POST,
+            "/party/join".into(),
+            Some(PartyJoinRequest { party_id }),
+        );
+    }
+
+    pub fn leave_party(&self) {
+        self.social_change(Method::POST, "/party/leave".into(), None::<()>);
+    }
+
+    /// Loads one of the friends and party lists, `parse` turns the body into its result.
+    fn social_get(
+        &self,
+        path: &str,
+        parse: impl FnOnce(Vec<u8>) -> serde_json::Result<RequestResult> + Send + 'static,
+    ) {
+        let Some(client) = network_client() else {
+            return;
+        };
+        let token = client.bind().auth_token().to_string();
+        let tx = self.tx.clone();
+        let address = format!("http://{}{path}", self.server_address);
+
+        thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move {
+                let client = Client::new();
+
+                let result = match client.get(address).bearer_auth(token).send().await {
+                    Ok(resp) if resp.status().is_success() => match resp.bytes().await {
+                        Ok(body) => parse(body.to_vec())
+                            .unwrap_or_else(|_| RequestResult::SocialFailed("Invalid JSON".into())),
+                        Err(err) => RequestResult::SocialFailed(err.to_string()),
+                    },
+                    Ok(resp) => RequestResult::SocialFailed(error_message(resp).await),
+                    Err(err) => RequestResult::SocialFailed(err.to_string()),
+                };
+
+                let _ = tx.send(result).await;
+            });
+        });
+    }
+
+    /// Changes a friendship or the party, both lists are loaded again once it went through.
+    fn social_change<T: Serialize + Send + 'static>(
+        &self,
+        method: Method,
+        path: String,
+        body: Option<T>,
+    ) {
+        let Some(client) = network_client() else {
+            return;
+        };
+        let token = client.bind().auth_token().to_string();
+        let tx = self.tx.clone();
+        let address = format!("http://{}{path}", self.server_address);
+
+        thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move {
+                let mut request = Client::new().request(method, address).bearer_auth(token);
+                if let Some(body) = &body {
+                    request = request.json(body);
+                }
+
+                let result = match request.send().await {
+                    Ok(resp) if resp.status().is_success() => RequestResult::SocialChanged,
+                    Ok(resp) => RequestResult::SocialFailed(error_message(resp).await),
+                    Err(err) => RequestResult::SocialFailed(err.to_string()),
+                };
+
+                let _ = tx.send(result).await;
+            });
+        });
+    }
+
     pub fn get_servers(&self) {
         let tx = self.tx.clone();
-        let server_address = format!("http://{}/server", self.server_address.clone());
+        let server_address = format!("http://{}/servers", self.server_address.clone());
 
         thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
@@ -175,13 +677,13 @@ impl NetworkAPI {
 
                 let result = match client.get(server_address).send().await {
                     Ok(resp) if resp.status().is_success() => {
-                        match resp.json::<GetServersResponse>().await {
-                            Ok(r) => RequestResult::GetServersOk(r),
-                            Err(_) => RequestResult::Error("Invalid JSON".into()),
+                        match resp.json::<ServerListResponse>().await {
+                            Ok(r) => RequestResult::GetServersOk(ping_servers(r.servers).await),
+                            Err(_) => RequestResult::GetServersFailed("Invalid JSON".into()),
                         }
                     }
-                    Ok(resp) => RequestResult::Error(format!("HTTP {}", resp.status())),
-                    Err(err) => RequestResult::Error(err.to_string()),
+                    Ok(resp) => RequestResult::GetServersFailed(format!("HTTP {}", resp.status())),
+                    Err(err) => RequestResult::GetServersFailed(err.to_string()),
                 };
 
                 let _ = tx.send(result).await;
@@ -189,3 +691,126 @@ impl NetworkAPI {
         });
     }
 }
+
+/// Lines shown on the main menu's profile panel.
+fn profile_summary(profile: &Profile) -> String {
+    let stats = &profile.stats;
+    let kd = stats.kills as f64 / stats.deaths.max(1) as f64;
+    let minutes = stats.seconds_played / 60;
+    format!(
+        "{}\nKills {}  Deaths {}  K/D {:.2}\nAsteroids destroyed {}\nMatches {}  Won {}\nPlayed {}h {}m",
+        profile.display_name,
+        stats.kills,
+        stats.deaths,
+        kd,
+        stats.asteroids_destroyed,
+        stats.matches_played,
+        stats.matches_won,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+fn friend_line(friend: &Friend) -> String {
+    let status = match (friend.state, &friend.presence) {
+        (FriendState::Friends, Some(presence)) => format!("playing on {}", presence.server_name),
+        (FriendState::Friends, None) => "offline".into(),
+        (FriendState::Incoming, _) => "wants to be friends".into(),
+        (FriendState::Outgoing, _) => "request sent".into(),
+    };
+    format!("{} - {}", friend.display_name, status)
+}
+
+/// What the party panel shows: the members, then invites waiting for an answer.
+fn party_summary(response: &PartyResponse) -> String {
+    let mut lines = Vec::new();
+    match &response.party {
+        Some(party) => {
+            for member in &party.members {
+                let leader = if member.user_id == party.leader_id {
+                    " (leader)"
+                } else {
+                    ""
+                };
+                lines.push(format!("{}{}", member.display_name, leader));
+            }
+            if !party.invited.is_empty() {
+                lines.push(format!("{} invited", party.invited.len()));
+            }
+            if let Some(current_match) = &party.current_match {
+                lines.push(format!("Match on {}", current_match.name));
+            }
+        }
+        None => lines.push("Not in a party".into()),
+    }
+    for invite in &response.invites {
+        lines.push(format!("{} invited you", invite.leader_name));
+    }
+    lines.join("\n")
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// The reason the auth service gave for refusing a request, or the status without one.
+async fn error_message(resp: reqwest::Response) -> String {
+    let status = resp.status();
+    match resp.json::<ErrorBody>().await {
+        Ok(body) => body.error,
+        Err(_) => format!("HTTP {status}"),
+    }
+}
+
+fn entry_line(entry: &LeaderboardEntry) -> String {
+    // counts are whole numbers, ratios get two decimals
+    let value = if entry.value.fract() == 0.0 {
+        format!("{}", entry.value)
+    } else {
+        format!("{:.2}", entry.value)
+    };
+    format!("#{} {} {}", entry.rank, entry.display_name, value)
+}
+
+/// Pings every server at once, so one that is down only costs [`PING_TIMEOUT`].
+async fn ping_servers(entries: Vec<ServerListEntry>) -> Vec<ListedServer> {
+    let pings: Vec<_> = entries
+        .iter()
+        .map(|entry| tokio::spawn(ping_server(entry.address.clone())))
+        .collect();
+
+    let mut servers = Vec::with_capacity(entries.len());
+    for (entry, ping) in entries.into_iter().zip(pings) {
+        let ping = ping.await.ok().flatten();
+        servers.push(ListedServer { entry, ping });
+    }
+    servers
+}
+
+/// Times a handshake `Connect`, which every game server answers right away.
+async fn ping_server(address: String) -> Option<Duration> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+    socket.connect(&address).await.ok()?;
+    let connect = HandshakeRequest::Connect {
+        protocol_version: PROTOCOL_VERSION,
+    };
+    let datagram = handshake::encode_datagram(&connect, CONNECT_PADDED_LEN).ok()?;
+
+    let started = Instant::now();
+    socket.send(&datagram).await.ok()?;
+    let mut buf = [0u8; MAX_HANDSHAKE_LEN];
+    let len = tokio::time::timeout(PING_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .ok()?
+        .ok()?;
+    handshake::decode_datagram::<HandshakeResponse>(&buf[..len])?;
+    Some(started.elapsed())
+}
+
+fn network_client() -> Option<Gd<NetworkClient>> {
+    Engine::singleton()
+        .get_singleton("NetworkClient")?
+        .try_cast::<NetworkClient>()
+        .ok()
+}
@@ -43,6 +43,11 @@ impl ICharacterBody2D for PlayerWrapper {
                 fire_rate_ms: 0,
                 last_shot_ms: 0,
                 last_processed_input_seq: 0,
+                is_bot: false,
+                kills: 0,
+                deaths: 0,
+                asteroids_destroyed: 0,
+                score: 0,
             },
             pending_inputs: Vec::new(),
             input_seq: 1,
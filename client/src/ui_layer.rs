@@ -10,6 +10,7 @@ use crate::player::PlayerWrapper;
 pub struct UiLayer {
     base: Base<CanvasLayer>,
     hp_label: Option<Gd<Label>>,
+    notice_label: Option<Gd<Label>>,
 }
 
 #[godot_api]
@@ -18,12 +19,14 @@ impl ICanvasLayer for UiLayer {
         Self {
             base,
             hp_label: None,
+            notice_label: None,
         }
     }
 
     fn ready(&mut self) {
         let label = self.base().get_node_as::<Label>("InfoPanel/HpLabel");
         self.hp_label = Some(label);
+        self.notice_label = self.base().try_get_node_as::<Label>("NoticeLabel");
     }
 }
 
@@ -40,6 +43,12 @@ impl UiLayer {
             });
     }
 
+    pub fn show_notice(&mut self, message: &str) {
+        if let Some(label) = &mut self.notice_label {
+            label.set_text(message);
+        }
+    }
+
     #[func]
     fn on_hp_changed(&mut self, new_hp: u16) {
         if let Some(label) = &mut self.hp_label {
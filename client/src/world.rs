@@ -1,18 +1,27 @@
 use std::collections::{HashMap, HashSet};
 
-use common::game_world::GameWorld;
-use godot::{classes::Engine, prelude::*};
+use common::{game_world::GameWorld, packet::ServerPacket};
+use godot::{
+    classes::{Engine, Input},
+    prelude::*,
+};
 use tokio::sync::mpsc::UnboundedReceiver;
 
 use crate::{
     asteroids::AsteroidWrapper,
     bullet::BulletNode,
+    camera::CameraNode,
     game_world::GameWorldWrapper,
-    net::{NetworkClient, async_runtime::AsyncRuntime},
+    net::{Joined, NetworkClient, async_runtime::AsyncRuntime},
     player::PlayerWrapper,
     ui_layer::UiLayer,
 };
 
+/// Pixels per second the free spectator camera moves.
+const SPECTATOR_PAN_SPEED: f32 = 600.0;
+/// Seconds between keep-alives, well inside the server's session timeout.
+const KEEP_ALIVE_INTERVAL: f64 = 2.0;
+
 #[derive(GodotClass)]
 #[class(base=Node2D)]
 pub struct World {
@@ -23,10 +32,16 @@ pub struct World {
     last_snapshot: GameWorld,
     network_client: Option<Gd<NetworkClient>>,
     player_id: Option<u32>,
-    snapshot_rx: Option<UnboundedReceiver<GameWorld>>,
+    snapshot_rx: Option<UnboundedReceiver<ServerPacket>>,
     player_scene: Gd<PackedScene>,
     asteroid_scene: Gd<PackedScene>,
     bullet_scene: Gd<PackedScene>,
+    /// Only set while spectating.
+    spectator_camera: Option<Gd<CameraNode>>,
+    /// Player the spectator camera sticks to, free camera when `None`.
+    followed: Option<u32>,
+    /// Seconds until the next keep-alive.
+    keep_alive_in: f64,
 }
 
 #[godot_api]
@@ -37,31 +52,48 @@ impl INode2D for World {
             players: HashMap::new(),
             bullets: HashMap::new(),
             asteroids: HashMap::new(),
-            last_snapshot: GameWorld {
-                players: HashMap::new(),
-                bullets: Vec::new(),
-                asteroids: Vec::new(),
-                width: 800.0,
-                height: 800.0,
-                bullet_id_counter: 0,
-                asteroid_id_counter: 0,
-                last_spawn_asteroid: 0,
-            },
+            last_snapshot: GameWorld::new(),
             snapshot_rx: None,
             network_client: None,
             player_id: None,
             player_scene: load("res://player.tscn"),
             bullet_scene: load("res://bullet.tscn"),
             asteroid_scene: load("res://asteroid.tscn"),
+            spectator_camera: None,
+            followed: None,
+            keep_alive_in: KEEP_ALIVE_INTERVAL,
         }
     }
 
     fn process(&mut self, delta: f64) {
+        self.keep_alive_in -= delta;
+        if self.keep_alive_in <= 0.0
+            && let Some(client) = &self.network_client
+        {
+            client.bind().keep_alive();
+            self.keep_alive_in = KEEP_ALIVE_INTERVAL;
+        }
+
         if let Some(rx) = &mut self.snapshot_rx {
             let mut last_world = None;
+            let mut notices = Vec::new();
+            let mut shutdown = None;
+
+            while let Ok(packet) = rx.try_recv() {
+                match packet {
+                    ServerPacket::Snapshot(world) => last_world = Some(world),
+                    ServerPacket::Notice(message) => notices.push(message),
+                    ServerPacket::Shutdown(reason) => shutdown = Some(reason),
+                }
+            }
+
+            for message in notices {
+                self.on_notice(message);
+            }
 
-            while let Ok(world) = rx.try_recv() {
-                last_world = Some(world);
+            if let Some(reason) = shutdown {
+                self.on_server_shutdown(reason);
+                return;
             }
 
             if let Some(world) = last_world {
@@ -70,6 +102,16 @@ impl INode2D for World {
                 self.on_snapshot_update(world_wrapped, delta);
             }
         }
+
+        if self.spectator_camera.is_some() {
+            self.update_spectator_camera(delta);
+        }
+    }
+
+    fn exit_tree(&mut self) {
+        if let Some(mut client) = self.network_client.take() {
+            client.bind_mut().leave();
+        }
     }
 
     fn ready(&mut self) {
@@ -83,8 +125,8 @@ impl INode2D for World {
 
         godot_print!("Creating player's client...");
         let response = AsyncRuntime::block_on(client.bind_mut().send_handshake());
-        match response {
-            Ok(id) => {
+        let joined = match response {
+            Ok(Joined::Player(id)) => {
                 self.player_id = Some(id);
 
                 // local player spawn
@@ -104,13 +146,20 @@ impl INode2D for World {
 
                 godot_print!("Player connected to NetworkClient node {id}");
                 godot_print!("Player connected to NetworkClient node {c}");
+                true
             }
-            Err(_) => {}
-        }
-        if let Some(player_id) = self.player_id {
-            client.bind_mut().connect_to_server();
+            Ok(Joined::Spectator) => {
+                self.start_spectating();
+                true
+            }
+            Err(e) => {
+                godot_error!("Handshake failed: {e}");
+                self.on_notice(format!("Could not join: {e}"));
+                false
+            }
+        };
+        if joined {
             self.snapshot_rx = client.bind_mut().start_listening();
-            client.bind().send_input(player_id, 0, 5);
             self.network_client = Some(client);
         }
     }
@@ -118,8 +167,104 @@ impl INode2D for World {
 
 #[godot_api]
 impl World {
+    pub fn on_notice(&mut self, message: String) {
+        godot_print!("Server notice: {message}");
+        if let Some(mut ui_node) = self.base().try_get_node_as::<UiLayer>("../UI") {
+            ui_node.bind_mut().show_notice(&message);
+        }
+    }
+
+    /// Watches without a ship: arrows move a free camera, Tab and Shift+Tab
+    /// cycle through players to follow, Escape lets go of the followed player.
+    pub fn start_spectating(&mut self) {
+        let camera = CameraNode::new_alloc();
+        self.base_mut().add_child(&camera.clone().upcast::<Node>());
+        self.spectator_camera = Some(camera);
+        self.on_notice("Spectating: Tab follows the next player, arrows look around".to_string());
+    }
+
+    fn update_spectator_camera(&mut self, delta: f64) {
+        let input = Input::singleton();
+        if input.is_action_just_pressed("ui_focus_next") {
+            self.follow_next(1);
+        } else if input.is_action_just_pressed("ui_focus_prev") {
+            self.follow_next(-1);
+        } else if input.is_action_just_pressed("ui_cancel") {
+            self.followed = None;
+        }
+
+        let mut pan = Vector2::ZERO;
+        if input.is_action_pressed("ui_left") {
+            pan.x -= 1.0;
+        }
+        if input.is_action_pressed("ui_right") {
+            pan.x += 1.0;
+        }
+        if input.is_action_pressed("ui_up") {
+            pan.y -= 1.0;
+        }
+        if input.is_action_pressed("ui_down") {
+            pan.y += 1.0;
+        }
+
+        // the followed player left the match
+        if self
+            .followed
+            .is_some_and(|id| !self.last_snapshot.players.contains_key(&id))
+        {
+            self.followed = None;
+        }
+
+        let Some(mut camera) = self.spectator_camera.clone() else {
+            return;
+        };
+        if pan != Vector2::ZERO {
+            self.followed = None;
+            let position = camera.get_position() + pan * SPECTATOR_PAN_SPEED * delta as f32;
+            camera.set_position(position);
+        } else if let Some(player) = self.followed.and_then(|id| self.players.get(&id)) {
+            camera.set_position(player.get_position());
+        }
+    }
+
+    /// Follows the player `step` places after the current one, ordered by id.
+    fn follow_next(&mut self, step: i64) {
+        let mut ids: Vec<u32> = self.last_snapshot.players.keys().cloned().collect();
+        if ids.is_empty() {
+            self.followed = None;
+            return;
+        }
+        ids.sort_unstable();
+
+        let next = match self
+            .followed
+            .and_then(|id| ids.iter().position(|p| *p == id))
+        {
+            Some(current) => (current as i64 + step).rem_euclid(ids.len() as i64) as usize,
+            None if step > 0 => 0,
+            None => ids.len() - 1,
+        };
+        self.followed = Some(ids[next]);
+        self.on_notice(format!("Following player {}", ids[next]));
+    }
+
+    /// Stops listening so the last state stays on screen, with the reason shown over it.
+    pub fn on_server_shutdown(&mut self, reason: String) {
+        godot_print!("Server shutting down: {reason}");
+        self.snapshot_rx = None;
+        self.network_client = None;
+        if let Some(mut ui_node) = self.base().try_get_node_as::<UiLayer>("../UI") {
+            ui_node
+                .bind_mut()
+                .show_notice(&format!("Disconnected: {reason}"));
+        }
+    }
+
     pub fn on_snapshot_update(&mut self, world_wrapper: Gd<GameWorldWrapper>, delta: f64) {
         let world = world_wrapper.bind().game_world.clone();
+        if let Some(world) = &world {
+            self.last_snapshot = world.clone();
+        }
 
         // Setup players
         for (id, player_data) in world.clone().unwrap().players {
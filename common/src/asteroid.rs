@@ -1,7 +1,8 @@
 use bincode::{Decode, Encode};
 use rand::Rng;
+use serde::Serialize;
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Serialize, Debug, Clone, PartialEq)]
 pub struct Asteroid {
     pub id: u32,
     pub x: f32,
@@ -20,9 +21,8 @@ impl Asteroid {
         self.distance_traveled += (self.vx).hypot(self.vy);
     }
 
-    pub fn new(id: u32) -> Self {
-        let mut rng = rand::rng();
-        let margin = 50.0;
+    pub fn new(id: u32, rng: &mut impl Rng) -> Self {
+        let margin: f32 = 50.0;
         let side = rng.random_range(0..=3);
         let spawn_pos = match side {
             0 => (rng.random_range(-800.0..800.0), 600.0 + margin), // top
@@ -32,16 +32,16 @@ impl Asteroid {
             _ => (0.0, 0.0),
         };
 
-        let players = vec![(0.0, 0.0), (100.0, 50.0), (-200.0, -100.0)];
+        let players: [(f32, f32); 3] = [(0.0, 0.0), (100.0, 50.0), (-200.0, -100.0)];
         let target_player = players[rng.random_range(0..players.len())];
 
         let dx = target_player.0 - spawn_pos.0;
         let dy = target_player.1 - spawn_pos.1;
-        let distance: f32 = ((dx * dx + dy * dy) as f32).sqrt();
+        let distance: f32 = (dx * dx + dy * dy).sqrt();
         let direction = (dx / distance, dy / distance);
 
         Self {
-            id: id,
+            id,
             x: spawn_pos.0,
             y: spawn_pos.1,
             vx: direction.0,
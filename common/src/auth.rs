@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// Claims of the tokens the auth service signs and game servers verify.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    /// User id.
+    pub sub: String,
+    pub username: String,
+    pub iat: u64,
+    pub exp: u64,
+    /// Only set on join tickets: the public address of the one game server that accepts it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// Only set on join tickets: the reservation the ticket redeems, usable once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+impl Claims {
+    pub fn player_id(&self) -> Option<u32> {
+        self.sub.parse().ok()
+    }
+}
@@ -1,6 +1,7 @@
 use bincode::{Decode, Encode};
+use serde::Serialize;
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Serialize, Debug, Clone, PartialEq)]
 pub struct Bullet {
     pub id: u32,
     pub owner_id: u32,
@@ -12,8 +13,6 @@ pub struct Bullet {
 }
 
 impl Bullet {
-    pub fn new(id: u32) {}
-
     pub fn update_position(&mut self) {
         self.x += self.vx;
         self.y += self.vy;
@@ -1,11 +1,15 @@
 use bincode::{Decode, Encode};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 
 use crate::{
-    asteroid::Asteroid, bullet::Bullet, packet::PlayerInput, player::Player, utils::current_time_ms,
+    asteroid::Asteroid, bullet::Bullet, packet::PlayerInput, player::Player, rules::GameRules,
+    utils::WorldRng,
 };
 
-#[derive(Encode, Decode, Debug, Clone)]
+pub const DEFAULT_TICK_MS: u64 = 16;
+
+#[derive(Encode, Decode, Serialize, Debug, Clone, PartialEq)]
 pub struct Bounds {
     pub west: f32,
     pub east: f32,
@@ -13,7 +17,7 @@ pub struct Bounds {
     pub south: f32,
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Serialize, Debug, Clone, PartialEq)]
 pub struct GameWorld {
     pub players: HashMap<u32, Player>,
     pub bullets: Vec<Bullet>,
@@ -23,10 +27,26 @@ pub struct GameWorld {
     pub bullet_id_counter: u32,
     pub asteroid_id_counter: u32,
     pub last_spawn_asteroid: u64,
+    pub rules: GameRules,
+    /// Simulated ticks since the match started.
+    pub tick: u64,
+    /// Simulated match time, advanced by `tick_ms` every update.
+    pub time_ms: u64,
+    pub tick_ms: u64,
+}
+
+impl Default for GameWorld {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GameWorld {
     pub fn new() -> Self {
+        Self::with_rules(GameRules::default())
+    }
+
+    pub fn with_rules(rules: GameRules) -> Self {
         Self {
             players: HashMap::new(),
             bullets: Vec::new(),
@@ -36,15 +56,24 @@ impl GameWorld {
             bullet_id_counter: 0,
             asteroid_id_counter: 0,
             last_spawn_asteroid: 0,
+            rules,
+            tick: 0,
+            time_ms: 0,
+            tick_ms: DEFAULT_TICK_MS,
         }
     }
 
-    pub fn update(&mut self) {
-        let now = current_time_ms();
+    /// Advances the world one tick. Worlds fed the same inputs and an `rng` with the
+    /// same state evolve identically. The rng stays with the server, it isn't part of
+    /// the world snapshots clients get, so they can't predict what spawns next.
+    pub fn update(&mut self, rng: &mut WorldRng) {
+        self.tick += 1;
+        self.time_ms += self.tick_ms;
+        let now = self.time_ms;
 
         // Update Bullets in world
-        let bullet_max_distance = 1000.0;
-        let asteroids_max_distance: f32 = 1000.0;
+        let bullet_max_distance = self.rules.bullet_range;
+        let asteroids_max_distance = self.rules.asteroid_range;
 
         // update entities
 
@@ -65,7 +94,9 @@ impl GameWorld {
         // Check for collision
         let mut bullets_to_remove = HashSet::new();
         let mut asteroids_to_remove = HashSet::new();
-        let mut players_hit = HashSet::new();
+        // who gets the credit, the first bullet to hit an asteroid and the last to hit a ship
+        let mut asteroids_shot = HashMap::new();
+        let mut players_hit = HashMap::new();
 
         for bullet in &self.bullets {
             for asteroid in self.asteroids.iter_mut() {
@@ -75,6 +106,7 @@ impl GameWorld {
                 let collision_radius = 30.0;
                 if dist_sq < collision_radius * collision_radius {
                     asteroids_to_remove.insert(asteroid.id);
+                    asteroids_shot.entry(asteroid.id).or_insert(bullet.owner_id);
                     bullets_to_remove.insert(bullet.id);
                 }
             }
@@ -89,9 +121,9 @@ impl GameWorld {
                 let dist_sq = dx * dx + dy * dy;
                 let collision_radius = 20.0;
                 if dist_sq < collision_radius * collision_radius {
-                    player.hp = player.hp.saturating_sub(20);
+                    player.hp = player.hp.saturating_sub(self.rules.bullet_damage);
                     bullets_to_remove.insert(bullet.id);
-                    players_hit.insert(player.id);
+                    players_hit.insert(player.id, Some(bullet.owner_id));
                 }
             }
         }
@@ -103,9 +135,9 @@ impl GameWorld {
                 let dist_sq = dx * dx + dy * dy;
                 let collision_radius = 30.0;
                 if dist_sq < collision_radius * collision_radius {
-                    player.hp = player.hp.saturating_sub(20);
+                    player.hp = player.hp.saturating_sub(self.rules.asteroid_damage);
                     asteroids_to_remove.insert(asteroid.id);
-                    players_hit.insert(player.id);
+                    players_hit.entry(player.id).or_insert(None);
                 }
             }
         }
@@ -114,24 +146,39 @@ impl GameWorld {
         self.asteroids
             .retain(|b| !asteroids_to_remove.contains(&b.id));
 
+        for owner_id in asteroids_shot.into_values() {
+            if let Some(owner) = self.players.get_mut(&owner_id) {
+                owner.asteroids_destroyed += 1;
+                owner.score += self.rules.asteroid_score;
+            }
+        }
+
         // Kill / Respawn
-        for id in players_hit {
-            if let Some(player) = self.players.get_mut(&id) {
-                if player.hp == 0 {
-                    player.x = 0.0;
-                    player.y = 0.0;
-                    player.vx = 0.0;
-                    player.vy = 0.0;
-                    player.hp = 100;
+        for (id, killer_id) in players_hit {
+            if let Some(player) = self.players.get_mut(&id)
+                && player.hp == 0
+            {
+                player.x = 0.0;
+                player.y = 0.0;
+                player.vx = 0.0;
+                player.vy = 0.0;
+                player.hp = self.rules.max_hp;
+                player.deaths += 1;
+
+                if let Some(killer) =
+                    killer_id.and_then(|killer_id| self.players.get_mut(&killer_id))
+                {
+                    killer.kills += 1;
+                    killer.score += self.rules.kill_score;
                 }
             }
         }
 
         // spawn asteroids
-        if now - self.last_spawn_asteroid > 1000 {
+        if now - self.last_spawn_asteroid > self.rules.asteroid_spawn_interval_ms {
             let id = self.asteroid_id_counter;
             self.asteroid_id_counter += 1;
-            self.asteroids.push(Asteroid::new(id));
+            self.asteroids.push(Asteroid::new(id, rng));
             self.last_spawn_asteroid = now;
         }
     }
@@ -144,16 +191,16 @@ impl GameWorld {
 
             match input.action {
                 crate::packet::InputAction::RotateLeft => {
-                    player.rotation -= 0.05;
+                    player.rotation -= self.rules.rotation_speed;
                 }
                 crate::packet::InputAction::RotateRight => {
-                    player.rotation += 0.05;
+                    player.rotation += self.rules.rotation_speed;
                 }
                 crate::packet::InputAction::Shoot => {
-                    let now = current_time_ms();
+                    let now = self.time_ms;
                     if now >= player.fire_rate_ms + player.last_shot_ms {
                         player.last_shot_ms = now;
-                        let speed = 20.0;
+                        let speed = self.rules.bullet_speed;
                         let id = self.bullet_id_counter;
                         self.bullet_id_counter += 1;
                         let bullet = Bullet {
@@ -170,7 +217,7 @@ impl GameWorld {
                     }
                 }
                 crate::packet::InputAction::Thrust => {
-                    let force = 1.0;
+                    let force = self.rules.thrust_force;
                     player.vx += force * player.rotation.cos();
                     player.vy += force * player.rotation.sin();
                 }
@@ -186,6 +233,43 @@ impl GameWorld {
     }
 
     pub fn add_player(&mut self, player_id: u32) {
-        self.players.insert(player_id, Player::new(player_id));
+        let mut player = Player::new(player_id);
+        player.hp = self.rules.max_hp;
+        player.fire_rate_ms = self.rules.fire_rate_ms;
+        self.players.insert(player_id, player);
+    }
+
+    pub fn add_bot(&mut self, player_id: u32) {
+        self.add_player(player_id);
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.is_bot = true;
+        }
+    }
+
+    /// Replaces the rules, already spawned players pick up the new fire rate and hp cap.
+    pub fn set_rules(&mut self, rules: GameRules) {
+        for player in self.players.values_mut() {
+            player.fire_rate_ms = rules.fire_rate_ms;
+            player.hp = player.hp.min(rules.max_hp);
+        }
+        self.rules = rules;
+    }
+
+    pub fn remove_player(&mut self, player_id: u32) -> Option<Player> {
+        self.players.remove(&player_id)
+    }
+
+    /// Starts a fresh match with the same players and rules.
+    pub fn restart(&mut self) {
+        let mut fresh = GameWorld::with_rules(self.rules.clone());
+        fresh.tick_ms = self.tick_ms;
+        for player in self.players.values() {
+            if player.is_bot {
+                fresh.add_bot(player.id);
+            } else {
+                fresh.add_player(player.id);
+            }
+        }
+        *self = fresh;
     }
 }
@@ -0,0 +1,140 @@
+//! Connectionless handshake on the game's UDP port:
+//! `Connect` -> `Challenge(cookie)` -> `Login { cookie, token, public_key }` -> `Accepted { public_key }`.
+//!
+//! Handshake datagrams start with [`HANDSHAKE_MARKER`] followed by bincode, session
+//! datagrams never do since session ids are never zero. The server stays stateless
+//! until the cookie comes back, and never answers with more bytes than it received,
+//! so a spoofed source address can't be used to reflect traffic at someone else.
+
+use bincode::{Decode, Encode};
+use std::fmt;
+
+use crate::packet::WIRE_CONFIG;
+use crate::secure::PUBLIC_KEY_LEN;
+
+pub const HANDSHAKE_MARKER: [u8; 4] = [0; 4];
+/// Anything longer is not a handshake.
+pub const MAX_HANDSHAKE_LEN: usize = 1200;
+/// `Connect` is padded to this size so the challenge always fits in the same number of bytes.
+pub const CONNECT_PADDED_LEN: usize = 128;
+pub const COOKIE_MAC_LEN: usize = 16;
+
+/// Proof that the client received a datagram at the address it claims.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cookie {
+    /// Server clock in seconds when the cookie was made, to let old ones expire.
+    pub issued_at: u64,
+    pub mac: [u8; COOKIE_MAC_LEN],
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum HandshakeRequest {
+    Connect {
+        protocol_version: u16,
+    },
+    /// Echoes the challenge and proves who the player is.
+    Login {
+        protocol_version: u16,
+        cookie: Cookie,
+        /// Token issued by the auth service at login.
+        token: String,
+        /// Watch the match without a ship.
+        spectate: bool,
+        /// The client's half of the key exchange, see [`crate::secure::KeyExchange`].
+        public_key: [u8; PUBLIC_KEY_LEN],
+    },
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum HandshakeResponse {
+    Challenge(Cookie),
+    /// Every session datagram after this is sealed with the key both ends derive
+    /// from `public_key` and their own half of the exchange, see [`crate::secure`].
+    /// The cookie's mac salts the derivation.
+    Accepted {
+        player_id: u32,
+        session_id: u32,
+        public_key: [u8; PUBLIC_KEY_LEN],
+        /// Whether payloads are encrypted or only authenticated.
+        encrypted: bool,
+    },
+    /// Like `Accepted`, but the session receives snapshots and events without owning a player.
+    Spectating {
+        session_id: u32,
+        public_key: [u8; PUBLIC_KEY_LEN],
+        encrypted: bool,
+    },
+    Rejected(RejectReason),
+}
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    InvalidToken,
+    ExpiredToken,
+    ProtocolMismatch,
+    NotAccepting,
+    /// The cookie is too old or wasn't issued to this address, start over with `Connect`.
+    InvalidCookie,
+    /// The client's public key can't be used for the key exchange.
+    InvalidKey,
+    /// Every player slot is taken, spectating still works.
+    RoomFull,
+}
+
+impl RejectReason {
+    /// Stable number for logs and client error messages.
+    pub fn code(self) -> u16 {
+        match self {
+            RejectReason::InvalidToken => 1,
+            RejectReason::ExpiredToken => 2,
+            RejectReason::ProtocolMismatch => 3,
+            RejectReason::NotAccepting => 4,
+            RejectReason::InvalidCookie => 5,
+            RejectReason::InvalidKey => 6,
+            RejectReason::RoomFull => 7,
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            RejectReason::InvalidToken => "invalid token",
+            RejectReason::ExpiredToken => "token expired",
+            RejectReason::ProtocolMismatch => "client and server versions differ",
+            RejectReason::NotAccepting => "server is not accepting players",
+            RejectReason::InvalidCookie => "handshake expired",
+            RejectReason::InvalidKey => "invalid key exchange",
+            RejectReason::RoomFull => "room is full",
+        };
+        write!(f, "{message} (code {})", self.code())
+    }
+}
+
+pub fn is_handshake(datagram: &[u8]) -> bool {
+    datagram.starts_with(&HANDSHAKE_MARKER)
+}
+
+/// Encodes a handshake message behind the marker, zero-padded up to `min_len`.
+pub fn encode_datagram<T: Encode>(
+    message: &T,
+    min_len: usize,
+) -> Result<Vec<u8>, bincode::error::EncodeError> {
+    let mut datagram = HANDSHAKE_MARKER.to_vec();
+    datagram.extend(bincode::encode_to_vec(message, WIRE_CONFIG)?);
+    if datagram.len() < min_len {
+        datagram.resize(min_len, 0);
+    }
+    Ok(datagram)
+}
+
+/// Decodes a handshake datagram, ignoring any padding after the message.
+pub fn decode_datagram<T: Decode<()>>(datagram: &[u8]) -> Option<T> {
+    if datagram.len() > MAX_HANDSHAKE_LEN {
+        return None;
+    }
+    let body = datagram.strip_prefix(&HANDSHAKE_MARKER)?;
+    bincode::decode_from_slice(body, WIRE_CONFIG)
+        .ok()
+        .map(|(message, _)| message)
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+/// What a leaderboard ranks players by.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaderboardStat {
+    #[default]
+    Kills,
+    /// Kills per death, a player without deaths counts as having one.
+    Kd,
+    Score,
+    /// Average score per match.
+    Rating,
+}
+
+/// How far back match results count, windows roll with the current time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaderboardWindow {
+    Day,
+    Week,
+    #[default]
+    All,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LeaderboardEntry {
+    /// Players with the same value share a rank.
+    pub rank: u64,
+    pub user_id: u32,
+    pub display_name: String,
+    pub value: f64,
+    pub matches: u64,
+}
+
+/// A page of `GET /leaderboard`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LeaderboardResponse {
+    pub stat: LeaderboardStat,
+    pub window: LeaderboardWindow,
+    pub entries: Vec<LeaderboardEntry>,
+    /// Pass as `cursor` to get the next page, absent on the last one.
+    pub next_cursor: Option<String>,
+}
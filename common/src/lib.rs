@@ -1,6 +1,16 @@
+pub mod asteroid;
+pub mod auth;
+pub mod bullet;
 pub mod game_world;
+pub mod handshake;
+pub mod leaderboard;
 pub mod packet;
 pub mod player;
-pub mod bullet;
-pub mod asteroid;
+pub mod profile;
+pub mod registry;
+pub mod rules;
+pub mod secure;
+pub mod social;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
 pub mod utils;
\ No newline at end of file
@@ -1,6 +1,19 @@
-use bincode::{Encode, Decode};
-use serde::{Deserialize, Serialize};
+use bincode::config::{Configuration, Limit, LittleEndian, Varint};
+use bincode::{Decode, Encode};
 
+use crate::game_world::GameWorld;
+
+/// Bumped whenever the wire format of packets or the world changes.
+pub const PROTOCOL_VERSION: u16 = 12;
+
+/// Largest UDP payload, nothing read off the wire can be longer.
+pub const MAX_WIRE_LEN: usize = 65_507;
+
+pub type WireConfig = Configuration<LittleEndian, Varint, Limit<MAX_WIRE_LEN>>;
+
+/// bincode settings for everything sent over UDP. The limit stops a forged length
+/// prefix from making the decoder allocate whatever it claims.
+pub const WIRE_CONFIG: WireConfig = bincode::config::standard().with_limit::<MAX_WIRE_LEN>();
 
 #[derive(Encode, Decode, Clone, Debug, Copy)]
 pub enum InputAction {
@@ -18,6 +31,26 @@ pub struct PlayerInput {
     pub action: InputAction,
 }
 
+/// Everything clients send over UDP once the handshake is done.
+#[derive(Encode, Decode, Clone, Debug)]
+pub enum ClientPacket {
+    Input(PlayerInput),
+    /// Keeps a session alive while the player sends no inputs, spectators send nothing else.
+    KeepAlive,
+    /// The player quit, the server ends the session right away.
+    Leave,
+}
+
+/// Everything the game server sends to clients over UDP.
+#[derive(Encode, Decode, Clone, Debug)]
+pub enum ServerPacket {
+    Snapshot(GameWorld),
+    /// Operator message shown to every player.
+    Notice(String),
+    /// Last packet before the server goes away, with the reason.
+    Shutdown(String),
+}
+
 impl InputAction {
     pub fn get_input_code_from_action(&self) -> u32 {
         match &self {
@@ -36,9 +69,107 @@ impl InputAction {
             3 => Self::Thrust,
             4 => Self::Shoot,
             5 => Self::Hello,
-            _ => Self::Hello
+            _ => Self::Hello,
         }
     }
-
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::{self, HandshakeRequest, MAX_HANDSHAKE_LEN};
+
+    fn decode<T: Decode<()>>(bytes: &[u8]) -> Option<T> {
+        bincode::decode_from_slice(bytes, WIRE_CONFIG)
+            .ok()
+            .map(|(value, _)| value)
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let input = PlayerInput {
+            id: 70_000,
+            seq: 123_456,
+            action: InputAction::Shoot,
+        };
+        let bytes = bincode::encode_to_vec(&input, WIRE_CONFIG).unwrap();
+        assert!(decode::<PlayerInput>(&bytes).is_some());
+        for len in 0..bytes.len() {
+            assert!(
+                decode::<PlayerInput>(&bytes[..len]).is_none(),
+                "{len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_handshake_is_rejected() {
+        let login = HandshakeRequest::Login {
+            protocol_version: PROTOCOL_VERSION,
+            cookie: handshake::Cookie {
+                issued_at: 1,
+                mac: [7; handshake::COOKIE_MAC_LEN],
+            },
+            token: "token".into(),
+            spectate: false,
+            public_key: [3; crate::secure::PUBLIC_KEY_LEN],
+        };
+        let datagram = handshake::encode_datagram(&login, 0).unwrap();
+        assert!(handshake::decode_datagram::<HandshakeRequest>(&datagram).is_some());
+        for len in 0..datagram.len() {
+            assert!(handshake::decode_datagram::<HandshakeRequest>(&datagram[..len]).is_none());
+        }
+    }
+
+    #[test]
+    fn forged_lengths_are_rejected_without_allocating() {
+        // a notice claiming a terabyte of text
+        let mut notice = vec![1, 253];
+        notice.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(decode::<ServerPacket>(&notice).is_none());
+
+        // a login whose token claims the same
+        let mut login = handshake::HANDSHAKE_MARKER.to_vec();
+        login.extend_from_slice(&[1, PROTOCOL_VERSION as u8, 0]);
+        login.extend_from_slice(&[0; handshake::COOKIE_MAC_LEN]);
+        login.push(253);
+        login.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(handshake::decode_datagram::<HandshakeRequest>(&login).is_none());
+    }
+
+    #[test]
+    fn oversized_handshake_is_rejected() {
+        let connect = HandshakeRequest::Connect {
+            protocol_version: PROTOCOL_VERSION,
+        };
+        let datagram = handshake::encode_datagram(&connect, MAX_HANDSHAKE_LEN + 1).unwrap();
+        assert!(handshake::decode_datagram::<HandshakeRequest>(&datagram).is_none());
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        // 255 is never a valid varint marker and no enum has that many variants
+        let garbage = [0xff; 64];
+        assert!(decode::<PlayerInput>(&garbage).is_none());
+        assert!(decode::<ServerPacket>(&garbage).is_none());
+        assert!(handshake::decode_datagram::<HandshakeRequest>(&garbage).is_none());
+
+        // random bytes may happen to decode, they just must not panic
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..10_000 {
+            let bytes: Vec<u8> = (0..state % 200)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            let _ = decode::<PlayerInput>(&bytes);
+            let _ = decode::<ServerPacket>(&bytes);
+            let mut datagram = handshake::HANDSHAKE_MARKER.to_vec();
+            datagram.extend_from_slice(&bytes);
+            let _ = handshake::decode_datagram::<HandshakeRequest>(&datagram);
+        }
+    }
+}
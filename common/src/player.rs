@@ -1,6 +1,7 @@
 use bincode::{Decode, Encode};
+use serde::Serialize;
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Serialize, Debug, Clone, PartialEq)]
 pub struct Player {
     pub id: u32,
     pub x: f32,
@@ -12,12 +13,19 @@ pub struct Player {
     pub last_shot_ms: u64,
     pub fire_rate_ms: u64,
     pub last_processed_input_seq: u32,
+    /// Played by the server to fill the room.
+    pub is_bot: bool,
+    /// Match stats, they start over with the match.
+    pub kills: u32,
+    pub deaths: u32,
+    pub asteroids_destroyed: u32,
+    pub score: u32,
 }
 
 impl Player {
     pub fn new(id: u32) -> Self {
         Player {
-            id: id,
+            id,
             x: 0.0,
             y: 0.0,
             rotation: 0.0,
@@ -27,6 +35,11 @@ impl Player {
             last_shot_ms: 0,
             fire_rate_ms: 200,
             last_processed_input_seq: 0,
+            is_bot: false,
+            kills: 0,
+            deaths: 0,
+            asteroids_destroyed: 0,
+            score: 0,
         }
     }
 
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+/// Ship color of accounts that never picked one.
+pub const DEFAULT_SHIP_COLOR: &str = "#ffffff";
+
+/// A player's public profile, served by the auth service at `GET /profile/{id}` and `GET /me`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    pub id: u32,
+    pub username: String,
+    /// Shown in game instead of the username, defaults to it.
+    pub display_name: String,
+    /// `#rrggbb`.
+    pub ship_color: String,
+    /// Unix seconds.
+    pub created_at: u64,
+    pub stats: PlayerStats,
+}
+
+/// Lifetime totals over every reported match.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PlayerStats {
+    pub kills: u64,
+    pub deaths: u64,
+    pub asteroids_destroyed: u64,
+    pub matches_played: u64,
+    pub matches_won: u64,
+    pub seconds_played: u64,
+}
+
+/// Body of `PATCH /me`, fields left out stay as they are.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdateProfileRequest {
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub ship_color: Option<String>,
+}
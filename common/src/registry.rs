@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+/// Sent by a game server to the auth service when it starts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegisterServerRequest {
+    pub name: String,
+    pub room_id: u32,
+    /// Address clients should use, not necessarily the one the server binds.
+    pub udp_addr: String,
+    pub region: String,
+    /// Free-form label for what is played, like `deathmatch`.
+    pub mode: String,
+    pub capacity: u32,
+    pub players: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegisterServerResponse {
+    pub server_id: String,
+    /// Servers that stay silent for a few intervals are dropped.
+    pub heartbeat_interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeartbeatRequest {
+    pub players: u32,
+    /// False while the server drains or shuts down.
+    pub accepting_players: bool,
+    /// Join tickets used since the last heartbeat, their reserved slots are filled now.
+    #[serde(default)]
+    pub redeemed_tickets: Vec<String>,
+    /// Accounts connected right now, playing or spectating, for friends' presence.
+    #[serde(default)]
+    pub online_players: Vec<u32>,
+}
+
+/// One game server as listed by the auth service's `GET /servers`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerListEntry {
+    pub id: String,
+    pub name: String,
+    pub room_id: u32,
+    /// Clients can time a handshake `Connect` against this address to measure ping.
+    pub address: String,
+    pub region: String,
+    pub mode: String,
+    pub players: u32,
+    pub capacity: u32,
+    pub accepting_players: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerListResponse {
+    pub servers: Vec<ServerListEntry>,
+}
+
+/// Sent by a logged in player to the auth service's `POST /matchmake`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchmakeRequest {
+    pub mode: String,
+    /// Preferred region, servers elsewhere are only used when it has no room.
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchmakeResponse {
+    pub server_id: String,
+    pub name: String,
+    pub room_id: u32,
+    pub address: String,
+    pub region: String,
+    pub mode: String,
+    /// Presented instead of the access token in the game server handshake.
+    pub ticket: String,
+    /// Seconds the slot stays reserved, the ticket expires with it.
+    pub expires_in: u64,
+}
+
+/// Sent by a game server to the auth service's `POST /servers/{id}/matches` when a match ends.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchResultRequest {
+    pub room_id: u32,
+    pub mode: String,
+    /// Unix seconds.
+    pub started_at: u64,
+    pub ended_at: u64,
+    /// Accounts that played in the match, even if they left early. Bots are not reported.
+    pub players: Vec<MatchPlayerResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MatchPlayerResult {
+    pub user_id: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    pub asteroids_destroyed: u32,
+    pub score: u32,
+    pub seconds_played: u64,
+    /// Finished with the top score, ties all win.
+    pub won: bool,
+}
@@ -0,0 +1,130 @@
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Tunable gameplay constants, the server may change them while a match runs.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct GameRules {
+    pub max_hp: u16,
+    pub bullet_damage: u16,
+    pub asteroid_damage: u16,
+    pub fire_rate_ms: u64,
+    pub bullet_speed: f32,
+    pub bullet_range: f32,
+    pub thrust_force: f32,
+    pub rotation_speed: f32,
+    pub asteroid_spawn_interval_ms: u64,
+    pub asteroid_range: f32,
+    /// Score for shooting down a ship and for shooting an asteroid.
+    pub kill_score: u32,
+    pub asteroid_score: u32,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            max_hp: 100,
+            bullet_damage: 20,
+            asteroid_damage: 20,
+            fire_rate_ms: 200,
+            bullet_speed: 20.0,
+            bullet_range: 1000.0,
+            thrust_force: 1.0,
+            rotation_speed: 0.05,
+            asteroid_spawn_interval_ms: 1000,
+            asteroid_range: 1000.0,
+            kill_score: 100,
+            asteroid_score: 10,
+        }
+    }
+}
+
+impl GameRules {
+    /// Checks the rules are something a match can run with, naming the first field that isn't.
+    pub fn validate(&self) -> Result<(), String> {
+        fn within<T: PartialOrd + std::fmt::Display>(
+            field: &str,
+            value: T,
+            min: T,
+            max: T,
+        ) -> Result<(), String> {
+            // written so NaN fails too
+            if value >= min && value <= max {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{field} must be between {min} and {max}, got {value}"
+                ))
+            }
+        }
+
+        within("max_hp", self.max_hp, 1, 10_000)?;
+        within("bullet_damage", self.bullet_damage, 0, self.max_hp)?;
+        within("asteroid_damage", self.asteroid_damage, 0, self.max_hp)?;
+        within("fire_rate_ms", self.fire_rate_ms, 1, 60_000)?;
+        within("bullet_speed", self.bullet_speed, 0.1, 200.0)?;
+        within("bullet_range", self.bullet_range, 1.0, 100_000.0)?;
+        within("thrust_force", self.thrust_force, 0.01, 50.0)?;
+        within(
+            "rotation_speed",
+            self.rotation_speed,
+            0.001,
+            std::f32::consts::PI,
+        )?;
+        within(
+            "asteroid_spawn_interval_ms",
+            self.asteroid_spawn_interval_ms,
+            1,
+            3_600_000,
+        )?;
+        within("asteroid_range", self.asteroid_range, 1.0, 100_000.0)?;
+        within("kill_score", self.kill_score, 0, 1_000_000)?;
+        within("asteroid_score", self.asteroid_score, 0, 1_000_000)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(GameRules::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_zero_intervals_and_out_of_range_values() {
+        let broken = [
+            GameRules {
+                fire_rate_ms: 0,
+                ..GameRules::default()
+            },
+            GameRules {
+                asteroid_spawn_interval_ms: 0,
+                ..GameRules::default()
+            },
+            GameRules {
+                bullet_speed: -20.0,
+                ..GameRules::default()
+            },
+            GameRules {
+                thrust_force: f32::NAN,
+                ..GameRules::default()
+            },
+            GameRules {
+                max_hp: 0,
+                bullet_damage: 0,
+                asteroid_damage: 0,
+                ..GameRules::default()
+            },
+            GameRules {
+                bullet_damage: 101,
+                ..GameRules::default()
+            },
+        ];
+        for rules in broken {
+            assert!(rules.validate().is_err(), "{rules:?}");
+        }
+    }
+}
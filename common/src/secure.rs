@@ -0,0 +1,417 @@
+//! Authenticated UDP datagrams. Every datagram is
+//! `session_id (u32 BE) | seq (u64 BE) | flags (u8) | payload | tag (16 bytes)`,
+//! sealed with ChaCha20-Poly1305 under a key both ends derive from an X25519
+//! exchange during the handshake, the key itself never goes over the wire.
+//! Without encryption the payload travels in the clear and the tag only
+//! authenticates header and payload.
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const KEY_LEN: usize = 32;
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const HEADER_LEN: usize = 13;
+pub const TAG_LEN: usize = 16;
+/// Sequence numbers further behind the newest one than this are dropped.
+pub const REPLAY_WINDOW: u64 = 64;
+
+const FLAG_ENCRYPTED: u8 = 0b0000_0001;
+const KEY_INFO: &[u8] = b"arena session key";
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionKey(pub [u8; KEY_LEN]);
+
+impl fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SessionKey(..)")
+    }
+}
+
+/// One end of the key exchange, made fresh for every handshake.
+pub struct KeyExchange {
+    secret: StaticSecret,
+}
+
+impl Default for KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyExchange {
+    pub fn new() -> Self {
+        let mut secret = [0u8; 32];
+        rand::rng().fill_bytes(&mut secret);
+        Self {
+            secret: StaticSecret::from(secret),
+        }
+    }
+
+    /// Sent to the other end, which can't derive the session key from it alone.
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_LEN] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+
+    /// The key shared with whoever holds the secret behind `peer_public`. `salt` ties
+    /// it to one handshake. None when the peer's key would make the result predictable.
+    pub fn session_key(
+        &self,
+        peer_public: [u8; PUBLIC_KEY_LEN],
+        salt: &[u8],
+    ) -> Option<SessionKey> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer_public));
+        if !shared.was_contributory() {
+            return None;
+        }
+
+        // both ends have to feed the public keys in the same order
+        let own = self.public_key();
+        let (low, high) = if own <= peer_public {
+            (own, peer_public)
+        } else {
+            (peer_public, own)
+        };
+        let mut key = [0u8; KEY_LEN];
+        Hkdf::<Sha256>::new(Some(salt), shared.as_bytes())
+            .expand_multi_info(&[KEY_INFO, &low, &high], &mut key)
+            .expect("a session key is a valid HKDF output length");
+        Some(SessionKey(key))
+    }
+}
+
+/// Keeps the nonces of the two directions apart under the same key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenError {
+    Truncated,
+    WrongSession,
+    /// Encrypted when the session isn't or the other way round.
+    WrongMode,
+    BadTag,
+    Replayed,
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            OpenError::Truncated => "datagram too short",
+            OpenError::WrongSession => "datagram for another session",
+            OpenError::WrongMode => "datagram encryption doesn't match the session",
+            OpenError::BadTag => "datagram failed authentication",
+            OpenError::Replayed => "datagram replayed or too old",
+        };
+        write!(f, "{message}")
+    }
+}
+
+/// Reads the session id so the receiver can find the right key.
+pub fn peek_session_id(datagram: &[u8]) -> Option<u32> {
+    let bytes = datagram.get(..4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn nonce(direction: Direction, seq: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[0] = match direction {
+        Direction::ClientToServer => 0,
+        Direction::ServerToClient => 1,
+    };
+    nonce[4..].copy_from_slice(&seq.to_be_bytes());
+    Nonce::from(nonce)
+}
+
+/// Seals outgoing datagrams, numbering them as it goes.
+pub struct PacketSealer {
+    cipher: ChaCha20Poly1305,
+    session_id: u32,
+    direction: Direction,
+    encrypt: bool,
+    next_seq: u64,
+}
+
+impl PacketSealer {
+    pub fn new(key: &SessionKey, session_id: u32, direction: Direction, encrypt: bool) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(&Key::from(key.0)),
+            session_id,
+            direction,
+            encrypt,
+            next_seq: 1,
+        }
+    }
+
+    pub fn seal(&mut self, payload: &[u8]) -> Vec<u8> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let mut datagram = Vec::with_capacity(HEADER_LEN + payload.len() + TAG_LEN);
+        datagram.extend_from_slice(&self.session_id.to_be_bytes());
+        datagram.extend_from_slice(&seq.to_be_bytes());
+        datagram.push(if self.encrypt { FLAG_ENCRYPTED } else { 0 });
+        datagram.extend_from_slice(payload);
+
+        let nonce = nonce(self.direction, seq);
+        let tag = if self.encrypt {
+            let (header, body) = datagram.split_at_mut(HEADER_LEN);
+            self.cipher.encrypt_in_place_detached(&nonce, header, body)
+        } else {
+            // header and payload go in as associated data, nothing gets encrypted
+            self.cipher
+                .encrypt_in_place_detached(&nonce, &datagram, &mut [])
+        }
+        .expect("payload fits in a single ChaCha20-Poly1305 message");
+
+        datagram.extend_from_slice(&tag);
+        datagram
+    }
+}
+
+/// Verifies incoming datagrams and drops replays.
+pub struct PacketOpener {
+    cipher: ChaCha20Poly1305,
+    session_id: u32,
+    direction: Direction,
+    encrypted: bool,
+    window: ReplayWindow,
+}
+
+impl PacketOpener {
+    pub fn new(key: &SessionKey, session_id: u32, direction: Direction, encrypted: bool) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(&Key::from(key.0)),
+            session_id,
+            direction,
+            encrypted,
+            window: ReplayWindow::default(),
+        }
+    }
+
+    /// Returns the payload of an authentic datagram that wasn't seen before.
+    pub fn open(&mut self, datagram: &[u8]) -> Result<Vec<u8>, OpenError> {
+        if datagram.len() < HEADER_LEN + TAG_LEN {
+            return Err(OpenError::Truncated);
+        }
+        if peek_session_id(datagram) != Some(self.session_id) {
+            return Err(OpenError::WrongSession);
+        }
+
+        let seq = u64::from_be_bytes(datagram[4..12].try_into().unwrap());
+        let encrypted = datagram[12] & FLAG_ENCRYPTED != 0;
+        if encrypted != self.encrypted {
+            return Err(OpenError::WrongMode);
+        }
+        // cheap check first, the window only moves once the tag is verified
+        if !self.window.is_fresh(seq) {
+            return Err(OpenError::Replayed);
+        }
+
+        let (sealed, tag) = datagram.split_at(datagram.len() - TAG_LEN);
+        let (header, body) = sealed.split_at(HEADER_LEN);
+        let nonce = nonce(self.direction, seq);
+        let tag = Tag::from(<[u8; TAG_LEN]>::try_from(tag).map_err(|_| OpenError::Truncated)?);
+
+        let mut payload = body.to_vec();
+        let verified = if encrypted {
+            self.cipher
+                .decrypt_in_place_detached(&nonce, header, &mut payload, &tag)
+        } else {
+            self.cipher
+                .decrypt_in_place_detached(&nonce, sealed, &mut [], &tag)
+        };
+        verified.map_err(|_| OpenError::BadTag)?;
+
+        self.window.mark(seq);
+        Ok(payload)
+    }
+}
+
+/// Sliding bitmap of the last [`REPLAY_WINDOW`] sequence numbers.
+#[derive(Debug, Default, Clone)]
+pub struct ReplayWindow {
+    highest: u64,
+    /// Bit `n` is set once `highest - n` was accepted.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    pub fn is_fresh(&self, seq: u64) -> bool {
+        if seq > self.highest {
+            return true;
+        }
+        let age = self.highest - seq;
+        age < REPLAY_WINDOW && self.seen & (1 << age) == 0
+    }
+
+    pub fn mark(&mut self, seq: u64) {
+        if seq > self.highest {
+            let shift = seq - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.highest = seq;
+        } else {
+            self.seen |= 1 << (self.highest - seq);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION_ID: u32 = 0x1234_5678;
+
+    fn pair(encrypted: bool) -> (PacketSealer, PacketOpener) {
+        let key = SessionKey([9; KEY_LEN]);
+        (
+            PacketSealer::new(&key, SESSION_ID, Direction::ClientToServer, encrypted),
+            PacketOpener::new(&key, SESSION_ID, Direction::ClientToServer, encrypted),
+        )
+    }
+
+    #[test]
+    fn sealed_datagrams_open() {
+        for encrypted in [false, true] {
+            let (mut sealer, mut opener) = pair(encrypted);
+            for payload in [&b""[..], b"input", &[0xab; 1200]] {
+                let datagram = sealer.seal(payload);
+                assert_eq!(datagram.len(), HEADER_LEN + payload.len() + TAG_LEN);
+                assert_eq!(opener.open(&datagram).unwrap(), payload);
+            }
+        }
+
+        let (mut sealer, _) = pair(true);
+        let datagram = sealer.seal(b"secret input");
+        assert!(!datagram.windows(6).any(|window| window == b"secret"));
+    }
+
+    #[test]
+    fn flipped_bits_fail() {
+        for encrypted in [false, true] {
+            let (mut sealer, _) = pair(encrypted);
+            let datagram = sealer.seal(b"input");
+            for bit in 0..datagram.len() * 8 {
+                let mut tampered = datagram.clone();
+                tampered[bit / 8] ^= 1 << (bit % 8);
+                let (_, mut opener) = pair(encrypted);
+                assert!(opener.open(&tampered).is_err(), "bit {bit} went unnoticed");
+            }
+        }
+    }
+
+    #[test]
+    fn replays_are_rejected() {
+        let (mut sealer, mut opener) = pair(true);
+        let first = sealer.seal(b"first");
+        assert!(opener.open(&first).is_ok());
+        assert_eq!(opener.open(&first), Err(OpenError::Replayed));
+
+        // late but inside the window is fine, once
+        let late = sealer.seal(b"late");
+        let datagrams: Vec<_> = (0..10).map(|_| sealer.seal(b"input")).collect();
+        assert!(opener.open(&datagrams[9]).is_ok());
+        assert!(opener.open(&late).is_ok());
+        assert_eq!(opener.open(&late), Err(OpenError::Replayed));
+
+        // older than the window
+        let old = sealer.seal(b"old");
+        for _ in 0..REPLAY_WINDOW {
+            sealer.seal(b"input");
+        }
+        assert!(opener.open(&sealer.seal(b"new")).is_ok());
+        assert_eq!(opener.open(&old), Err(OpenError::Replayed));
+    }
+
+    #[test]
+    fn replay_window_slides() {
+        let mut window = ReplayWindow::default();
+        window.mark(5);
+        assert!(!window.is_fresh(5));
+        assert!(window.is_fresh(3));
+        window.mark(3);
+        assert!(!window.is_fresh(3));
+
+        // a jump forgets everything, the oldest seq still accepted is `highest - 63`
+        let highest = 100;
+        window.mark(highest);
+        assert!(!window.is_fresh(5));
+        assert!(!window.is_fresh(highest - REPLAY_WINDOW));
+        let oldest = highest - REPLAY_WINDOW + 1;
+        assert!(window.is_fresh(oldest));
+        window.mark(highest + 1);
+        assert!(!window.is_fresh(oldest));
+        assert!(window.is_fresh(oldest + 1));
+        window.mark(oldest + 1);
+        assert!(!window.is_fresh(oldest + 1));
+    }
+
+    #[test]
+    fn other_sessions_are_rejected() {
+        let key = SessionKey([9; KEY_LEN]);
+        let mut sealer = PacketSealer::new(&key, SESSION_ID + 1, Direction::ClientToServer, true);
+        let (_, mut opener) = pair(true);
+        assert_eq!(
+            opener.open(&sealer.seal(b"input")),
+            Err(OpenError::WrongSession)
+        );
+        assert_eq!(opener.open(&[0; HEADER_LEN]), Err(OpenError::Truncated));
+    }
+
+    #[test]
+    fn directions_and_modes_do_not_mix() {
+        let key = SessionKey([9; KEY_LEN]);
+        let mut reflected = PacketSealer::new(&key, SESSION_ID, Direction::ServerToClient, true);
+        let (_, mut opener) = pair(true);
+        assert_eq!(
+            opener.open(&reflected.seal(b"input")),
+            Err(OpenError::BadTag)
+        );
+
+        let (mut plain, _) = pair(false);
+        assert_eq!(
+            opener.open(&plain.seal(b"input")),
+            Err(OpenError::WrongMode)
+        );
+    }
+
+    #[test]
+    fn both_ends_derive_the_same_key() {
+        let client = KeyExchange::new();
+        let server = KeyExchange::new();
+        let salt = [7; 16];
+        let client_key = client.session_key(server.public_key(), &salt).unwrap();
+        let server_key = server.session_key(client.public_key(), &salt).unwrap();
+        assert!(client_key == server_key);
+
+        // another handshake or another peer gives another key
+        assert!(client.session_key(server.public_key(), &[8; 16]).unwrap() != client_key);
+        let other = KeyExchange::new();
+        assert!(other.session_key(server.public_key(), &salt).unwrap() != client_key);
+    }
+
+    #[test]
+    fn low_order_keys_are_refused() {
+        let exchange = KeyExchange::new();
+        assert!(
+            exchange
+                .session_key([0; PUBLIC_KEY_LEN], &[7; 16])
+                .is_none()
+        );
+        let mut one = [0; PUBLIC_KEY_LEN];
+        one[0] = 1;
+        assert!(exchange.session_key(one, &[7; 16]).is_none());
+    }
+}
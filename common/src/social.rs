@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+/// Where an account is playing, as the game server hosting it last reported.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Presence {
+    pub server_id: String,
+    pub server_name: String,
+    pub room_id: u32,
+    pub mode: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FriendState {
+    Friends,
+    /// They asked, accepting makes them a friend.
+    Incoming,
+    /// We asked and they haven't answered yet.
+    Outgoing,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Friend {
+    pub user_id: u32,
+    pub display_name: String,
+    pub state: FriendState,
+    /// Unix seconds of the request, or of accepting it for friends.
+    pub since: u64,
+    /// Only shown between friends, unset while they aren't in a game.
+    pub presence: Option<Presence>,
+}
+
+/// Friends and open requests, `GET /friends`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FriendsResponse {
+    pub friends: Vec<Friend>,
+}
+
+/// Body of `POST /friends`. Asking someone who already asked us accepts their request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FriendRequest {
+    pub username: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartyMember {
+    pub user_id: u32,
+    pub display_name: String,
+}
+
+/// Where the leader's last matchmake put the party. Members `POST /matchmake` to
+/// claim the slot held for them there.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartyMatch {
+    pub server_id: String,
+    pub name: String,
+    pub mode: String,
+    /// Seconds the slots stay held.
+    pub expires_in: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Party {
+    pub id: String,
+    pub leader_id: u32,
+    /// In the order they joined, the leader first.
+    pub members: Vec<PartyMember>,
+    /// Friends the leader invited who haven't joined yet.
+    pub invited: Vec<u32>,
+    pub current_match: Option<PartyMatch>,
+}
+
+/// An invitation to someone else's party.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartyInvite {
+    pub party_id: String,
+    pub leader_id: u32,
+    pub leader_name: String,
+}
+
+/// `GET /party`, the caller's party if they're in one and the parties they were invited to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartyResponse {
+    pub party: Option<Party>,
+    pub invites: Vec<PartyInvite>,
+}
+
+/// Body of `POST /party/invite`, only friends can be invited.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartyInviteRequest {
+    pub user_id: u32,
+}
+
+/// Body of `POST /party/join`, joining needs an invite.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartyJoinRequest {
+    pub party_id: String,
+}
@@ -0,0 +1,23 @@
+//! Logging setup shared by the game server and the auth service.
+
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_FILTER: &str = "info";
+
+/// Installs the global subscriber. Verbosity comes from `RUST_LOG`
+/// (e.g. `RUST_LOG=server=debug` or `RUST_LOG=auth=debug`), `LOG_FORMAT=json` switches to JSON lines.
+pub fn init_tracing() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+
+    let json = std::env::var("LOG_FORMAT")
+        .map(|format| format.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if json {
+        builder.json().with_current_span(true).init();
+    } else {
+        builder.init();
+    }
+}
@@ -1,3 +1,7 @@
+use bincode::{Decode, Encode};
+use rand::RngCore;
+use serde::Serialize;
+
 pub fn current_time_ms() -> u64 {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -5,3 +9,37 @@ pub fn current_time_ms() -> u64 {
 
     now.as_millis() as u64
 }
+
+/// Small seedable generator (SplitMix64) the server simulates the world with. Its
+/// state is recorded next to the world, so a match can be re-simulated from its seed.
+#[derive(Encode, Decode, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WorldRng {
+    state: u64,
+}
+
+impl WorldRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl RngCore for WorldRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        for chunk in dst.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
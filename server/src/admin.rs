@@ -0,0 +1,336 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Request, State, rejection::JsonRejection},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use common::rules::GameRules;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::{io, sync::Arc};
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::lifecycle::DEFAULT_SHUTDOWN_REASON;
+
+/// Operator requests, executed by the game loop between ticks.
+pub enum AdminCommand {
+    ListSessions(oneshot::Sender<Vec<SessionInfo>>),
+    ListRooms(oneshot::Sender<Vec<RoomInfo>>),
+    Kick {
+        player_id: u32,
+        reply: oneshot::Sender<bool>,
+    },
+    /// Replies with the number of sessions that were kicked.
+    Ban {
+        ip: IpAddr,
+        reply: oneshot::Sender<usize>,
+    },
+    GetRules(oneshot::Sender<GameRules>),
+    SetRules {
+        rules: GameRules,
+        reply: oneshot::Sender<()>,
+    },
+    RestartMatch(oneshot::Sender<()>),
+    Notice {
+        message: String,
+        reply: oneshot::Sender<()>,
+    },
+    /// Replies with false if the server was already draining or shutting down.
+    Drain(oneshot::Sender<bool>),
+    Shutdown {
+        reason: String,
+        reply: oneshot::Sender<()>,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionInfo {
+    pub player_id: u32,
+    pub username: Option<String>,
+    /// Unknown until the player's first datagram arrives.
+    pub addr: Option<SocketAddr>,
+    pub hp: u16,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RoomInfo {
+    pub room_id: u32,
+    pub tick: u64,
+    pub players: usize,
+    pub spectators: usize,
+    pub bots: usize,
+    pub bullets: usize,
+    pub asteroids: usize,
+}
+
+#[derive(Deserialize)]
+pub struct BanRequest {
+    pub ip: IpAddr,
+}
+
+#[derive(Deserialize)]
+pub struct NoticeRequest {
+    pub message: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ShutdownRequest {
+    pub reason: Option<String>,
+}
+
+pub enum AdminError {
+    Unauthorized,
+    BadRequest(String),
+    NotFound(String),
+    Unavailable,
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            AdminError::Unauthorized => (StatusCode::UNAUTHORIZED, "Invalid admin token".into()),
+            AdminError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            AdminError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AdminError::Unavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Game loop is not running".into(),
+            ),
+        };
+
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+struct AdminState {
+    commands: mpsc::Sender<AdminCommand>,
+    token: String,
+}
+
+/// Serves the admin API. Every request needs `Authorization: Bearer <token>`.
+pub async fn serve_admin(
+    listener: TcpListener,
+    token: String,
+    commands: mpsc::Sender<AdminCommand>,
+) -> io::Result<()> {
+    info!(addr = %listener.local_addr()?, "Admin API listening");
+    let state = Arc::new(AdminState { commands, token });
+
+    let app = Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:player_id/kick", post(kick))
+        .route("/rooms", get(list_rooms))
+        .route("/bans", post(ban))
+        .route("/rules", get(get_rules).put(set_rules))
+        .route("/match/restart", post(restart_match))
+        .route("/notice", post(notice))
+        .route("/drain", post(drain))
+        .route("/shutdown", post(shutdown))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+
+    axum::serve(listener, app).await
+}
+
+async fn authorize(
+    State(state): State<Arc<AdminState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AdminError> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    // compared in constant time, how long a rejection takes mustn't give the token away
+    let valid =
+        token.is_some_and(|token| bool::from(token.as_bytes().ct_eq(state.token.as_bytes())));
+    if !valid {
+        warn!(uri = %request.uri(), "Rejected admin request");
+        return Err(AdminError::Unauthorized);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Sends a command to the game loop and waits for its answer.
+async fn ask<T>(
+    state: &AdminState,
+    command: impl FnOnce(oneshot::Sender<T>) -> AdminCommand,
+) -> Result<T, AdminError> {
+    let (tx, rx) = oneshot::channel();
+    state
+        .commands
+        .send(command(tx))
+        .await
+        .map_err(|_| AdminError::Unavailable)?;
+    rx.await.map_err(|_| AdminError::Unavailable)
+}
+
+async fn list_sessions(
+    State(state): State<Arc<AdminState>>,
+) -> Result<Json<Vec<SessionInfo>>, AdminError> {
+    Ok(Json(ask(&state, AdminCommand::ListSessions).await?))
+}
+
+async fn list_rooms(
+    State(state): State<Arc<AdminState>>,
+) -> Result<Json<Vec<RoomInfo>>, AdminError> {
+    Ok(Json(ask(&state, AdminCommand::ListRooms).await?))
+}
+
+async fn kick(
+    State(state): State<Arc<AdminState>>,
+    Path(player_id): Path<u32>,
+) -> Result<StatusCode, AdminError> {
+    info!(player_id, "Admin kick");
+    let kicked = ask(&state, |reply| AdminCommand::Kick { player_id, reply }).await?;
+    if !kicked {
+        return Err(AdminError::NotFound(format!(
+            "No player with id {player_id}"
+        )));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn ban(
+    State(state): State<Arc<AdminState>>,
+    Json(req): Json<BanRequest>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    info!(ip = %req.ip, "Admin ban");
+    let kicked = ask(&state, |reply| AdminCommand::Ban { ip: req.ip, reply }).await?;
+    Ok(Json(serde_json::json!({ "kicked": kicked })))
+}
+
+async fn get_rules(State(state): State<Arc<AdminState>>) -> Result<Json<GameRules>, AdminError> {
+    Ok(Json(ask(&state, AdminCommand::GetRules).await?))
+}
+
+async fn set_rules(
+    State(state): State<Arc<AdminState>>,
+    rules: Result<Json<GameRules>, JsonRejection>,
+) -> Result<Json<GameRules>, AdminError> {
+    let Json(rules) = rules.map_err(|e| AdminError::BadRequest(e.body_text()))?;
+    rules.validate().map_err(AdminError::BadRequest)?;
+    info!(?rules, "Admin changed rules");
+    ask(&state, |reply| AdminCommand::SetRules {
+        rules: rules.clone(),
+        reply,
+    })
+    .await?;
+    Ok(Json(rules))
+}
+
+async fn restart_match(State(state): State<Arc<AdminState>>) -> Result<StatusCode, AdminError> {
+    info!("Admin restarted the match");
+    ask(&state, AdminCommand::RestartMatch).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn notice(
+    State(state): State<Arc<AdminState>>,
+    Json(req): Json<NoticeRequest>,
+) -> Result<StatusCode, AdminError> {
+    info!(message = %req.message, "Admin notice");
+    ask(&state, |reply| AdminCommand::Notice {
+        message: req.message,
+        reply,
+    })
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn drain(
+    State(state): State<Arc<AdminState>>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    info!("Admin requested drain");
+    let draining = ask(&state, AdminCommand::Drain).await?;
+    Ok(Json(serde_json::json!({ "draining": draining })))
+}
+
+async fn shutdown(
+    State(state): State<Arc<AdminState>>,
+    req: Option<Json<ShutdownRequest>>,
+) -> Result<StatusCode, AdminError> {
+    let reason = req
+        .and_then(|Json(req)| req.reason)
+        .unwrap_or_else(|| DEFAULT_SHUTDOWN_REASON.into());
+    info!(%reason, "Admin requested shutdown");
+    ask(&state, |reply| AdminCommand::Shutdown { reason, reply }).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "admin-secret";
+
+    /// Serves the admin API with a stand-in game loop that applies rule changes.
+    async fn admin_api() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (commands, mut rx) = mpsc::channel(8);
+        tokio::spawn(serve_admin(listener, TOKEN.into(), commands));
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                if let AdminCommand::SetRules { reply, .. } = command {
+                    let _ = reply.send(());
+                }
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    async fn put_rules(base: &str, token: &str, rules: serde_json::Value) -> StatusCode {
+        reqwest::Client::new()
+            .put(format!("{base}/rules"))
+            .bearer_auth(token)
+            .json(&rules)
+            .send()
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_tokens() {
+        let base = admin_api().await;
+        let rules = serde_json::json!({});
+        for token in ["", "admin-secreT", "admin-secret2", "admin"] {
+            assert_eq!(
+                put_rules(&base, token, rules.clone()).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        assert_eq!(put_rules(&base, TOKEN, rules).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_rules_a_match_cannot_run_with() {
+        let base = admin_api().await;
+        for rules in [
+            serde_json::json!({ "fire_rate_ms": 0 }),
+            serde_json::json!({ "fire_rate_ms": -200 }),
+            serde_json::json!({ "asteroid_spawn_interval_ms": 0 }),
+            serde_json::json!({ "bullet_speed": -20.0 }),
+            serde_json::json!({ "max_hp": 100, "bullet_damage": 500 }),
+            serde_json::json!({ "max_hp": "lots" }),
+        ] {
+            assert_eq!(
+                put_rules(&base, TOKEN, rules.clone()).await,
+                StatusCode::BAD_REQUEST,
+                "{rules}"
+            );
+        }
+        assert_eq!(
+            put_rules(&base, TOKEN, serde_json::json!({ "bullet_speed": 30.0 })).await,
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Re-simulates a match recording and checks it against its keyframes.
+//!
+//! ```text
+//! replay <recording>                              verify every keyframe
+//! replay <recording> --export <tick> [--out file] dump the world at a tick as JSON
+//! ```
+
+use common::packet::PROTOCOL_VERSION;
+use server::recording;
+use server::replay::Replay;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: replay <recording> [--export <tick> [--out <file>]]";
+
+struct Args {
+    path: String,
+    export: Option<u64>,
+    out: Option<String>,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut args = std::env::args().skip(1);
+    let mut parsed = Args {
+        path: args.next()?,
+        export: None,
+        out: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--export" => parsed.export = Some(args.next()?.parse().ok()?),
+            "--out" => parsed.out = Some(args.next()?),
+            _ => return None,
+        }
+    }
+
+    Some(parsed)
+}
+
+fn main() -> ExitCode {
+    let Some(args) = parse_args() else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}: {e}", args.path);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns whether the replay reproduced the recording.
+fn run(args: &Args) -> Result<bool, Box<dyn std::error::Error>> {
+    let chunks = recording::read_chunks(BufReader::new(File::open(&args.path)?))?;
+    let replay = Replay::from_chunks(chunks)?;
+    let header = &replay.header;
+
+    if header.protocol_version != PROTOCOL_VERSION {
+        eprintln!(
+            "warning: recorded with protocol {}, simulating with {PROTOCOL_VERSION}",
+            header.protocol_version
+        );
+    }
+
+    if let Some(tick) = args.export {
+        let world = replay.world_at(tick)?;
+        let json = serde_json::to_string_pretty(&world)?;
+        match &args.out {
+            Some(out) => std::fs::write(out, json)?,
+            None => writeln!(io::stdout(), "{json}")?,
+        }
+        return Ok(true);
+    }
+
+    println!(
+        "room {} at {} Hz, ticks {}..={}",
+        header.room_id,
+        header.tick_rate_hz,
+        replay.first_tick(),
+        replay.last_tick()
+    );
+
+    let verification = replay.verify();
+    match verification.divergence {
+        None => {
+            println!(
+                "ok: {} ticks, {} keyframes match",
+                verification.ticks, verification.keyframes_checked
+            );
+            Ok(true)
+        }
+        Some(divergence) => {
+            println!("diverged at tick {}", divergence.tick);
+            for difference in divergence.differences {
+                println!("  {difference}");
+            }
+            Ok(false)
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use common::game_world::GameWorld;
+use common::packet::{InputAction, PlayerInput};
+use common::player::Player;
+use std::collections::BTreeMap;
+use std::f32::consts::{PI, TAU};
+
+/// Bot ids start here so they never collide with account ids from the auth service.
+pub const BOT_ID_BASE: u32 = 0xF000_0000;
+
+/// Ticks ahead a bot looks for asteroids on a collision course.
+const DODGE_HORIZON_TICKS: f32 = 60.0;
+/// Asteroids hit within 30 units, keep some room on top of that.
+const DODGE_RADIUS: f32 = 60.0;
+/// Close enough to fight, no need to thrust any further.
+const ENGAGE_DISTANCE: f32 = 300.0;
+/// Closer than this a bot backs off, bullets fired point blank fly past the target.
+const KEEP_DISTANCE: f32 = 120.0;
+/// Spreads bots that sit on top of each other, as they all do at spawn.
+const GOLDEN_ANGLE: f32 = 2.399_963;
+/// How far off the firing solution a bot still pulls the trigger, in radians.
+const AIM_TOLERANCE: f32 = 0.1;
+/// Bots only thrust when roughly facing where they want to go.
+const THRUST_TOLERANCE: f32 = 0.6;
+
+/// Decides what a bot does, given the world as it is at the start of the tick.
+pub trait BotBehaviour: Send {
+    /// Returns the actions to apply this tick, in order.
+    fn think(&mut self, me: &Player, world: &GameWorld) -> Vec<InputAction>;
+}
+
+/// Chases the closest enemy and shoots where it is going to be, unless an asteroid is in the way.
+#[derive(Debug, Default)]
+pub struct Hunter;
+
+impl BotBehaviour for Hunter {
+    fn think(&mut self, me: &Player, world: &GameWorld) -> Vec<InputAction> {
+        let rules = &world.rules;
+
+        if let Some(heading) = dodge_heading(me, world) {
+            return fly_toward(me.rotation, heading, rules.rotation_speed);
+        }
+
+        let Some(target) = world
+            .players
+            .values()
+            .filter(|player| player.id != me.id)
+            .min_by(|a, b| distance_sq(me, a).total_cmp(&distance_sq(me, b)))
+        else {
+            return Vec::new();
+        };
+
+        let distance = distance_sq(me, target).sqrt();
+        if distance < KEEP_DISTANCE {
+            let away = if distance < 1.0 {
+                (me.id % 64) as f32 * GOLDEN_ANGLE
+            } else {
+                (me.y - target.y).atan2(me.x - target.x)
+            };
+            return fly_toward(me.rotation, away, rules.rotation_speed);
+        }
+
+        let (aim_x, aim_y) = lead_target(me, target, rules.bullet_speed);
+        let heading = aim_y.atan2(aim_x);
+        let off_by = angle_between(me.rotation, heading).abs();
+
+        let mut actions = steer(me.rotation, heading, rules.rotation_speed);
+        if distance > ENGAGE_DISTANCE && off_by < THRUST_TOLERANCE {
+            actions.push(InputAction::Thrust);
+        }
+        if off_by < AIM_TOLERANCE && distance < rules.bullet_range {
+            actions.push(InputAction::Shoot);
+        }
+        actions
+    }
+}
+
+fn distance_sq(a: &Player, b: &Player) -> f32 {
+    let dx = b.x - a.x;
+    let dy = b.y - a.y;
+    dx * dx + dy * dy
+}
+
+/// Signed shortest turn from `from` to `to`, in `-PI..PI`.
+fn angle_between(from: f32, to: f32) -> f32 {
+    (to - from + PI).rem_euclid(TAU) - PI
+}
+
+/// One rotation step toward `heading`, nothing once a step would overshoot.
+fn steer(rotation: f32, heading: f32, rotation_speed: f32) -> Vec<InputAction> {
+    let turn = angle_between(rotation, heading);
+    if turn.abs() < rotation_speed {
+        Vec::new()
+    } else if turn > 0.0 {
+        vec![InputAction::RotateRight]
+    } else {
+        vec![InputAction::RotateLeft]
+    }
+}
+
+/// Turns toward `heading` and thrusts once roughly facing it.
+fn fly_toward(rotation: f32, heading: f32, rotation_speed: f32) -> Vec<InputAction> {
+    let mut actions = steer(rotation, heading, rotation_speed);
+    if angle_between(rotation, heading).abs() < THRUST_TOLERANCE {
+        actions.push(InputAction::Thrust);
+    }
+    actions
+}
+
+/// Offset from `me` to where a bullet fired now meets `target`, assuming it keeps its velocity.
+/// Bullets don't inherit the shooter's velocity, so only the target's motion matters.
+fn lead_target(me: &Player, target: &Player, bullet_speed: f32) -> (f32, f32) {
+    let (dx, dy) = (target.x - me.x, target.y - me.y);
+    let (vx, vy) = (target.vx, target.vy);
+
+    // |d + v t| = bullet_speed * t
+    let a = vx * vx + vy * vy - bullet_speed * bullet_speed;
+    let b = 2.0 * (dx * vx + dy * vy);
+    let c = dx * dx + dy * dy;
+
+    let t = if a.abs() < f32::EPSILON {
+        if b.abs() < f32::EPSILON {
+            0.0
+        } else {
+            (-c / b).max(0.0)
+        }
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            // target outruns our bullets, aim straight at it
+            0.0
+        } else {
+            let root = discriminant.sqrt();
+            [(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+                .into_iter()
+                .filter(|t| *t > 0.0)
+                .fold(f32::INFINITY, f32::min)
+        }
+    };
+    let t = if t.is_finite() { t } else { 0.0 };
+
+    (dx + vx * t, dy + vy * t)
+}
+
+/// Heading that takes `me` out of the path of the most urgent asteroid, if any is coming.
+fn dodge_heading(me: &Player, world: &GameWorld) -> Option<f32> {
+    world
+        .asteroids
+        .iter()
+        .filter_map(|asteroid| {
+            // asteroid relative to us, in position and velocity
+            let (rx, ry) = (asteroid.x - me.x, asteroid.y - me.y);
+            let (wx, wy) = (asteroid.vx - me.vx, asteroid.vy - me.vy);
+            let speed_sq = wx * wx + wy * wy;
+            if speed_sq < f32::EPSILON {
+                return None;
+            }
+
+            let t = (-(rx * wx + ry * wy) / speed_sq).clamp(0.0, DODGE_HORIZON_TICKS);
+            let (cx, cy) = (rx + wx * t, ry + wy * t);
+            if cx * cx + cy * cy > DODGE_RADIUS * DODGE_RADIUS {
+                return None;
+            }
+
+            // sideways to its path, on the side we are already on
+            let (mut px, mut py) = (-wy, wx);
+            if px * -rx + py * -ry < 0.0 {
+                (px, py) = (-px, -py);
+            }
+            Some((t, py.atan2(px)))
+        })
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, heading)| heading)
+}
+
+struct Bot {
+    behaviour: Box<dyn BotBehaviour>,
+    next_seq: u32,
+}
+
+/// Bots currently playing in the room, keyed by player id.
+#[derive(Default)]
+pub struct BotRoster {
+    bots: BTreeMap<u32, Bot>,
+    next_id: u32,
+}
+
+impl BotRoster {
+    pub fn len(&self) -> usize {
+        self.bots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bots.is_empty()
+    }
+
+    pub fn contains(&self, player_id: u32) -> bool {
+        self.bots.contains_key(&player_id)
+    }
+
+    /// Adds a bot and returns the player id it plays as.
+    pub fn spawn(&mut self, behaviour: Box<dyn BotBehaviour>) -> u32 {
+        let id = loop {
+            let id = BOT_ID_BASE.wrapping_add(self.next_id);
+            self.next_id = self.next_id.wrapping_add(1);
+            if !self.bots.contains_key(&id) {
+                break id;
+            }
+        };
+        self.bots.insert(
+            id,
+            Bot {
+                behaviour,
+                next_seq: 1,
+            },
+        );
+        id
+    }
+
+    /// Removes the most recently added bot, returning its player id.
+    pub fn retire(&mut self) -> Option<u32> {
+        self.bots.pop_last().map(|(id, _)| id)
+    }
+
+    pub fn remove(&mut self, player_id: u32) -> bool {
+        self.bots.remove(&player_id).is_some()
+    }
+
+    /// Asks every bot that is in the world for its inputs this tick.
+    pub fn think(&mut self, world: &GameWorld) -> Vec<PlayerInput> {
+        let mut inputs = Vec::new();
+        for (&id, bot) in &mut self.bots {
+            let Some(me) = world.players.get(&id) else {
+                continue;
+            };
+            for action in bot.behaviour.think(me, world) {
+                inputs.push(PlayerInput {
+                    id,
+                    seq: bot.next_seq,
+                    action,
+                });
+                bot.next_seq += 1;
+            }
+        }
+        inputs
+    }
+}
@@ -0,0 +1,177 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::warn;
+
+const DEFAULT_TICK_RATE_HZ: u32 = 60;
+const DEFAULT_SNAPSHOT_RATE_HZ: u32 = 30;
+const DEFAULT_ROOM_ID: u32 = 1;
+// a keyframe every 5 seconds at 60 Hz
+const DEFAULT_RECORDING_KEYFRAME_TICKS: u64 = 300;
+const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 600;
+const DEFAULT_SESSION_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_CAPACITY: u32 = 16;
+const DEFAULT_MATCH_DURATION_SECS: u64 = 600;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Fixed simulation rate of the game loop.
+    pub tick_rate_hz: u32,
+    /// Rate at which world snapshots are handed to the broadcaster.
+    pub snapshot_rate_hz: u32,
+    /// Identifier of the single room this process hosts, used in logs.
+    pub room_id: u32,
+    /// Address of the Prometheus scrape endpoint, local only by default.
+    pub metrics_addr: SocketAddr,
+    /// Address of the admin API, local only by default.
+    pub admin_addr: SocketAddr,
+    /// Bearer token for the admin API, the API stays off without one.
+    pub admin_token: Option<String>,
+    /// Directory matches are recorded to, recording stays off without one.
+    pub recording_dir: Option<PathBuf>,
+    /// Ticks between full world keyframes in a recording.
+    pub recording_keyframe_ticks: u64,
+    /// How long notifying clients and flushing files may take before the process exits anyway.
+    pub shutdown_timeout: Duration,
+    /// How long a draining server waits for its players to leave before shutting down.
+    pub drain_timeout: Duration,
+    /// Sessions that sent nothing for this long are ended, clients keep idle ones alive.
+    pub session_timeout: Duration,
+    /// Base URL of the auth service to register with, the server stays unlisted without one.
+    pub auth_url: Option<String>,
+    /// Secret shared with the auth service to verify login tokens, required.
+    pub jwt_secret: Option<String>,
+    /// Shared secret the auth service expects from game servers.
+    pub registration_token: Option<String>,
+    /// Name shown in the server list.
+    pub server_name: String,
+    /// Address advertised to clients, the server itself binds on all interfaces.
+    pub public_udp_addr: String,
+    pub region: String,
+    /// Game mode shown in the server list.
+    pub mode: String,
+    /// Players the room is meant for.
+    pub capacity: u32,
+    /// Encrypt UDP payloads, otherwise they are only authenticated.
+    pub encrypt_udp: bool,
+    /// Bots fill the room up to this many players and leave as humans join, 0 turns them off.
+    pub min_players: u32,
+    /// Simulated time after which a match ends and the next one starts, matches never end without one.
+    pub match_duration: Option<Duration>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            tick_rate_hz: DEFAULT_TICK_RATE_HZ,
+            snapshot_rate_hz: DEFAULT_SNAPSHOT_RATE_HZ,
+            room_id: DEFAULT_ROOM_ID,
+            metrics_addr: SocketAddr::from(([127, 0, 0, 1], 9100)),
+            admin_addr: SocketAddr::from(([127, 0, 0, 1], 9101)),
+            admin_token: None,
+            recording_dir: None,
+            recording_keyframe_ticks: DEFAULT_RECORDING_KEYFRAME_TICKS,
+            shutdown_timeout: Duration::from_millis(DEFAULT_SHUTDOWN_TIMEOUT_MS),
+            drain_timeout: Duration::from_secs(DEFAULT_DRAIN_TIMEOUT_SECS),
+            session_timeout: Duration::from_secs(DEFAULT_SESSION_TIMEOUT_SECS),
+            auth_url: None,
+            jwt_secret: None,
+            registration_token: None,
+            server_name: format!("room-{DEFAULT_ROOM_ID}"),
+            public_udp_addr: "127.0.0.1:8080".into(),
+            region: "local".into(),
+            mode: "deathmatch".into(),
+            capacity: DEFAULT_CAPACITY,
+            encrypt_udp: false,
+            min_players: 0,
+            match_duration: Some(Duration::from_secs(DEFAULT_MATCH_DURATION_SECS)),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads overrides from `TICK_RATE_HZ`, `SNAPSHOT_RATE_HZ`, `ROOM_ID`,
+    /// `METRICS_ADDR`, `ADMIN_ADDR`, `ADMIN_TOKEN`, `RECORDING_DIR`,
+    /// `RECORDING_KEYFRAME_TICKS`, `SHUTDOWN_TIMEOUT_MS`, `DRAIN_TIMEOUT_SECS`, `SESSION_TIMEOUT_SECS`,
+    /// `AUTH_URL`, `JWT_SECRET`, `SERVER_REGISTRATION_TOKEN`, `SERVER_NAME`, `PUBLIC_UDP_ADDR`,
+    /// `REGION`, `GAME_MODE`, `CAPACITY`, `ENCRYPT_UDP`, `MIN_PLAYERS` and `MATCH_DURATION_SECS`
+    /// (0 for endless matches), falling back to defaults.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let tick_rate_hz = env_or("TICK_RATE_HZ", defaults.tick_rate_hz).max(1);
+        // snapshots faster than the simulation would only repeat the same world
+        let snapshot_rate_hz =
+            env_or("SNAPSHOT_RATE_HZ", defaults.snapshot_rate_hz).clamp(1, tick_rate_hz);
+
+        let room_id = env_or("ROOM_ID", defaults.room_id);
+        let capacity = env_or("CAPACITY", defaults.capacity).max(1);
+
+        Self {
+            tick_rate_hz,
+            snapshot_rate_hz,
+            room_id,
+            metrics_addr: env_or("METRICS_ADDR", defaults.metrics_addr),
+            admin_addr: env_or("ADMIN_ADDR", defaults.admin_addr),
+            admin_token: env_opt("ADMIN_TOKEN"),
+            recording_dir: env_opt("RECORDING_DIR").map(PathBuf::from),
+            recording_keyframe_ticks: env_or(
+                "RECORDING_KEYFRAME_TICKS",
+                defaults.recording_keyframe_ticks,
+            )
+            .max(1),
+            shutdown_timeout: Duration::from_millis(env_or(
+                "SHUTDOWN_TIMEOUT_MS",
+                DEFAULT_SHUTDOWN_TIMEOUT_MS,
+            )),
+            drain_timeout: Duration::from_secs(env_or(
+                "DRAIN_TIMEOUT_SECS",
+                DEFAULT_DRAIN_TIMEOUT_SECS,
+            )),
+            session_timeout: Duration::from_secs(
+                env_or("SESSION_TIMEOUT_SECS", DEFAULT_SESSION_TIMEOUT_SECS).max(1),
+            ),
+            auth_url: env_opt("AUTH_URL").map(|url| url.trim_end_matches('/').to_string()),
+            jwt_secret: env_opt("JWT_SECRET"),
+            registration_token: env_opt("SERVER_REGISTRATION_TOKEN"),
+            server_name: env_opt("SERVER_NAME").unwrap_or_else(|| format!("room-{room_id}")),
+            public_udp_addr: env_or("PUBLIC_UDP_ADDR", defaults.public_udp_addr),
+            region: env_or("REGION", defaults.region),
+            mode: env_or("GAME_MODE", defaults.mode),
+            capacity,
+            encrypt_udp: env_or("ENCRYPT_UDP", defaults.encrypt_udp),
+            min_players: env_or("MIN_PLAYERS", defaults.min_players).min(capacity),
+            match_duration: Some(env_or("MATCH_DURATION_SECS", DEFAULT_MATCH_DURATION_SECS))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+        }
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.tick_rate_hz as f64)
+    }
+
+    /// Simulated milliseconds per tick, fed to the world so match time doesn't depend on the wall clock.
+    pub fn tick_ms(&self) -> u64 {
+        (1000 / self.tick_rate_hz as u64).max(1)
+    }
+
+    pub fn snapshot_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.snapshot_rate_hz as f64)
+    }
+}
+
+fn env_opt(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!(key, value, "Invalid config value, using default");
+            default
+        }),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,26 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use crate::lifecycle::Lifecycle;
+use crate::metrics::Metrics;
+use crate::network::NetStats;
+use crate::rate_limit::FloodGuard;
+use crate::registration::Registrar;
+use crate::session::Sessions;
+
+/// Banned addresses, checked by the listeners before anything else.
+pub type Bans = Arc<RwLock<HashSet<IpAddr>>>;
+
+/// Handles shared between the game loop and the networking tasks.
+#[derive(Clone)]
+pub struct ServerContext {
+    pub sessions: Sessions,
+    pub bans: Bans,
+    pub flood_guard: Arc<FloodGuard>,
+    pub metrics: Arc<Metrics>,
+    pub net_stats: Arc<NetStats>,
+    pub lifecycle: Arc<Lifecycle>,
+    /// Set when the server is listed with the auth service, which also takes match results.
+    pub registrar: Option<Arc<Registrar>>,
+}
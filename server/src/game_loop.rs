@@ -0,0 +1,706 @@
+use common::game_world::GameWorld;
+use common::packet::{PlayerInput, ServerPacket};
+use common::registry::MatchResultRequest;
+use common::rules::GameRules;
+use common::utils::{WorldRng, current_time_ms};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{io, mem};
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, info, trace, trace_span, warn};
+
+use crate::admin::{AdminCommand, RoomInfo, SessionInfo};
+use crate::bots::{BotRoster, Hunter};
+use crate::config::ServerConfig;
+use crate::context::ServerContext;
+use crate::lifecycle::ServerState;
+use crate::rate_limit::Verdict;
+use crate::recording::{MatchRecorder, RecordedEvent};
+use crate::session::SessionKind;
+use crate::stats::MatchLedger;
+use crate::tokens::PlayerIdentity;
+
+/// Told to players when a draining server's last match ends.
+const DRAINED_MATCH_REASON: &str = "Server is restarting";
+
+pub struct GameLoopChannels {
+    /// Decoded inputs from the UDP listener.
+    pub inputs: Receiver<(SocketAddr, PlayerInput)>,
+    /// Players and spectators that completed the handshake with a valid token.
+    pub joins: Receiver<(SocketAddr, PlayerIdentity, SessionKind)>,
+    /// Latest-value handoff to the broadcaster, never waited on.
+    pub snapshots: watch::Sender<Arc<ServerPacket>>,
+    /// One-off packets every client should get, e.g. notices.
+    pub events: broadcast::Sender<ServerPacket>,
+    /// Requests from the admin API.
+    pub admin: Receiver<AdminCommand>,
+}
+
+/// Why the game loop stopped, plus writes that should finish before the process exits.
+pub struct Stopped {
+    pub reason: String,
+    pub pending_writes: Vec<JoinHandle<io::Result<()>>>,
+}
+
+/// Owns the world and is the only place it gets mutated.
+pub struct GameLoop {
+    config: ServerConfig,
+    channels: GameLoopChannels,
+    ctx: ServerContext,
+    world: GameWorld,
+    /// Drives the world's randomness, kept out of the snapshots clients receive.
+    rng: WorldRng,
+    addr_to_id: HashMap<SocketAddr, u32>,
+    usernames: HashMap<u32, String>,
+    /// Usernames of accounts watching without a ship.
+    spectators: HashMap<u32, String>,
+    bots: BotRoster,
+    tick: u64,
+    next_snapshot: Instant,
+    recorder: Option<MatchRecorder>,
+    /// Stats of the running match and when it started, in unix seconds.
+    ledger: MatchLedger,
+    match_started_at: u64,
+    draining_since: Option<Instant>,
+    pending_writes: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl GameLoop {
+    pub fn new(config: ServerConfig, channels: GameLoopChannels, ctx: ServerContext) -> Self {
+        let seed = rand::random();
+        let mut world = GameWorld::with_rules(GameRules::default());
+        world.tick_ms = config.tick_ms();
+
+        let mut game_loop = Self {
+            config,
+            channels,
+            ctx,
+            world,
+            rng: WorldRng::new(seed),
+            addr_to_id: HashMap::new(),
+            usernames: HashMap::new(),
+            spectators: HashMap::new(),
+            bots: BotRoster::default(),
+            tick: 0,
+            next_snapshot: Instant::now(),
+            recorder: None,
+            ledger: MatchLedger::default(),
+            match_started_at: current_time_ms() / 1000,
+            draining_since: None,
+            pending_writes: Vec::new(),
+        };
+        game_loop.start_recording(seed);
+        game_loop
+    }
+
+    /// Runs until shutdown is requested or a draining server runs out of players.
+    pub async fn run(mut self) -> Stopped {
+        let tick_budget = self.config.tick_duration();
+        let mut interval = tokio::time::interval(tick_budget);
+        self.ctx.metrics.rooms.set(1);
+
+        let reason = loop {
+            interval.tick().await;
+            if let Some(reason) = self.stop_reason() {
+                break reason;
+            }
+            self.tick += 1;
+            let tick_started = Instant::now();
+
+            trace_span!("tick", tick = self.tick).in_scope(|| self.step());
+
+            let elapsed = tick_started.elapsed();
+            self.ctx
+                .metrics
+                .tick_duration
+                .observe(elapsed.as_secs_f64());
+            if elapsed > tick_budget {
+                self.ctx.metrics.tick_overruns.inc();
+            }
+        };
+
+        info!(%reason, tick = self.world.tick, "Game loop stopped");
+        self.ctx.metrics.rooms.set(0);
+        self.finish_match();
+        if let Some(recorder) = self.recorder.take() {
+            self.pending_writes.push(recorder.finish(&self.world));
+        }
+
+        Stopped {
+            reason,
+            pending_writes: mem::take(&mut self.pending_writes),
+        }
+    }
+
+    /// Checks whether the loop should stop before the next tick.
+    fn stop_reason(&mut self) -> Option<String> {
+        match self.ctx.lifecycle.state() {
+            ServerState::Running => None,
+            ServerState::ShuttingDown { reason } => Some(reason),
+            ServerState::Draining => {
+                let humans = self.humans();
+                let since = *self.draining_since.get_or_insert_with(|| {
+                    info!(players = humans, "Draining");
+                    Instant::now()
+                });
+
+                let reason = if humans == 0 {
+                    "Server drained"
+                } else if since.elapsed() >= self.config.drain_timeout {
+                    "Server is restarting"
+                } else {
+                    return None;
+                };
+                self.ctx.lifecycle.shut_down(reason);
+                Some(reason.into())
+            }
+        }
+    }
+
+    fn step(&mut self) {
+        self.handle_admin_commands();
+        self.accept_joins();
+        self.drop_gone_sessions();
+        self.balance_bots();
+        self.apply_inputs();
+        self.run_bots();
+        self.kick_blocked();
+        self.publish_snapshot(self.config.snapshot_interval());
+
+        self.world.update(&mut self.rng);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.end_tick(&self.world, &self.rng);
+        }
+        if self.match_time_is_up() {
+            self.end_match();
+        }
+        self.ctx.metrics.players.set(self.humans() as i64);
+        self.ctx.metrics.bots.set(self.bots.len() as i64);
+        self.ctx
+            .metrics
+            .spectators
+            .set(self.spectators.len() as i64);
+        if let Some(registrar) = &self.ctx.registrar {
+            registrar.set_online_players(self.online_players());
+        }
+    }
+
+    fn accept_joins(&mut self) {
+        while let Ok((addr, identity, kind)) = self.channels.joins.try_recv() {
+            let player_id = identity.player_id;
+            match kind {
+                SessionKind::Player => {
+                    info!(player_id, username = %identity.username, %addr, "Player joined");
+                    self.spectators.remove(&player_id);
+                    // a player joining again gets a fresh ship, the old one's stats still count
+                    if let Some(previous) = self.world.players.get(&player_id) {
+                        self.ledger.leave(previous, self.world.time_ms);
+                    }
+                    self.ledger
+                        .join(player_id, &identity.username, self.world.time_ms);
+                    self.usernames.insert(player_id, identity.username);
+                    self.world.add_player(player_id);
+                    self.record(RecordedEvent::Connect { player_id });
+                }
+                SessionKind::Spectator => {
+                    info!(player_id, username = %identity.username, %addr, "Spectator joined");
+                    // the handshake already replaced the old session, only the ship is left
+                    self.addr_to_id.retain(|_, id| *id != player_id);
+                    if let Some(ship) = self.world.remove_player(player_id) {
+                        self.ledger.leave(&ship, self.world.time_ms);
+                        self.usernames.remove(&player_id);
+                        self.record(RecordedEvent::Disconnect { player_id });
+                    }
+                    self.spectators.insert(player_id, identity.username);
+                }
+            }
+        }
+    }
+
+    /// Accounts in the room, with a ship or watching.
+    fn online_players(&self) -> Vec<u32> {
+        let mut player_ids: Vec<u32> = self
+            .usernames
+            .keys()
+            .chain(self.spectators.keys())
+            .copied()
+            .collect();
+        player_ids.sort_unstable();
+        player_ids
+    }
+
+    /// Drops players and spectators whose session timed out or who left.
+    fn drop_gone_sessions(&mut self) {
+        let expired = self
+            .ctx
+            .sessions
+            .lock()
+            .unwrap()
+            .expire(self.config.session_timeout);
+        for (player_id, addr) in expired {
+            info!(player_id, %addr, "Session timed out");
+        }
+
+        let gone: Vec<u32> = {
+            let sessions = self.ctx.sessions.lock().unwrap();
+            self.usernames
+                .keys()
+                .chain(self.spectators.keys())
+                .copied()
+                .filter(|player_id| !sessions.has_player(*player_id))
+                .collect()
+        };
+        for player_id in gone {
+            self.end_player(player_id);
+            info!(player_id, "Player left");
+        }
+    }
+
+    /// Players in the world that aren't bots.
+    fn humans(&self) -> usize {
+        self.world.players.len() - self.bots.len()
+    }
+
+    /// Adds bots until the room has `min_players`, and retires them as humans take their place.
+    /// A room that stopped accepting players lets its bots go.
+    fn balance_bots(&mut self) {
+        let wanted = if self.ctx.lifecycle.accepts_players() {
+            (self.config.min_players as usize).saturating_sub(self.humans())
+        } else {
+            0
+        };
+
+        while self.bots.len() < wanted {
+            let player_id = self.bots.spawn(Box::new(Hunter));
+            debug!(player_id, "Bot joined");
+            self.world.add_bot(player_id);
+            self.record(RecordedEvent::ConnectBot { player_id });
+        }
+        while self.bots.len() > wanted {
+            let Some(player_id) = self.bots.retire() else {
+                break;
+            };
+            debug!(player_id, "Bot left");
+            self.remove_player(player_id);
+        }
+    }
+
+    /// Applies what the bots decided, like inputs that arrived over the network.
+    fn run_bots(&mut self) {
+        for input in self.bots.think(&self.world) {
+            let id = input.id;
+            self.world.apply_input(id, &input);
+            self.record(RecordedEvent::Input {
+                player_id: id,
+                input,
+            });
+        }
+    }
+
+    fn record(&mut self, event: RecordedEvent) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(event);
+        }
+    }
+
+    /// Starts recording the current world as a new match, if recording is enabled.
+    fn start_recording(&mut self, seed: u64) {
+        let Some(dir) = &self.config.recording_dir else {
+            return;
+        };
+        self.recorder = Some(MatchRecorder::start(
+            dir,
+            self.config.room_id,
+            self.config.tick_rate_hz,
+            seed,
+            self.config.recording_keyframe_ticks,
+            &self.world,
+            &self.rng,
+        ));
+    }
+
+    fn match_time_is_up(&self) -> bool {
+        self.config
+            .match_duration
+            .is_some_and(|duration| self.world.time_ms >= duration.as_millis() as u64)
+    }
+
+    /// Ends a match that ran its course, announces the winners and starts the next one.
+    /// A draining server doesn't start another, the room shuts down once the match is over.
+    fn end_match(&mut self) {
+        let winners = self.finish_match();
+        let message = if winners.is_empty() {
+            "Match over".to_string()
+        } else {
+            format!("Match over, {} won", winners.join(", "))
+        };
+        info!(winners = ?winners, "Match over");
+        let _ = self.channels.events.send(ServerPacket::Notice(message));
+
+        if self.ctx.lifecycle.state() == ServerState::Draining {
+            self.ctx.lifecycle.shut_down(DRAINED_MATCH_REASON);
+            return;
+        }
+        self.restart_match();
+    }
+
+    /// Reports the stats of the running match, returning its winners.
+    /// Whatever happens next starts from an empty ledger.
+    fn finish_match(&mut self) -> Vec<String> {
+        let ended_at = current_time_ms() / 1000;
+        let started_at = mem::replace(&mut self.match_started_at, ended_at);
+        let outcome = mem::take(&mut self.ledger).finish(&self.world);
+
+        if !outcome.players.is_empty()
+            && let Some(registrar) = &self.ctx.registrar
+        {
+            let result = MatchResultRequest {
+                room_id: self.config.room_id,
+                mode: self.config.mode.clone(),
+                started_at,
+                ended_at,
+                players: outcome.players,
+            };
+            self.pending_writes.retain(|write| !write.is_finished());
+            self.pending_writes.extend(registrar.report_match(result));
+        }
+        outcome.winners
+    }
+
+    fn restart_match(&mut self) {
+        self.pending_writes.retain(|write| !write.is_finished());
+        if let Some(recorder) = self.recorder.take() {
+            self.pending_writes.push(recorder.finish(&self.world));
+        }
+        let seed = rand::random();
+        self.rng = WorldRng::new(seed);
+        self.world.restart();
+        // the new match counts everyone still in the world from its first tick
+        for player in self.world.players.values().filter(|player| !player.is_bot) {
+            if let Some(username) = self.usernames.get(&player.id) {
+                self.ledger.join(player.id, username, 0);
+            }
+        }
+        self.start_recording(seed);
+    }
+
+    fn apply_inputs(&mut self) {
+        while let Ok((addr, input)) = self.channels.inputs.try_recv() {
+            if self.ctx.flood_guard.admit_input(addr) != Verdict::Accept {
+                continue;
+            }
+            let id = input.id;
+            self.addr_to_id.insert(addr, id);
+            self.world.apply_input(id, &input);
+            trace!(player_id = id, seq = input.seq, action = ?input.action, "Applied input");
+            self.record(RecordedEvent::Input {
+                player_id: id,
+                input,
+            });
+        }
+        self.ctx.flood_guard.end_tick();
+    }
+
+    /// Kicks sessions the flood guard blocked this tick.
+    fn kick_blocked(&mut self) {
+        let kicked: Vec<SocketAddr> = self
+            .addr_to_id
+            .keys()
+            .filter(|addr| self.ctx.flood_guard.is_blocked(addr))
+            .cloned()
+            .collect();
+
+        for addr in kicked {
+            if let Some(id) = self.disconnect(addr) {
+                warn!(player_id = id, %addr, "Kicked player for flooding");
+            }
+        }
+    }
+
+    /// Removes the session behind `addr` and its player, returning the player id.
+    fn disconnect(&mut self, addr: SocketAddr) -> Option<u32> {
+        let id = self.addr_to_id.remove(&addr);
+        if let Some(id) = id {
+            self.remove_player(id);
+        }
+        self.ctx.sessions.lock().unwrap().remove_addr(&addr);
+        self.ctx.flood_guard.forget(&addr);
+        id
+    }
+
+    /// Removes a player from the world, returning whether it was there.
+    fn remove_player(&mut self, player_id: u32) -> bool {
+        let removed = match self.world.remove_player(player_id) {
+            Some(ship) => {
+                self.ledger.leave(&ship, self.world.time_ms);
+                true
+            }
+            None => false,
+        };
+        self.usernames.remove(&player_id);
+        self.bots.remove(player_id);
+        self.ctx.sessions.lock().unwrap().remove_player(player_id);
+        if removed {
+            self.record(RecordedEvent::Disconnect { player_id });
+        }
+        removed
+    }
+
+    fn handle_admin_commands(&mut self) {
+        while let Ok(command) = self.channels.admin.try_recv() {
+            match command {
+                AdminCommand::ListSessions(reply) => {
+                    let _ = reply.send(self.sessions());
+                }
+                AdminCommand::ListRooms(reply) => {
+                    let _ = reply.send(vec![self.room_info()]);
+                }
+                AdminCommand::Kick { player_id, reply } => {
+                    let _ = reply.send(self.kick_player(player_id));
+                }
+                AdminCommand::Ban { ip, reply } => {
+                    let _ = reply.send(self.ban(ip));
+                }
+                AdminCommand::GetRules(reply) => {
+                    let _ = reply.send(self.world.rules.clone());
+                }
+                AdminCommand::SetRules { rules, reply } => {
+                    self.world.set_rules(rules.clone());
+                    self.record(RecordedEvent::SetRules(rules));
+                    let _ = reply.send(());
+                }
+                AdminCommand::RestartMatch(reply) => {
+                    self.finish_match();
+                    self.restart_match();
+                    info!("Match restarted");
+                    let _ = reply.send(());
+                }
+                AdminCommand::Notice { message, reply } => {
+                    let _ = self.channels.events.send(ServerPacket::Notice(message));
+                    let _ = reply.send(());
+                }
+                AdminCommand::Drain(reply) => {
+                    let _ = reply.send(self.ctx.lifecycle.drain());
+                }
+                AdminCommand::Shutdown { reason, reply } => {
+                    self.ctx.lifecycle.shut_down(reason);
+                    let _ = reply.send(());
+                }
+            }
+        }
+    }
+
+    fn sessions(&self) -> Vec<SessionInfo> {
+        self.world
+            .players
+            .values()
+            .filter(|player| !player.is_bot)
+            .map(|player| SessionInfo {
+                player_id: player.id,
+                username: self.usernames.get(&player.id).cloned(),
+                addr: self
+                    .addr_to_id
+                    .iter()
+                    .find(|(_, id)| **id == player.id)
+                    .map(|(addr, _)| *addr),
+                hp: player.hp,
+            })
+            .collect()
+    }
+
+    fn room_info(&self) -> RoomInfo {
+        RoomInfo {
+            room_id: self.config.room_id,
+            tick: self.tick,
+            players: self.humans(),
+            spectators: self.spectators.len(),
+            bots: self.bots.len(),
+            bullets: self.world.bullets.len(),
+            asteroids: self.world.asteroids.len(),
+        }
+    }
+
+    /// Kicks a player, dropping its session so it has to handshake again.
+    fn kick_player(&mut self, player_id: u32) -> bool {
+        let found = self.end_player(player_id);
+        if found {
+            info!(player_id, "Kicked player");
+        }
+        found
+    }
+
+    /// Removes a player or spectator with its sessions, returning whether it was here.
+    fn end_player(&mut self, player_id: u32) -> bool {
+        let addrs: Vec<SocketAddr> = self
+            .addr_to_id
+            .iter()
+            .filter(|(_, id)| **id == player_id)
+            .map(|(addr, _)| *addr)
+            .collect();
+
+        let mut found = false;
+        for addr in addrs {
+            found |= self.disconnect(addr).is_some();
+        }
+
+        // players that haven't sent a datagram yet have no address
+        found |= self.remove_player(player_id);
+        if self.spectators.remove(&player_id).is_some() {
+            self.ctx.sessions.lock().unwrap().remove_player(player_id);
+            found = true;
+        }
+        found
+    }
+
+    fn ban(&mut self, ip: IpAddr) -> usize {
+        self.ctx.bans.write().unwrap().insert(ip);
+
+        let addrs: Vec<SocketAddr> = self
+            .addr_to_id
+            .keys()
+            .filter(|addr| addr.ip() == ip)
+            .cloned()
+            .collect();
+
+        let mut kicked = 0;
+        for addr in addrs {
+            if let Some(player_id) = self.disconnect(addr) {
+                info!(player_id, %addr, "Kicked banned player");
+                kicked += 1;
+            }
+        }
+
+        // spectators and players that haven't sent an input yet never show up in addr_to_id
+        let unmapped = self.ctx.sessions.lock().unwrap().remove_ip(ip);
+        for player_id in unmapped {
+            if self.end_player(player_id) {
+                info!(player_id, "Kicked banned player");
+                kicked += 1;
+            }
+        }
+        kicked
+    }
+
+    fn publish_snapshot(&mut self, snapshot_interval: Duration) {
+        let now = Instant::now();
+        if now < self.next_snapshot {
+            return;
+        }
+
+        // overwrites a snapshot the broadcaster hasn't picked up yet
+        self.channels
+            .snapshots
+            .send_replace(Arc::new(ServerPacket::Snapshot(self.world.clone())));
+        self.next_snapshot += snapshot_interval;
+        if self.next_snapshot < now {
+            self.next_snapshot = now + snapshot_interval;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::Lifecycle;
+    use crate::metrics::Metrics;
+    use crate::network::NetStats;
+    use crate::rate_limit::{FloodConfig, FloodGuard, FloodStats};
+    use crate::session::SessionTable;
+    use common::secure::SessionKey;
+    use std::collections::HashSet;
+    use std::sync::{Mutex, RwLock};
+    use tokio::sync::mpsc;
+
+    fn test_context() -> ServerContext {
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let flood_stats = FloodStats::new(metrics.registry()).unwrap();
+        ServerContext {
+            sessions: Arc::new(Mutex::new(SessionTable::default())),
+            bans: Arc::new(RwLock::new(HashSet::new())),
+            flood_guard: Arc::new(FloodGuard::new(FloodConfig::default(), flood_stats)),
+            net_stats: Arc::new(NetStats::new(metrics.registry()).unwrap()),
+            metrics,
+            lifecycle: Arc::new(Lifecycle::new()),
+            registrar: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn draining_stops_when_the_match_ends() {
+        let ctx = test_context();
+        let config = ServerConfig {
+            tick_rate_hz: 1000,
+            snapshot_rate_hz: 1000,
+            match_duration: Some(Duration::from_millis(500)),
+            ..ServerConfig::default()
+        };
+        let (_input_tx, inputs) = mpsc::channel(1);
+        let (join_tx, joins) = mpsc::channel(1);
+        let (snapshots, mut snapshot_rx) =
+            watch::channel(Arc::new(ServerPacket::Snapshot(GameWorld::new())));
+        let (events, mut event_rx) = broadcast::channel(16);
+        let (_admin_tx, admin) = mpsc::channel(1);
+        let channels = GameLoopChannels {
+            inputs,
+            joins,
+            snapshots,
+            events,
+            admin,
+        };
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 4000));
+        ctx.sessions.lock().unwrap().create(
+            7,
+            SessionKind::Player,
+            addr,
+            &SessionKey([0; 32]),
+            false,
+        );
+        let identity = PlayerIdentity {
+            player_id: 7,
+            username: "alice".into(),
+            ticket: None,
+        };
+        join_tx
+            .send((addr, identity, SessionKind::Player))
+            .await
+            .unwrap();
+
+        let lifecycle = ctx.lifecycle.clone();
+        let game_loop = tokio::spawn(GameLoop::new(config, channels, ctx).run());
+        snapshot_rx
+            .wait_for(|packet| {
+                matches!(&**packet, ServerPacket::Snapshot(world)
+                    if world.players.contains_key(&7) && world.time_ms >= 100)
+            })
+            .await
+            .unwrap();
+        assert!(lifecycle.drain());
+
+        // the drain timeout is ten minutes, only the end of the match can stop the loop this soon
+        let stopped = tokio::time::timeout(Duration::from_secs(5), game_loop)
+            .await
+            .expect("game loop kept running after the match")
+            .unwrap();
+        assert_eq!(stopped.reason, DRAINED_MATCH_REASON);
+
+        let mut notices = Vec::new();
+        while let Ok(packet) = event_rx.try_recv() {
+            if let ServerPacket::Notice(message) = packet {
+                notices.push(message);
+            }
+        }
+        assert_eq!(notices, ["Match over"]);
+
+        let ServerPacket::Snapshot(world) = &**snapshot_rx.borrow() else {
+            panic!("expected a snapshot");
+        };
+        // snapshots are skipped when ticks bunch up, the last one may predate the final tick
+        assert!(world.time_ms >= 100, "a new match started");
+    }
+}
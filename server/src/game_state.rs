@@ -11,4 +11,8 @@ impl GameState {
             world_state: game_world
         }
     }
+
+    pub fn world(&self) -> &GameWorld {
+        &self.world_state
+    }
 }
@@ -0,0 +1,207 @@
+use common::handshake::{
+    self, CONNECT_PADDED_LEN, COOKIE_MAC_LEN, Cookie, HandshakeRequest, HandshakeResponse,
+    RejectReason,
+};
+use common::packet::PROTOCOL_VERSION;
+use common::secure::{KeyExchange, PUBLIC_KEY_LEN};
+use common::utils::current_time_ms;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::lifecycle::Lifecycle;
+use crate::session::{SessionKind, Sessions};
+use crate::tokens::{PlayerIdentity, TicketBook, TokenVerifier};
+
+/// How long a client has to come back with its cookie.
+pub const COOKIE_LIFETIME_SECS: u64 = 10;
+
+/// Issues and checks handshake cookies without keeping any state per client.
+pub struct CookieJar {
+    secret: [u8; 32],
+}
+
+impl Default for CookieJar {
+    fn default() -> Self {
+        let mut secret = [0u8; 32];
+        rand::rng().fill_bytes(&mut secret);
+        Self { secret }
+    }
+}
+
+impl CookieJar {
+    pub fn issue(&self, addr: SocketAddr) -> Cookie {
+        let issued_at = current_time_ms() / 1000;
+        Cookie {
+            issued_at,
+            mac: self.mac(addr, issued_at),
+        }
+    }
+
+    /// True for a cookie issued to `addr` within the last [`COOKIE_LIFETIME_SECS`].
+    pub fn verify(&self, addr: SocketAddr, cookie: &Cookie) -> bool {
+        let now = current_time_ms() / 1000;
+        if cookie.issued_at > now || now - cookie.issued_at > COOKIE_LIFETIME_SECS {
+            return false;
+        }
+
+        self.hmac(addr, cookie.issued_at)
+            .verify_truncated_left(&cookie.mac)
+            .is_ok()
+    }
+
+    fn mac(&self, addr: SocketAddr, issued_at: u64) -> [u8; COOKIE_MAC_LEN] {
+        let digest = self.hmac(addr, issued_at).finalize().into_bytes();
+        let mut mac = [0u8; COOKIE_MAC_LEN];
+        mac.copy_from_slice(&digest[..COOKIE_MAC_LEN]);
+        mac
+    }
+
+    fn hmac(&self, addr: SocketAddr, issued_at: u64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(addr.to_string().as_bytes());
+        mac.update(&issued_at.to_be_bytes());
+        mac
+    }
+}
+
+/// What came of one handshake datagram.
+pub struct HandshakeOutcome {
+    /// Datagram to send back, never longer than the one that was received.
+    pub reply: Option<Vec<u8>>,
+    /// Set once a session was created for the sender.
+    pub joined: Option<(PlayerIdentity, SessionKind)>,
+    /// Set when the sender was turned away.
+    pub rejected: Option<RejectReason>,
+}
+
+/// Everything the handshake needs to decide on a player and set up its session.
+pub struct Gatekeeper {
+    pub verifier: TokenVerifier,
+    pub lifecycle: Arc<Lifecycle>,
+    pub sessions: Sessions,
+    pub cookies: CookieJar,
+    pub tickets: Arc<TicketBook>,
+    /// Player sessions the room takes, tickets or not.
+    pub capacity: usize,
+    /// Encrypt UDP payloads instead of only authenticating them.
+    pub encrypt_udp: bool,
+}
+
+impl Gatekeeper {
+    /// Answers one handshake datagram from `addr`. Only a valid cookie creates a
+    /// session, which binds to `addr` right away since the cookie proved it receives there.
+    pub fn handle(&self, addr: SocketAddr, datagram: &[u8]) -> HandshakeOutcome {
+        let mut outcome = HandshakeOutcome {
+            reply: None,
+            joined: None,
+            rejected: None,
+        };
+        let Some(request) = handshake::decode_datagram::<HandshakeRequest>(datagram) else {
+            return outcome;
+        };
+
+        let response = match request {
+            HandshakeRequest::Connect { protocol_version } => {
+                // padding is what keeps the challenge from amplifying anything
+                if datagram.len() < CONNECT_PADDED_LEN {
+                    return outcome;
+                }
+                if protocol_version != PROTOCOL_VERSION {
+                    HandshakeResponse::Rejected(RejectReason::ProtocolMismatch)
+                } else {
+                    HandshakeResponse::Challenge(self.cookies.issue(addr))
+                }
+            }
+            HandshakeRequest::Login {
+                protocol_version,
+                cookie,
+                token,
+                spectate,
+                public_key,
+            } => {
+                let kind = if spectate {
+                    SessionKind::Spectator
+                } else {
+                    SessionKind::Player
+                };
+                match self.login(addr, protocol_version, &cookie, &token, public_key, kind) {
+                    Ok((identity, response)) => {
+                        outcome.joined = Some((identity, kind));
+                        response
+                    }
+                    Err(reason) => HandshakeResponse::Rejected(reason),
+                }
+            }
+        };
+
+        if let HandshakeResponse::Rejected(reason) = response {
+            outcome.rejected = Some(reason);
+        }
+        outcome.reply = handshake::encode_datagram(&response, 0)
+            .ok()
+            .filter(|reply| reply.len() <= datagram.len());
+        outcome
+    }
+
+    fn login(
+        &self,
+        addr: SocketAddr,
+        protocol_version: u16,
+        cookie: &Cookie,
+        token: &str,
+        client_public: [u8; PUBLIC_KEY_LEN],
+        kind: SessionKind,
+    ) -> Result<(PlayerIdentity, HandshakeResponse), RejectReason> {
+        if protocol_version != PROTOCOL_VERSION {
+            return Err(RejectReason::ProtocolMismatch);
+        }
+        if !self.cookies.verify(addr, cookie) {
+            return Err(RejectReason::InvalidCookie);
+        }
+        if !self.lifecycle.accepts_players() {
+            return Err(RejectReason::NotAccepting);
+        }
+        // checked before the ticket is spent, a bad key shouldn't burn it
+        let exchange = KeyExchange::new();
+        let session_key = exchange
+            .session_key(client_public, &cookie.mac)
+            .ok_or(RejectReason::InvalidKey)?;
+        let identity = self.verifier.verify(token)?;
+
+        // checked and filled under one lock, so two logins can't take the last slot
+        let mut sessions = self.sessions.lock().unwrap();
+        if kind == SessionKind::Player
+            && sessions.other_players(identity.player_id, addr) >= self.capacity
+        {
+            return Err(RejectReason::RoomFull);
+        }
+        if let Some(ticket) = &identity.ticket
+            && !self.tickets.redeem(ticket, addr)
+        {
+            return Err(RejectReason::InvalidToken);
+        }
+
+        let encrypted = self.encrypt_udp;
+        let session_id = sessions.create(identity.player_id, kind, addr, &session_key, encrypted);
+        let public_key = exchange.public_key();
+
+        let response = match kind {
+            SessionKind::Player => HandshakeResponse::Accepted {
+                player_id: identity.player_id,
+                session_id,
+                public_key,
+                encrypted,
+            },
+            SessionKind::Spectator => HandshakeResponse::Spectating {
+                session_id,
+                public_key,
+                encrypted,
+            },
+        };
+        Ok((identity, response))
+    }
+}
@@ -1,2 +1,17 @@
+pub mod admin;
+pub mod bots;
+pub mod config;
+pub mod context;
+pub mod game_loop;
 pub mod game_state;
-pub mod network;
\ No newline at end of file
+pub mod handshake;
+pub mod lifecycle;
+pub mod metrics;
+pub mod network;
+pub mod rate_limit;
+pub mod recording;
+pub mod registration;
+pub mod replay;
+pub mod session;
+pub mod stats;
+pub mod tokens;
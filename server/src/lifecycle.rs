@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+pub const DEFAULT_SHUTDOWN_REASON: &str = "Server is shutting down";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerState {
+    Running,
+    /// Current players keep playing, nobody new gets in.
+    Draining,
+    ShuttingDown {
+        reason: String,
+    },
+}
+
+/// Where the server is between starting up and exiting, shared by every task.
+pub struct Lifecycle {
+    state: watch::Sender<ServerState>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            state: watch::Sender::new(ServerState::Running),
+        }
+    }
+
+    pub fn state(&self) -> ServerState {
+        self.state.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ServerState> {
+        self.state.subscribe()
+    }
+
+    pub fn accepts_players(&self) -> bool {
+        *self.state.borrow() == ServerState::Running
+    }
+
+    /// Returns false if the server is already draining or shutting down.
+    pub fn drain(&self) -> bool {
+        self.state.send_if_modified(|state| {
+            if *state != ServerState::Running {
+                return false;
+            }
+            *state = ServerState::Draining;
+            true
+        })
+    }
+
+    /// Keeps the first reason if shutdown was already requested.
+    pub fn shut_down(&self, reason: impl Into<String>) {
+        let reason = reason.into();
+        self.state.send_if_modified(|state| {
+            if matches!(state, ServerState::ShuttingDown { .. }) {
+                return false;
+            }
+            *state = ServerState::ShuttingDown { reason };
+            true
+        });
+    }
+}
+
+/// Turns the first SIGINT or SIGTERM into a graceful shutdown, a second one exits right away.
+pub async fn watch_signals(lifecycle: Arc<Lifecycle>) {
+    let (mut interrupt, mut terminate) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+        (Err(e), _) | (_, Err(e)) => {
+            error!(error = %e, "Failed to install signal handlers");
+            return;
+        }
+    };
+
+    let mut requested = false;
+    loop {
+        tokio::select! {
+            _ = interrupt.recv() => {}
+            _ = terminate.recv() => {}
+        }
+
+        if requested {
+            warn!("Second shutdown signal, exiting immediately");
+            std::process::exit(1);
+        }
+        requested = true;
+        info!("Shutdown signal received");
+        lifecycle.shut_down(DEFAULT_SHUTDOWN_REASON);
+    }
+}
@@ -1,137 +1,214 @@
-use bincode::config;
-use common::{game_world::GameWorld, packet::PlayerInput};
+use common::game_world::GameWorld;
+use common::packet::{PlayerInput, ServerPacket};
+use common::telemetry;
+use server::admin::{self, AdminCommand};
+use server::config::ServerConfig;
+use server::context::ServerContext;
+use server::game_loop::{GameLoop, GameLoopChannels};
+use server::handshake::{CookieJar, Gatekeeper};
+use server::lifecycle::{self, Lifecycle};
+use server::metrics::{self, Metrics};
+use server::network::{self, NetStats, supervise};
+use server::rate_limit::{FloodConfig, FloodGuard, FloodStats, FloodStatsSnapshot};
+use server::registration::Registrar;
+use server::session::{SessionKind, SessionTable};
+use server::tokens::{PlayerIdentity, TicketBook, TokenVerifier};
 use std::collections::HashSet;
+use std::io;
 use std::net::SocketAddr;
-use std::sync::atomic::AtomicU32;
-use std::{io, sync::Arc};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::sync::{Arc, Mutex, RwLock};
 use tokio::net::{TcpListener, UdpSocket};
-use tokio::sync::Mutex;
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::mpsc;
+use tokio::sync::{broadcast, watch};
+use tracing::{Instrument, error, info, info_span, warn};
 
-const TICK_DURATION: u64 = 16;
+const FLOOD_REPORT_INTERVAL: u64 = 10;
+const EVENT_BUFFER: usize = 64;
+const ADMIN_BUFFER: usize = 16;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
+    telemetry::init_tracing();
+
+    let server_config = ServerConfig::from_env();
+    info!(
+        tick_rate_hz = server_config.tick_rate_hz,
+        snapshot_rate_hz = server_config.snapshot_rate_hz,
+        "Starting game server"
+    );
+
+    let Some(jwt_secret) = server_config.jwt_secret.clone() else {
+        error!("JWT_SECRET is not set, players could not be verified");
+        return Err(io::Error::other("missing JWT_SECRET"));
+    };
+
     let bind = "0.0.0.0:8080";
-    let bind_tcp = "0.0.0.0:8081";
     let socket = Arc::new(UdpSocket::bind(bind).await?);
-    let tcp_socket = Arc::new(TcpListener::bind(bind_tcp).await?);
 
     let (input_tx, input_rx) = mpsc::channel::<(SocketAddr, PlayerInput)>(1024);
-    let (snapshot_tx, mut snapshot_rx) = mpsc::channel::<GameWorld>(1024);
-    let (input_tcp_tx, input_tcp_rx) = mpsc::channel::<(SocketAddr, u32)>(128);
-
-    let clients = Arc::new(Mutex::new(HashSet::<SocketAddr>::new()));
+    // latest-value handoff, the game loop never waits on the broadcaster
+    let (snapshot_tx, snapshot_rx) =
+        watch::channel(Arc::new(ServerPacket::Snapshot(GameWorld::new())));
+    let (events_tx, _) = broadcast::channel::<ServerPacket>(EVENT_BUFFER);
+    let (join_tx, join_rx) = mpsc::channel::<(SocketAddr, PlayerIdentity, SessionKind)>(128);
+    let (admin_tx, admin_rx) = mpsc::channel::<AdminCommand>(ADMIN_BUFFER);
+
+    let registrar = Registrar::from_config(&server_config).map(Arc::new);
+    let metrics = Arc::new(Metrics::new().map_err(io::Error::other)?);
+    let flood_stats = FloodStats::new(metrics.registry()).map_err(io::Error::other)?;
+    let ctx = ServerContext {
+        sessions: Arc::new(Mutex::new(SessionTable::default())),
+        bans: Arc::new(RwLock::new(HashSet::new())),
+        flood_guard: Arc::new(FloodGuard::new(FloodConfig::default(), flood_stats)),
+        net_stats: Arc::new(NetStats::new(metrics.registry()).map_err(io::Error::other)?),
+        metrics,
+        lifecycle: Arc::new(Lifecycle::new()),
+        registrar: registrar.clone(),
+    };
+    let tickets = Arc::new(TicketBook::default());
+    let gatekeeper = Arc::new(Gatekeeper {
+        verifier: TokenVerifier::new(&jwt_secret, &server_config.public_udp_addr),
+        lifecycle: ctx.lifecycle.clone(),
+        sessions: ctx.sessions.clone(),
+        cookies: CookieJar::default(),
+        tickets: tickets.clone(),
+        capacity: server_config.capacity as usize,
+        encrypt_udp: server_config.encrypt_udp,
+    });
+
+    tokio::spawn(lifecycle::watch_signals(ctx.lifecycle.clone()));
 
     {
-        // TCP/Auth -> ovo kasnije ce biti posebna aplikacija
-        let input_tcp_tx = input_tcp_tx.clone();
-        let tcp_socket = tcp_socket.clone();
+        // Prometheus scrape endpoint
+        let listener = TcpListener::bind(server_config.metrics_addr).await?;
+        let metrics = ctx.metrics.clone();
         tokio::spawn(async move {
-            let mut buf = [0u8; 4];
-
-            loop {
-                if let Ok((mut stream, addr)) = tcp_socket.accept().await {
-
-                    let n = stream.read_exact(&mut buf).await.unwrap();
-
-                    if n > 4 {
-                        eprint!("Something rusty is going on");
-                    }
-
-                    let player_id = u32::from_be_bytes(buf);
-
-                    println!("{player_id}");
-
-                    let _ = stream.write_all(&player_id.to_be_bytes()).await;
-                    let _ = stream.flush().await;
-
-                    if let Err(e) = input_tcp_tx.send((addr, player_id)).await {
-                        eprintln!("Failed to send input {e}");
-                    }
-
-                    println!("Assigned player_id={} to {}", player_id, addr);
-                }
+            if let Err(e) = metrics::serve_metrics(listener, metrics).await {
+                error!(error = %e, "Metrics endpoint stopped");
             }
         });
     }
 
-    {
-        // Task taking UDP load and sending command to game loop to update game state
-        let socket_listener = socket.clone();
-        let input_tx_listener = input_tx.clone();
-        let clients = clients.clone();
-        tokio::spawn(async move {
-            let mut buf = [0u8; 1024];
-            let config = config::standard();
-            loop {
-                let (len, addr) = socket_listener.recv_from(&mut buf).await.unwrap();
-
-                {
-                    clients.lock().await.insert(addr);
+    match server_config.admin_token.clone() {
+        Some(token) => {
+            let listener = TcpListener::bind(server_config.admin_addr).await?;
+            tokio::spawn(async move {
+                if let Err(e) = admin::serve_admin(listener, token, admin_tx).await {
+                    error!(error = %e, "Admin API stopped");
                 }
+            });
+        }
+        None => warn!("ADMIN_TOKEN is not set, admin API disabled"),
+    }
 
-                if let Ok((input, _)) =
-                    bincode::decode_from_slice::<PlayerInput, _>(&buf[..len], config)
-                {
-                    if let Err(e) = input_tx_listener.send((addr, input)).await {
-                        eprintln!("Failed to send input {e}");
-                    }
-                }
-            }
+    match &registrar {
+        Some(registrar) => {
+            tokio::spawn(registrar.clone().run(
+                ctx.metrics.clone(),
+                ctx.lifecycle.clone(),
+                tickets,
+            ));
+        }
+        None => warn!("AUTH_URL is not set, server won't be listed"),
+    }
+
+    {
+        // Task answering handshakes, taking UDP load and sending command to game loop to update game state
+        let socket = socket.clone();
+        let ctx = ctx.clone();
+        supervise("udp-listener", ctx.net_stats.clone(), move || {
+            network::run_udp_listener(
+                socket.clone(),
+                input_tx.clone(),
+                join_tx.clone(),
+                gatekeeper.clone(),
+                ctx.bans.clone(),
+                ctx.flood_guard.clone(),
+                ctx.net_stats.clone(),
+            )
         });
     }
 
     {
         // Task broadcasting world state
-        let socket_output = socket.clone();
-        let clients = clients.clone();
+        let socket = socket.clone();
+        let events_tx = events_tx.clone();
+        let ctx = ctx.clone();
+        supervise("broadcaster", ctx.net_stats.clone(), move || {
+            network::run_broadcaster(
+                socket.clone(),
+                snapshot_rx.clone(),
+                events_tx.subscribe(),
+                ctx.sessions.clone(),
+                ctx.net_stats.clone(),
+            )
+        });
+    }
+
+    {
+        // Task reporting flood protection counters
+        let flood_guard = ctx.flood_guard.clone();
         tokio::spawn(async move {
-            let config = config::standard();
-            while let Some(world) = snapshot_rx.recv().await {
-                let data = bincode::encode_to_vec(&world, config).unwrap();
-                let clients = clients.lock().await.clone();
-                for addr in clients {
-                    if let Err(e) = socket_output.send_to(&data, addr).await {
-                        eprintln!("Failed to broadcast data to {addr}: {e}");
-                    }
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(FLOOD_REPORT_INTERVAL));
+            let mut last = FloodStatsSnapshot::default();
+            loop {
+                interval.tick().await;
+                let current = flood_guard.stats().snapshot();
+                if current != last {
+                    warn!(stats = ?current, "Flood protection dropped traffic");
+                    last = current;
                 }
             }
         });
     }
 
-    game_loop(input_rx, input_tcp_rx, snapshot_tx).await;
-
-    Ok(())
-}
-
-async fn game_loop(
-    mut input_rx: Receiver<(SocketAddr, PlayerInput)>,
-    mut input_tcp: Receiver<(SocketAddr, u32)>,
-    snapshot_tx: Sender<GameWorld>,
-) {
-    let tick_duration = std::time::Duration::from_millis(TICK_DURATION);
-    let mut interval = tokio::time::interval(tick_duration);
-    let mut world = GameWorld::new();
-    // let mut addr_to_id: HashMap<SocketAddr, u32> = HashMap::new();
-
-    loop {
-        interval.tick().await;
-
-        while let Ok((_, player_id)) = input_tcp.try_recv() {
-            println!("new player");
-            world.add_player(player_id);
+    let channels = GameLoopChannels {
+        inputs: input_rx,
+        joins: join_rx,
+        snapshots: snapshot_tx,
+        events: events_tx,
+        admin: admin_rx,
+    };
+
+    let shutdown_timeout = server_config.shutdown_timeout;
+    let room_span = info_span!("room", room_id = server_config.room_id);
+    let stopped = GameLoop::new(server_config, channels, ctx.clone())
+        .run()
+        .instrument(room_span)
+        .await;
+
+    // the handshake listener already refuses new players, tell the current ones and flush
+    let shutdown = async {
+        if let Some(registrar) = &registrar {
+            registrar.deregister().await;
         }
 
-        while let Ok((_addr, input)) = input_rx.try_recv() {
-            let id = input.id;
-            world.apply_input(id, &input);
-            println!("{:?}", input);
+        let packet = ServerPacket::Shutdown(stopped.reason);
+        if let Err(e) = network::send_to_all(&socket, &ctx.sessions, &packet, &ctx.net_stats).await
+        {
+            error!(error = %e, "Failed to notify clients of shutdown");
         }
 
-        let _ = snapshot_tx.send(world.clone()).await;
+        for write in stopped.pending_writes {
+            match write.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!(error = %e, "Failed to flush on shutdown"),
+                Err(e) => error!(error = %e, "Flush task failed"),
+            }
+        }
+    };
 
-        world.update();
+    if tokio::time::timeout(shutdown_timeout, shutdown)
+        .await
+        .is_err()
+    {
+        warn!(
+            ?shutdown_timeout,
+            "Shutdown deadline passed, exiting anyway"
+        );
     }
+    info!("Server stopped");
+
+    Ok(())
 }
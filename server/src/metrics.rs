@@ -0,0 +1,150 @@
+use axum::{Router, extract::State, http::header, response::IntoResponse, routing::get};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::{io, sync::Arc};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+/// Game loop metrics plus the registry every other counter in the server is registered with.
+pub struct Metrics {
+    registry: Registry,
+    pub tick_duration: Histogram,
+    pub tick_overruns: IntCounter,
+    pub players: IntGauge,
+    pub spectators: IntGauge,
+    pub bots: IntGauge,
+    pub rooms: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("arena".into()), None)?;
+
+        // buckets around the 16 ms budget of a 60 Hz tick
+        let tick_duration = Histogram::with_opts(
+            HistogramOpts::new("tick_duration_seconds", "Time spent simulating one tick").buckets(
+                vec![
+                    0.0005, 0.001, 0.002, 0.004, 0.008, 0.012, 0.016, 0.025, 0.05, 0.1,
+                ],
+            ),
+        )?;
+        registry.register(Box::new(tick_duration.clone()))?;
+
+        let metrics = Self {
+            tick_overruns: counter(
+                &registry,
+                "tick_overruns_total",
+                "Ticks that took longer than the tick budget",
+            )?,
+            players: gauge(&registry, "players", "Human players currently in the world")?,
+            spectators: gauge(&registry, "spectators", "Spectators currently watching")?,
+            bots: gauge(&registry, "bots", "Bots currently filling the room")?,
+            rooms: gauge(&registry, "rooms", "Rooms hosted by this process")?,
+            tick_duration,
+            registry,
+        };
+
+        Ok(metrics)
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Encodes every registered metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            error!(error = %e, "Failed to encode metrics");
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
+
+pub fn counter(registry: &Registry, name: &str, help: &str) -> prometheus::Result<IntCounter> {
+    let counter = IntCounter::new(name, help)?;
+    registry.register(Box::new(counter.clone()))?;
+    Ok(counter)
+}
+
+pub fn counter_vec(
+    registry: &Registry,
+    name: &str,
+    help: &str,
+    labels: &[&str],
+) -> prometheus::Result<IntCounterVec> {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels)?;
+    registry.register(Box::new(counter.clone()))?;
+    Ok(counter)
+}
+
+pub fn gauge(registry: &Registry, name: &str, help: &str) -> prometheus::Result<IntGauge> {
+    let gauge = IntGauge::new(name, help)?;
+    registry.register(Box::new(gauge.clone()))?;
+    Ok(gauge)
+}
+
+/// Serves `GET /metrics` for Prometheus to scrape.
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) -> io::Result<()> {
+    info!(addr = %listener.local_addr()?, "Metrics endpoint listening");
+    let app = Router::new()
+        .route("/metrics", get(scrape))
+        .with_state(metrics);
+    axum::serve(listener, app).await
+}
+
+async fn scrape(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::NetStats;
+    use crate::rate_limit::FloodStats;
+
+    #[tokio::test]
+    async fn scrape_lists_registered_series() {
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let net_stats = NetStats::new(metrics.registry()).unwrap();
+        FloodStats::new(metrics.registry()).unwrap();
+        metrics.players.set(3);
+        net_stats.handshakes_accepted.inc();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_metrics(listener, metrics));
+
+        let response = reqwest::get(format!("http://{addr}/metrics"))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert!(
+            response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+
+        let body = response.text().await.unwrap();
+        for series in [
+            "arena_tick_duration_seconds_bucket",
+            "arena_tick_overruns_total 0",
+            "arena_players 3",
+            "arena_spectators 0",
+            "arena_bots 0",
+            "arena_rooms 0",
+            "arena_handshakes_accepted_total 1",
+            "arena_decode_failures_total 0",
+            "arena_inputs_dropped_total 0",
+            "arena_sessions_kicked_total 0",
+        ] {
+            assert!(body.contains(series), "missing {series} in\n{body}");
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use common::handshake::{self, RejectReason};
+use common::packet::{ClientPacket, PlayerInput, ServerPacket, WIRE_CONFIG};
+use prometheus::{IntCounter, IntCounterVec, Registry};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, info_span, warn};
+
+use crate::context::Bans;
+use crate::handshake::Gatekeeper;
+use crate::metrics::{counter, counter_vec};
+use crate::rate_limit::{FloodGuard, Verdict};
+use crate::session::{SessionKind, Sessions};
+use crate::tokens::PlayerIdentity;
+
+/// Largest datagram read, comfortably above both handshakes and inputs.
+const MAX_DATAGRAM_LEN: usize = 1500;
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+pub struct NetStats {
+    pub handshakes_accepted: IntCounter,
+    pub handshake_failures: IntCounter,
+    pub udp_recv_errors: IntCounter,
+    pub udp_send_errors: IntCounter,
+    pub malformed_datagrams: IntCounter,
+    pub rejected_datagrams: IntCounter,
+    pub inputs_received: IntCounter,
+    pub snapshot_bytes_sent: IntCounterVec,
+    pub task_restarts: IntCounter,
+}
+
+impl NetStats {
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        Ok(Self {
+            handshakes_accepted: counter(
+                registry,
+                "handshakes_accepted_total",
+                "Handshakes that created a session",
+            )?,
+            handshake_failures: counter(
+                registry,
+                "handshake_failures_total",
+                "Handshakes that were rejected",
+            )?,
+            udp_recv_errors: counter(registry, "udp_recv_errors_total", "Failed UDP receives")?,
+            udp_send_errors: counter(registry, "udp_send_errors_total", "Failed UDP sends")?,
+            malformed_datagrams: counter(
+                registry,
+                "decode_failures_total",
+                "Datagrams that could not be decoded",
+            )?,
+            rejected_datagrams: counter(
+                registry,
+                "datagrams_rejected_total",
+                "Datagrams that failed authentication, were replayed or spoofed another player",
+            )?,
+            inputs_received: counter(registry, "inputs_received_total", "Decoded player inputs")?,
+            snapshot_bytes_sent: counter_vec(
+                registry,
+                "snapshot_bytes_sent_total",
+                "Snapshot bytes sent to each client",
+                &["client"],
+            )?,
+            task_restarts: counter(
+                registry,
+                "task_restarts_total",
+                "Networking tasks restarted by their supervisor",
+            )?,
+        })
+    }
+}
+
+/// Runs the task produced by `factory` and starts a fresh one whenever it
+/// fails or panics, so a single bad packet can't take a listener down for good.
+/// A task that finishes cleanly is done, its input went away.
+pub fn supervise<F, Fut>(name: &'static str, stats: Arc<NetStats>, factory: F) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = io::Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            match tokio::spawn(factory()).await {
+                Ok(Ok(())) => {
+                    info!(task = name, "Task finished");
+                    return;
+                }
+                Ok(Err(e)) => error!(task = name, error = %e, "Task failed, restarting"),
+                Err(e) if e.is_panic() => error!(task = name, "Task panicked, restarting"),
+                Err(e) => {
+                    warn!(task = name, error = %e, "Task was cancelled");
+                    return;
+                }
+            }
+            stats.task_restarts.inc();
+            tokio::time::sleep(RESTART_BACKOFF).await;
+        }
+    })
+}
+
+/// Answers handshakes, then receives player inputs, checks them against their
+/// session and forwards them to the game loop. A player leaving ends its session
+/// here, the game loop notices on its next tick.
+pub async fn run_udp_listener(
+    socket: Arc<UdpSocket>,
+    inputs: Sender<(SocketAddr, PlayerInput)>,
+    joins: Sender<(SocketAddr, PlayerIdentity, SessionKind)>,
+    gatekeeper: Arc<Gatekeeper>,
+    bans: Bans,
+    flood_guard: Arc<FloodGuard>,
+    stats: Arc<NetStats>,
+) -> io::Result<()> {
+    let mut buf = [0u8; MAX_DATAGRAM_LEN];
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                // ICMP port unreachable from a gone client surfaces here on some platforms
+                warn!(error = %e, "Failed to receive datagram");
+                stats.udp_recv_errors.inc();
+                continue;
+            }
+        };
+
+        if bans.read().unwrap().contains(&addr.ip()) {
+            continue;
+        }
+
+        if flood_guard.admit_datagram(addr, len) != Verdict::Accept {
+            continue;
+        }
+
+        if handshake::is_handshake(&buf[..len]) {
+            handle_handshake(&socket, &joins, &gatekeeper, addr, &buf[..len], &stats).await?;
+            continue;
+        }
+
+        let opened = gatekeeper.sessions.lock().unwrap().open(addr, &buf[..len]);
+        let (player_id, kind, payload) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                stats.rejected_datagrams.inc();
+                debug!(%addr, len, error = %e, "Dropped unauthenticated datagram");
+                continue;
+            }
+        };
+        // nothing below awaits, so the guard never crosses an await
+        let _session = info_span!("session", %addr, player_id).entered();
+        let Ok((packet, _)) = bincode::decode_from_slice::<ClientPacket, _>(&payload, WIRE_CONFIG)
+        else {
+            stats.malformed_datagrams.inc();
+            debug!(len, "Dropped malformed datagram");
+            continue;
+        };
+        let input = match packet {
+            // spectators only send to stay connected
+            ClientPacket::Input(input) if kind == SessionKind::Player => input,
+            ClientPacket::Input(_) | ClientPacket::KeepAlive => continue,
+            ClientPacket::Leave => {
+                gatekeeper.sessions.lock().unwrap().remove_addr(&addr);
+                info!("Session left");
+                continue;
+            }
+        };
+        // a valid session can still only steer its own ship
+        if input.id != player_id {
+            stats.rejected_datagrams.inc();
+            debug!(claimed = input.id, "Dropped input for another player");
+            continue;
+        }
+        stats.inputs_received.inc();
+
+        // never wait on a full queue, a flooding client would stall everyone
+        match inputs.try_send((addr, input)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                flood_guard.stats().channel_full_drops.inc();
+            }
+            Err(TrySendError::Closed(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "input channel closed",
+                ));
+            }
+        }
+    }
+}
+
+/// Answers one handshake datagram and hands a new session over to the game loop.
+async fn handle_handshake(
+    socket: &UdpSocket,
+    joins: &Sender<(SocketAddr, PlayerIdentity, SessionKind)>,
+    gatekeeper: &Gatekeeper,
+    addr: SocketAddr,
+    datagram: &[u8],
+    stats: &NetStats,
+) -> io::Result<()> {
+    let mut outcome = gatekeeper.handle(addr, datagram);
+
+    if let Some((identity, kind)) = outcome.joined.take() {
+        let player_id = identity.player_id;
+        let _session = info_span!("session", %addr, player_id).entered();
+        // never wait on a full queue, everyone's inputs come through this task
+        match joins.try_send((addr, identity.clone(), kind)) {
+            Ok(()) => {
+                stats.handshakes_accepted.inc();
+                info!(
+                    username = %identity.username,
+                    spectator = kind == SessionKind::Spectator,
+                    "Session assigned"
+                );
+            }
+            Err(TrySendError::Full(_)) => {
+                gatekeeper.sessions.lock().unwrap().remove_addr(&addr);
+                warn!("Join queue full, turning player away");
+                outcome.rejected = Some(RejectReason::NotAccepting);
+                outcome.reply = handshake::encode_datagram(
+                    &handshake::HandshakeResponse::Rejected(RejectReason::NotAccepting),
+                    0,
+                )
+                .ok();
+            }
+            Err(TrySendError::Closed(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "join channel closed",
+                ));
+            }
+        }
+    }
+
+    if let Some(reason) = outcome.rejected {
+        stats.handshake_failures.inc();
+        warn!(%addr, %reason, "Handshake rejected");
+    }
+
+    if let Some(reply) = outcome.reply
+        && let Err(e) = socket.send_to(&reply, addr).await
+    {
+        stats.udp_send_errors.inc();
+        warn!(%addr, error = %e, "Failed to answer handshake");
+    }
+    Ok(())
+}
+
+/// Sends every new snapshot and every server event to all known clients.
+pub async fn run_broadcaster(
+    socket: Arc<UdpSocket>,
+    mut snapshots: watch::Receiver<Arc<ServerPacket>>,
+    mut events: broadcast::Receiver<ServerPacket>,
+    sessions: Sessions,
+    stats: Arc<NetStats>,
+) -> io::Result<()> {
+    loop {
+        let packet = tokio::select! {
+            changed = snapshots.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                snapshots.borrow_and_update().clone()
+            }
+            event = events.recv() => match event {
+                Ok(event) => Arc::new(event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "Broadcaster fell behind on events");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        };
+
+        let data = bincode::encode_to_vec(&*packet, WIRE_CONFIG)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let (datagrams, dropped) = {
+            let mut sessions = sessions.lock().unwrap();
+            (sessions.seal_for_all(&data), sessions.take_dropped())
+        };
+        // per-client series would pile up as clients come and go
+        for addr in dropped {
+            let _ = stats
+                .snapshot_bytes_sent
+                .remove_label_values(&[&addr.to_string()]);
+        }
+        for (addr, datagram) in datagrams {
+            match socket.send_to(&datagram, addr).await {
+                Ok(sent) => {
+                    if matches!(*packet, ServerPacket::Snapshot(_)) {
+                        stats
+                            .snapshot_bytes_sent
+                            .with_label_values(&[&addr.to_string()])
+                            .inc_by(sent as u64);
+                    }
+                }
+                Err(e) => {
+                    stats.udp_send_errors.inc();
+                    warn!(%addr, error = %e, "Failed to broadcast packet");
+                }
+            }
+        }
+    }
+}
+
+/// Sends one packet to every known client right away, without going through the broadcaster.
+pub async fn send_to_all(
+    socket: &UdpSocket,
+    sessions: &Sessions,
+    packet: &ServerPacket,
+    stats: &NetStats,
+) -> io::Result<()> {
+    let data = bincode::encode_to_vec(packet, WIRE_CONFIG)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let datagrams = sessions.lock().unwrap().seal_for_all(&data);
+    for (addr, datagram) in datagrams {
+        if let Err(e) = socket.send_to(&datagram, addr).await {
+            stats.udp_send_errors.inc();
+            warn!(%addr, error = %e, "Failed to send packet");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,314 @@
+use prometheus::{IntCounter, Registry};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::metrics::counter;
+
+const BYTE_WINDOW: Duration = Duration::from_secs(1);
+/// How often budgets and blocks that ran out are looked for.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct FloodConfig {
+    /// Inputs a single session may have applied in one simulation tick.
+    pub max_inputs_per_tick: u32,
+    /// Datagram bytes a single session may send per second.
+    pub max_bytes_per_sec: u32,
+    /// Strikes after which a session is reported as suspicious.
+    pub flag_after_strikes: u32,
+    /// Strikes after which a session is kicked and its address blocked.
+    pub kick_after_strikes: u32,
+    /// Budgets of addresses that sent nothing for this long are forgotten.
+    pub idle_timeout: Duration,
+    /// How long a kicked address stays blocked.
+    pub block_duration: Duration,
+}
+
+impl Default for FloodConfig {
+    fn default() -> Self {
+        Self {
+            // client sends at most one packet per action per physics frame
+            max_inputs_per_tick: 8,
+            max_bytes_per_sec: 16 * 1024,
+            flag_after_strikes: 5,
+            kick_after_strikes: 20,
+            idle_timeout: Duration::from_secs(30),
+            block_duration: Duration::from_secs(600),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    /// Session crossed the kick threshold on this call, caller should disconnect it.
+    Kick,
+    /// Address was kicked earlier, everything from it is ignored.
+    Blocked,
+}
+
+pub struct FloodStats {
+    pub inputs_dropped: IntCounter,
+    pub datagrams_dropped: IntCounter,
+    pub bytes_dropped: IntCounter,
+    pub channel_full_drops: IntCounter,
+    pub sessions_flagged: IntCounter,
+    pub sessions_kicked: IntCounter,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FloodStatsSnapshot {
+    pub inputs_dropped: u64,
+    pub datagrams_dropped: u64,
+    pub bytes_dropped: u64,
+    pub channel_full_drops: u64,
+    pub sessions_flagged: u64,
+    pub sessions_kicked: u64,
+}
+
+impl FloodStats {
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        Ok(Self {
+            inputs_dropped: counter(
+                registry,
+                "inputs_dropped_total",
+                "Inputs dropped for exceeding the per tick budget",
+            )?,
+            datagrams_dropped: counter(
+                registry,
+                "datagrams_dropped_total",
+                "Datagrams dropped for exceeding the byte budget",
+            )?,
+            bytes_dropped: counter(
+                registry,
+                "bytes_dropped_total",
+                "Bytes dropped for exceeding the byte budget",
+            )?,
+            channel_full_drops: counter(
+                registry,
+                "input_queue_full_total",
+                "Inputs dropped because the game loop queue was full",
+            )?,
+            sessions_flagged: counter(
+                registry,
+                "sessions_flagged_total",
+                "Sessions flagged for flooding",
+            )?,
+            sessions_kicked: counter(
+                registry,
+                "sessions_kicked_total",
+                "Sessions kicked for flooding",
+            )?,
+        })
+    }
+
+    pub fn snapshot(&self) -> FloodStatsSnapshot {
+        FloodStatsSnapshot {
+            inputs_dropped: self.inputs_dropped.get(),
+            datagrams_dropped: self.datagrams_dropped.get(),
+            bytes_dropped: self.bytes_dropped.get(),
+            channel_full_drops: self.channel_full_drops.get(),
+            sessions_flagged: self.sessions_flagged.get(),
+            sessions_kicked: self.sessions_kicked.get(),
+        }
+    }
+}
+
+struct SessionBudget {
+    last_seen: Instant,
+    window_start: Instant,
+    bytes_in_window: u32,
+    inputs_this_tick: u32,
+    strikes: u32,
+    flagged: bool,
+}
+
+impl SessionBudget {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_seen: now,
+            window_start: now,
+            bytes_in_window: 0,
+            inputs_this_tick: 0,
+            strikes: 0,
+            flagged: false,
+        }
+    }
+}
+
+struct GuardState {
+    sessions: HashMap<SocketAddr, SessionBudget>,
+    /// Blocked addresses and when the block ends. Blocks cover the whole IP so a
+    /// new source port doesn't get around them.
+    blocked: HashMap<IpAddr, Instant>,
+    last_prune: Instant,
+}
+
+impl GuardState {
+    fn is_blocked(&self, addr: &SocketAddr, now: Instant) -> bool {
+        self.blocked
+            .get(&addr.ip())
+            .is_some_and(|until| *until > now)
+    }
+
+    fn block(&mut self, addr: SocketAddr, duration: Duration, now: Instant) {
+        self.sessions.remove(&addr);
+        self.blocked.insert(addr.ip(), now + duration);
+    }
+}
+
+/// Per-session flood protection shared by the UDP listener (byte budget)
+/// and the game loop (input budget per tick).
+pub struct FloodGuard {
+    config: FloodConfig,
+    stats: FloodStats,
+    state: Mutex<GuardState>,
+}
+
+impl FloodGuard {
+    pub fn new(config: FloodConfig, stats: FloodStats) -> Self {
+        Self {
+            config,
+            stats,
+            state: Mutex::new(GuardState {
+                sessions: HashMap::new(),
+                blocked: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn stats(&self) -> &FloodStats {
+        &self.stats
+    }
+
+    /// Charges `len` bytes against the sender's per second budget.
+    pub fn admit_datagram(&self, addr: SocketAddr, len: usize) -> Verdict {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if state.is_blocked(&addr, now) {
+            self.count_dropped_datagram(len);
+            return Verdict::Blocked;
+        }
+
+        let budget = state
+            .sessions
+            .entry(addr)
+            .or_insert_with(|| SessionBudget::new(now));
+        budget.last_seen = now;
+
+        if now.duration_since(budget.window_start) >= BYTE_WINDOW {
+            // a clean second forgives one strike
+            if budget.bytes_in_window <= self.config.max_bytes_per_sec {
+                budget.strikes = budget.strikes.saturating_sub(1);
+            }
+            budget.window_start = now;
+            budget.bytes_in_window = 0;
+        }
+
+        budget.bytes_in_window = budget.bytes_in_window.saturating_add(len as u32);
+        if budget.bytes_in_window <= self.config.max_bytes_per_sec {
+            return Verdict::Accept;
+        }
+
+        self.count_dropped_datagram(len);
+        let verdict = self.strike(addr, &mut state);
+        if verdict == Verdict::Kick {
+            state.block(addr, self.config.block_duration, now);
+        }
+        verdict
+    }
+
+    /// Charges one applied input against the sender's per tick budget.
+    pub fn admit_input(&self, addr: SocketAddr) -> Verdict {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if state.is_blocked(&addr, now) {
+            self.stats.inputs_dropped.inc();
+            return Verdict::Blocked;
+        }
+
+        let budget = state
+            .sessions
+            .entry(addr)
+            .or_insert_with(|| SessionBudget::new(now));
+
+        budget.inputs_this_tick += 1;
+        if budget.inputs_this_tick <= self.config.max_inputs_per_tick {
+            return Verdict::Accept;
+        }
+
+        self.stats.inputs_dropped.inc();
+        // one strike per offending tick, not per dropped input
+        if budget.inputs_this_tick != self.config.max_inputs_per_tick + 1 {
+            return Verdict::Drop;
+        }
+
+        let verdict = self.strike(addr, &mut state);
+        if verdict == Verdict::Kick {
+            state.block(addr, self.config.block_duration, now);
+        }
+        verdict
+    }
+
+    /// Resets per tick input counters, called by the game loop once per tick.
+    /// Also forgets idle budgets and expired blocks, so spoofed or rotating
+    /// source addresses don't pile up.
+    pub fn end_tick(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state
+            .sessions
+            .values_mut()
+            .for_each(|b| b.inputs_this_tick = 0);
+
+        if now.duration_since(state.last_prune) >= PRUNE_INTERVAL {
+            state.last_prune = now;
+            let idle_timeout = self.config.idle_timeout;
+            state
+                .sessions
+                .retain(|_, b| now.duration_since(b.last_seen) < idle_timeout);
+            state.blocked.retain(|_, until| *until > now);
+        }
+    }
+
+    /// Forgets budgets of a session that left, keeping the block list intact.
+    pub fn forget(&self, addr: &SocketAddr) {
+        self.state.lock().unwrap().sessions.remove(addr);
+    }
+
+    pub fn is_blocked(&self, addr: &SocketAddr) -> bool {
+        self.state.lock().unwrap().is_blocked(addr, Instant::now())
+    }
+
+    fn count_dropped_datagram(&self, len: usize) {
+        self.stats.datagrams_dropped.inc();
+        self.stats.bytes_dropped.inc_by(len as u64);
+    }
+
+    fn strike(&self, addr: SocketAddr, state: &mut GuardState) -> Verdict {
+        let Some(budget) = state.sessions.get_mut(&addr) else {
+            return Verdict::Drop;
+        };
+
+        budget.strikes += 1;
+
+        if budget.strikes >= self.config.kick_after_strikes {
+            self.stats.sessions_kicked.inc();
+            state.sessions.remove(&addr);
+            return Verdict::Kick;
+        }
+
+        if budget.strikes >= self.config.flag_after_strikes && !budget.flagged {
+            budget.flagged = true;
+            self.stats.sessions_flagged.inc();
+            warn!(%addr, strikes = budget.strikes, "Session flagged for flooding");
+        }
+
+        Verdict::Drop
+    }
+}
@@ -0,0 +1,232 @@
+//! Match recordings: a magic and format version, then length-prefixed bincode
+//! chunks. The first chunk is a [`RecordingHeader`], the second a keyframe of the
+//! world the match started from, followed by tick records, periodic keyframes
+//! and an end marker.
+
+use bincode::{Decode, Encode, config};
+use common::game_world::GameWorld;
+use common::packet::{PROTOCOL_VERSION, PlayerInput};
+use common::rules::GameRules;
+use common::utils::{WorldRng, current_time_ms};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+pub const RECORDING_MAGIC: &[u8; 4] = b"ARNR";
+pub const RECORDING_FORMAT_VERSION: u16 = 4;
+pub const RECORDING_EXTENSION: &str = "arena";
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct RecordingHeader {
+    pub format_version: u16,
+    pub protocol_version: u16,
+    pub room_id: u32,
+    pub tick_rate_hz: u32,
+    pub tick_ms: u64,
+    /// Seed the match world was created with, its full rng state is in the first keyframe.
+    pub seed: u64,
+    pub rules: GameRules,
+    pub started_at_ms: u64,
+}
+
+/// Something the game loop did to the world before simulating a tick.
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum RecordedEvent {
+    Connect { player_id: u32 },
+    Disconnect { player_id: u32 },
+    Input { player_id: u32, input: PlayerInput },
+    SetRules(GameRules),
+    ConnectBot { player_id: u32 },
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum Chunk {
+    Header(RecordingHeader),
+    /// Events applied right before the world advanced to `tick`.
+    Tick {
+        tick: u64,
+        events: Vec<RecordedEvent>,
+    },
+    /// The world right after it advanced to `tick`, with the rng it advances with.
+    Keyframe {
+        tick: u64,
+        world: GameWorld,
+        rng: WorldRng,
+    },
+    End {
+        tick: u64,
+    },
+}
+
+/// Collects events of the running match and streams them to a writer task,
+/// so disk latency never lands on the game loop.
+pub struct MatchRecorder {
+    tx: UnboundedSender<Chunk>,
+    writer: JoinHandle<io::Result<()>>,
+    pending: Vec<RecordedEvent>,
+    keyframe_interval: u64,
+    path: PathBuf,
+}
+
+impl MatchRecorder {
+    /// Starts recording a match beginning with `world`.
+    pub fn start(
+        dir: &Path,
+        room_id: u32,
+        tick_rate_hz: u32,
+        seed: u64,
+        keyframe_interval: u64,
+        world: &GameWorld,
+        rng: &WorldRng,
+    ) -> Self {
+        let started_at_ms = current_time_ms();
+        let path = dir.join(format!(
+            "match-{room_id}-{started_at_ms}.{RECORDING_EXTENSION}"
+        ));
+
+        let (tx, rx) = unbounded_channel();
+        let header = RecordingHeader {
+            format_version: RECORDING_FORMAT_VERSION,
+            protocol_version: PROTOCOL_VERSION,
+            room_id,
+            tick_rate_hz,
+            tick_ms: world.tick_ms,
+            seed,
+            rules: world.rules.clone(),
+            started_at_ms,
+        };
+        let _ = tx.send(Chunk::Header(header));
+        let _ = tx.send(Chunk::Keyframe {
+            tick: world.tick,
+            world: world.clone(),
+            rng: rng.clone(),
+        });
+
+        let writer = tokio::spawn(write_chunks(path.clone(), rx));
+        info!(path = %path.display(), "Recording match");
+
+        Self {
+            tx,
+            writer,
+            pending: Vec::new(),
+            keyframe_interval: keyframe_interval.max(1),
+            path,
+        }
+    }
+
+    pub fn record(&mut self, event: RecordedEvent) {
+        self.pending.push(event);
+    }
+
+    /// Flushes the events of the tick `world` just advanced to, plus a keyframe when due.
+    pub fn end_tick(&mut self, world: &GameWorld, rng: &WorldRng) {
+        if !self.pending.is_empty() {
+            let events = std::mem::take(&mut self.pending);
+            let _ = self.tx.send(Chunk::Tick {
+                tick: world.tick,
+                events,
+            });
+        }
+
+        if world.tick.is_multiple_of(self.keyframe_interval) {
+            let _ = self.tx.send(Chunk::Keyframe {
+                tick: world.tick,
+                world: world.clone(),
+                rng: rng.clone(),
+            });
+        }
+    }
+
+    /// Writes the end marker and returns the writer task, which completes once the file is flushed.
+    pub fn finish(self, world: &GameWorld) -> JoinHandle<io::Result<()>> {
+        let _ = self.tx.send(Chunk::End { tick: world.tick });
+        info!(path = %self.path.display(), tick = world.tick, "Match recording finished");
+        self.writer
+    }
+}
+
+async fn write_chunks(path: PathBuf, mut rx: UnboundedReceiver<Chunk>) -> io::Result<()> {
+    let result = async {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut file = BufWriter::new(File::create(&path).await?);
+        file.write_all(RECORDING_MAGIC).await?;
+        file.write_all(&RECORDING_FORMAT_VERSION.to_le_bytes())
+            .await?;
+
+        let config = config::standard();
+        while let Some(chunk) = rx.recv().await {
+            let data = bincode::encode_to_vec(&chunk, config)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            file.write_all(&(data.len() as u32).to_le_bytes()).await?;
+            file.write_all(&data).await?;
+            // a crashed server still leaves everything up to the last keyframe on disk
+            if matches!(chunk, Chunk::Keyframe { .. }) {
+                file.flush().await?;
+            }
+        }
+
+        file.flush().await
+    }
+    .await;
+
+    if let Err(e) = &result {
+        error!(path = %path.display(), error = %e, "Failed to write match recording");
+    }
+    result
+}
+
+/// Reads every chunk of a recording. A chunk cut short by a crash ends the
+/// recording instead of failing it, everything before it is still usable.
+pub fn read_chunks(mut reader: impl Read) -> io::Result<Vec<Chunk>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != RECORDING_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a match recording",
+        ));
+    }
+
+    let mut version = [0u8; 2];
+    reader.read_exact(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    // keyframes embed the world, older formats don't decode anymore
+    if version != RECORDING_FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "recording format {version} is not supported, expected {RECORDING_FORMAT_VERSION}"
+            ),
+        ));
+    }
+
+    let config = config::standard();
+    let mut chunks = Vec::new();
+    let mut len = [0u8; 4];
+    loop {
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+        match reader.read_exact(&mut data) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let (chunk, _) = bincode::decode_from_slice(&data, config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        chunks.push(chunk);
+    }
+
+    Ok(chunks)
+}
@@ -0,0 +1,223 @@
+use common::registry::{
+    HeartbeatRequest, MatchResultRequest, RegisterServerRequest, RegisterServerResponse,
+};
+use reqwest::StatusCode;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::ServerConfig;
+use crate::lifecycle::{Lifecycle, ServerState};
+use crate::metrics::Metrics;
+use crate::tokens::TicketBook;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keeps this server listed with the auth service.
+pub struct Registrar {
+    http: reqwest::Client,
+    auth_url: String,
+    token: String,
+    request: RegisterServerRequest,
+    server_id: Mutex<Option<String>>,
+    /// Accounts connected to the room, sent with every heartbeat.
+    online_players: Mutex<Vec<u32>>,
+}
+
+impl Registrar {
+    /// Returns `None` unless both `AUTH_URL` and `SERVER_REGISTRATION_TOKEN` are set.
+    pub fn from_config(config: &ServerConfig) -> Option<Self> {
+        let (auth_url, token) = match (&config.auth_url, &config.registration_token) {
+            (Some(url), Some(token)) => (url.clone(), token.clone()),
+            (None, _) => return None,
+            (Some(_), None) => {
+                warn!("AUTH_URL is set without SERVER_REGISTRATION_TOKEN, not registering");
+                return None;
+            }
+        };
+
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .ok()?;
+
+        Some(Self {
+            http,
+            auth_url,
+            token,
+            request: RegisterServerRequest {
+                name: config.server_name.clone(),
+                room_id: config.room_id,
+                udp_addr: config.public_udp_addr.clone(),
+                region: config.region.clone(),
+                mode: config.mode.clone(),
+                capacity: config.capacity,
+                players: 0,
+            },
+            server_id: Mutex::new(None),
+            online_players: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Registers, then sends a heartbeat every interval and right away whenever
+    /// the lifecycle changes. Registers again if the auth service forgot us.
+    pub async fn run(
+        self: Arc<Self>,
+        metrics: Arc<Metrics>,
+        lifecycle: Arc<Lifecycle>,
+        tickets: Arc<TicketBook>,
+    ) {
+        let mut state = lifecycle.subscribe();
+        let mut interval = RETRY_INTERVAL;
+
+        loop {
+            let players = metrics.players.get().max(0) as u32;
+            let server_id = self.server_id.lock().unwrap().clone();
+
+            match server_id {
+                None => match self.register(players).await {
+                    Ok(response) => {
+                        info!(server_id = %response.server_id, "Registered with the auth service");
+                        interval = Duration::from_secs(response.heartbeat_interval_secs.max(1));
+                        *self.server_id.lock().unwrap() = Some(response.server_id);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "Failed to register with the auth service");
+                        interval = RETRY_INTERVAL;
+                    }
+                },
+                Some(server_id) => {
+                    let accepting_players = lifecycle.accepts_players();
+                    // a lost heartbeat only means those reservations time out on their own
+                    let redeemed_tickets = tickets.take_unreported();
+                    let heartbeat = HeartbeatRequest {
+                        players,
+                        accepting_players,
+                        redeemed_tickets,
+                        online_players: self.online_players.lock().unwrap().clone(),
+                    };
+                    match self.heartbeat(&server_id, &heartbeat).await {
+                        Ok(()) => debug!(players, accepting_players, "Heartbeat sent"),
+                        Err(e) if e.status() == Some(StatusCode::NOT_FOUND) => {
+                            warn!("Auth service dropped this server, registering again");
+                            *self.server_id.lock().unwrap() = None;
+                            continue;
+                        }
+                        Err(e) => warn!(error = %e, "Failed to send heartbeat"),
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                changed = state.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    // shutdown deregisters instead, nothing to report
+                    if matches!(*state.borrow_and_update(), ServerState::ShuttingDown { .. }) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes this server from the list, called on shutdown.
+    pub async fn deregister(&self) {
+        let Some(server_id) = self.server_id.lock().unwrap().take() else {
+            return;
+        };
+
+        let result = self
+            .http
+            .delete(format!("{}/servers/{server_id}", self.auth_url))
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match result {
+            Ok(_) => info!(%server_id, "Deregistered from the auth service"),
+            Err(e) => warn!(error = %e, "Failed to deregister from the auth service"),
+        }
+    }
+
+    /// Sends the results of a finished match in the background, the handle completes once
+    /// the auth service has them or gave up. Without a registration there is nowhere to send them.
+    pub fn report_match(
+        self: &Arc<Self>,
+        result: MatchResultRequest,
+    ) -> Option<JoinHandle<io::Result<()>>> {
+        // read now, shutdown deregisters right after the last match ends
+        let Some(server_id) = self.server_id.lock().unwrap().clone() else {
+            warn!("Not registered with the auth service, dropping match results");
+            return None;
+        };
+
+        let registrar = self.clone();
+        Some(tokio::spawn(async move {
+            match registrar.post_match(&server_id, &result).await {
+                Ok(()) => info!(players = result.players.len(), "Reported match results"),
+                Err(e) => warn!(error = %e, "Failed to report match results"),
+            }
+            Ok(())
+        }))
+    }
+
+    async fn register(&self, players: u32) -> reqwest::Result<RegisterServerResponse> {
+        let request = RegisterServerRequest {
+            players,
+            ..self.request.clone()
+        };
+
+        self.http
+            .post(format!("{}/servers/register", self.auth_url))
+            .bearer_auth(&self.token)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    async fn post_match(
+        &self,
+        server_id: &str,
+        result: &MatchResultRequest,
+    ) -> reqwest::Result<()> {
+        self.http
+            .post(format!("{}/servers/{server_id}/matches", self.auth_url))
+            .bearer_auth(&self.token)
+            .json(result)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Called by the game loop, the auth service learns about it with the next heartbeat.
+    pub fn set_online_players(&self, player_ids: Vec<u32>) {
+        *self.online_players.lock().unwrap() = player_ids;
+    }
+
+    async fn heartbeat(
+        &self,
+        server_id: &str,
+        heartbeat: &HeartbeatRequest,
+    ) -> reqwest::Result<()> {
+        self.http
+            .post(format!("{}/servers/{server_id}/heartbeat", self.auth_url))
+            .bearer_auth(&self.token)
+            .json(heartbeat)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
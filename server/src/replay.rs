@@ -0,0 +1,370 @@
+//! Re-simulates recorded matches. Feeding a recording's events through the
+//! current `GameWorld` must land on the same keyframes, so old recordings
+//! double as regression tests for simulation changes.
+
+use common::game_world::GameWorld;
+use common::utils::WorldRng;
+use std::collections::BTreeMap;
+use std::{fmt, io};
+
+use crate::recording::{Chunk, RecordedEvent, RecordingHeader};
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    MissingHeader,
+    MissingStartKeyframe,
+    TickOutOfRange { tick: u64, first: u64, last: u64 },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{e}"),
+            ReplayError::MissingHeader => write!(f, "recording has no header"),
+            ReplayError::MissingStartKeyframe => {
+                write!(f, "recording has no keyframe to start from")
+            }
+            ReplayError::TickOutOfRange { tick, first, last } => {
+                write!(f, "tick {tick} is outside the recording ({first}..={last})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+/// A recording split into what the simulation needs.
+pub struct Replay {
+    pub header: RecordingHeader,
+    start: (GameWorld, WorldRng),
+    events: BTreeMap<u64, Vec<RecordedEvent>>,
+    keyframes: BTreeMap<u64, (GameWorld, WorldRng)>,
+    last_tick: u64,
+}
+
+/// First tick where the re-simulated world differs from the recorded keyframe.
+pub struct Divergence {
+    pub tick: u64,
+    pub differences: Vec<String>,
+}
+
+pub struct Verification {
+    pub ticks: u64,
+    pub keyframes_checked: usize,
+    pub divergence: Option<Divergence>,
+}
+
+impl Replay {
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Result<Self, ReplayError> {
+        let mut chunks = chunks.into_iter();
+        let Some(Chunk::Header(header)) = chunks.next() else {
+            return Err(ReplayError::MissingHeader);
+        };
+        let Some(Chunk::Keyframe { world, rng, .. }) = chunks.next() else {
+            return Err(ReplayError::MissingStartKeyframe);
+        };
+
+        let mut replay = Self {
+            header,
+            last_tick: world.tick,
+            start: (world, rng),
+            events: BTreeMap::new(),
+            keyframes: BTreeMap::new(),
+        };
+
+        for chunk in chunks {
+            match chunk {
+                Chunk::Tick { tick, events } => {
+                    replay.events.entry(tick).or_default().extend(events);
+                    replay.last_tick = replay.last_tick.max(tick);
+                }
+                Chunk::Keyframe { tick, world, rng } => {
+                    replay.keyframes.insert(tick, (world, rng));
+                    replay.last_tick = replay.last_tick.max(tick);
+                }
+                Chunk::End { tick } => replay.last_tick = replay.last_tick.max(tick),
+                // a second header never gets written, nothing to rebuild from it
+                Chunk::Header(_) => {}
+            }
+        }
+
+        Ok(replay)
+    }
+
+    pub fn first_tick(&self) -> u64 {
+        self.start.0.tick
+    }
+
+    pub fn last_tick(&self) -> u64 {
+        self.last_tick
+    }
+
+    /// Re-simulates the match up to and including `tick`.
+    pub fn world_at(&self, tick: u64) -> Result<GameWorld, ReplayError> {
+        if tick < self.first_tick() || tick > self.last_tick {
+            return Err(ReplayError::TickOutOfRange {
+                tick,
+                first: self.first_tick(),
+                last: self.last_tick,
+            });
+        }
+
+        let (mut world, mut rng) = self.start.clone();
+        while world.tick < tick {
+            self.step(&mut world, &mut rng);
+        }
+        Ok(world)
+    }
+
+    /// Re-simulates the whole match, comparing against every stored keyframe.
+    pub fn verify(&self) -> Verification {
+        let (mut world, mut rng) = self.start.clone();
+        let mut keyframes_checked = 0;
+
+        while world.tick < self.last_tick {
+            self.step(&mut world, &mut rng);
+
+            if let Some((expected, expected_rng)) = self.keyframes.get(&world.tick) {
+                keyframes_checked += 1;
+                let mut found = Vec::new();
+                if *expected != world {
+                    found = differences(expected, &world);
+                }
+                if *expected_rng != rng {
+                    found.push("rng state".into());
+                }
+                if !found.is_empty() {
+                    return Verification {
+                        ticks: world.tick - self.first_tick(),
+                        keyframes_checked,
+                        divergence: Some(Divergence {
+                            tick: world.tick,
+                            differences: found,
+                        }),
+                    };
+                }
+            }
+        }
+
+        Verification {
+            ticks: world.tick - self.first_tick(),
+            keyframes_checked,
+            divergence: None,
+        }
+    }
+
+    /// Applies the events recorded for the next tick, then advances the world to it.
+    fn step(&self, world: &mut GameWorld, rng: &mut WorldRng) {
+        if let Some(events) = self.events.get(&(world.tick + 1)) {
+            for event in events {
+                apply_event(world, event);
+            }
+        }
+        world.update(rng);
+    }
+}
+
+/// Replays one event the same way the game loop applied it.
+pub fn apply_event(world: &mut GameWorld, event: &RecordedEvent) {
+    match event {
+        RecordedEvent::Connect { player_id } => world.add_player(*player_id),
+        RecordedEvent::Disconnect { player_id } => {
+            world.remove_player(*player_id);
+        }
+        RecordedEvent::Input { player_id, input } => world.apply_input(*player_id, input),
+        RecordedEvent::SetRules(rules) => world.set_rules(rules.clone()),
+        RecordedEvent::ConnectBot { player_id } => world.add_bot(*player_id),
+    }
+}
+
+/// Names the parts of the world that differ, to point at where to look.
+fn differences(expected: &GameWorld, actual: &GameWorld) -> Vec<String> {
+    let mut out = Vec::new();
+
+    let mut ids: Vec<u32> = expected
+        .players
+        .keys()
+        .chain(actual.players.keys())
+        .copied()
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    for id in ids {
+        match (expected.players.get(&id), actual.players.get(&id)) {
+            (Some(e), Some(a)) if e != a => {
+                out.push(format!("player {id}: expected {e:?}, got {a:?}"))
+            }
+            (Some(_), None) => out.push(format!("player {id}: missing")),
+            (None, Some(_)) => out.push(format!("player {id}: unexpected")),
+            _ => {}
+        }
+    }
+
+    if expected.bullets != actual.bullets {
+        out.push(format!(
+            "bullets: expected {}, got {}",
+            expected.bullets.len(),
+            actual.bullets.len()
+        ));
+    }
+    if expected.asteroids != actual.asteroids {
+        out.push(format!(
+            "asteroids: expected {}, got {}",
+            expected.asteroids.len(),
+            actual.asteroids.len()
+        ));
+    }
+    if expected.rules != actual.rules {
+        out.push("rules".into());
+    }
+    if out.is_empty() {
+        out.push("counters or timers".into());
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{MatchRecorder, read_chunks};
+    use common::packet::{InputAction, PlayerInput};
+    use common::rules::GameRules;
+    use std::path::{Path, PathBuf};
+
+    /// A match recorded with the current format. Changing the simulation or the
+    /// format breaks this test: bump `RECORDING_FORMAT_VERSION` and either keep a
+    /// decoder for the old one or re-record the fixture with `record_match`.
+    const FIXTURE: &[u8] = include_bytes!("../fixtures/match-v4.arena");
+    const FIXTURE_TICKS: u64 = 300;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("arena-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Plays `ticks` ticks of a match the way the game loop does, recording it into
+    /// `dir`. Returns the recording and the live world after each tick, by tick.
+    async fn record_match(dir: &Path, ticks: u64) -> (PathBuf, Vec<GameWorld>) {
+        let mut world = GameWorld::new();
+        let mut rng = WorldRng::new(7);
+        let mut recorder = MatchRecorder::start(dir, 1, 60, 7, 50, &world, &rng);
+        let mut live = vec![world.clone()];
+
+        for tick in 1..=ticks {
+            let mut events = Vec::new();
+            if tick == 1 {
+                events.push(RecordedEvent::Connect { player_id: 1 });
+                events.push(RecordedEvent::Connect { player_id: 2 });
+                events.push(RecordedEvent::ConnectBot { player_id: 3 });
+            }
+            if tick == ticks / 2 {
+                events.push(RecordedEvent::SetRules(GameRules {
+                    bullet_speed: 30.0,
+                    ..GameRules::default()
+                }));
+            }
+            if tick == ticks * 3 / 4 {
+                events.push(RecordedEvent::Disconnect { player_id: 2 });
+            }
+            let actions = [
+                InputAction::Thrust,
+                InputAction::RotateLeft,
+                InputAction::Shoot,
+                InputAction::RotateRight,
+            ];
+            for player_id in 1..=3 {
+                events.push(RecordedEvent::Input {
+                    player_id,
+                    input: PlayerInput {
+                        id: player_id,
+                        seq: tick as u32,
+                        action: actions[((tick + player_id as u64) % 4) as usize],
+                    },
+                });
+            }
+
+            for event in events {
+                // the game loop only records inputs of players still in the world
+                if let RecordedEvent::Input { player_id, .. } = &event
+                    && !world.players.contains_key(player_id)
+                {
+                    continue;
+                }
+                apply_event(&mut world, &event);
+                recorder.record(event);
+            }
+            world.update(&mut rng);
+            recorder.end_tick(&world, &rng);
+            live.push(world.clone());
+        }
+
+        recorder.finish(&world).await.unwrap().unwrap();
+        let path = std::fs::read_dir(dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        (path, live)
+    }
+
+    #[tokio::test]
+    async fn replays_land_on_the_recorded_worlds() {
+        let dir = scratch_dir("replay-round-trip");
+        let (path, live) = record_match(&dir, 240).await;
+        let chunks = read_chunks(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let replay = Replay::from_chunks(chunks).unwrap();
+
+        assert_eq!(replay.first_tick(), 0);
+        assert_eq!(replay.last_tick(), 240);
+        let verification = replay.verify();
+        assert!(verification.divergence.is_none());
+        assert_eq!(verification.ticks, 240);
+        assert_eq!(verification.keyframes_checked, 4);
+
+        for tick in [0, 1, 37, 120, 181, 240] {
+            assert!(
+                replay.world_at(tick).unwrap() == live[tick as usize],
+                "tick {tick}"
+            );
+        }
+        assert!(replay.world_at(241).is_err());
+    }
+
+    #[test]
+    fn replays_the_checked_in_recording() {
+        let chunks = read_chunks(FIXTURE).unwrap();
+        let replay = Replay::from_chunks(chunks).unwrap();
+
+        assert_eq!(replay.last_tick(), FIXTURE_TICKS);
+        let verification = replay.verify();
+        if let Some(divergence) = &verification.divergence {
+            panic!(
+                "diverged at tick {}: {:?}",
+                divergence.tick, divergence.differences
+            );
+        }
+        assert_eq!(verification.ticks, FIXTURE_TICKS);
+    }
+
+    #[tokio::test]
+    #[ignore = "writes fixtures/match-v4.arena, run by hand after a format change"]
+    async fn record_fixture() {
+        let dir = scratch_dir("replay-fixture");
+        let (path, _) = record_match(&dir, FIXTURE_TICKS).await;
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/match-v4.arena");
+        std::fs::create_dir_all(fixture.parent().unwrap()).unwrap();
+        std::fs::copy(&path, fixture).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
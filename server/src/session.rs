@@ -0,0 +1,231 @@
+use common::secure::{Direction, OpenError, PacketOpener, PacketSealer, SessionKey};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Per-session UDP keys, created by the handshake and used by both directions.
+pub type Sessions = Arc<Mutex<SessionTable>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    Player,
+    /// Receives snapshots and events, its inputs are ignored.
+    Spectator,
+}
+
+struct Session {
+    /// Account behind the session, spectators have one too.
+    player_id: u32,
+    kind: SessionKind,
+    /// Where the handshake came from, moved by later datagrams if the client's NAT rebinds.
+    addr: SocketAddr,
+    opener: PacketOpener,
+    sealer: PacketSealer,
+    /// When the last authentic datagram came in, or the handshake for a fresh session.
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+pub struct SessionTable {
+    sessions: HashMap<u32, Session>,
+    by_addr: HashMap<SocketAddr, u32>,
+    /// Addresses no session uses anymore, kept until the broadcaster drops their metrics.
+    dropped: Vec<SocketAddr>,
+}
+
+impl SessionTable {
+    /// Creates a session bound to `addr` under the key the handshake derived,
+    /// replacing any older session of the same player or from the same address.
+    pub fn create(
+        &mut self,
+        player_id: u32,
+        kind: SessionKind,
+        addr: SocketAddr,
+        key: &SessionKey,
+        encrypted: bool,
+    ) -> u32 {
+        self.remove_player(player_id);
+        self.remove_addr(&addr);
+
+        let mut rng = rand::rng();
+        let session_id = loop {
+            let id = rng.next_u32();
+            if id != 0 && !self.sessions.contains_key(&id) {
+                break id;
+            }
+        };
+
+        self.sessions.insert(
+            session_id,
+            Session {
+                player_id,
+                kind,
+                addr,
+                opener: PacketOpener::new(key, session_id, Direction::ClientToServer, encrypted),
+                sealer: PacketSealer::new(key, session_id, Direction::ServerToClient, encrypted),
+                last_seen: Instant::now(),
+            },
+        );
+        self.by_addr.insert(addr, session_id);
+        session_id
+    }
+
+    /// Verifies a datagram and returns the sender's player id and session kind with the payload.
+    /// The session follows the sender to `addr`, so a NAT rebinding doesn't cut it off.
+    pub fn open(
+        &mut self,
+        addr: SocketAddr,
+        datagram: &[u8],
+    ) -> Result<(u32, SessionKind, Vec<u8>), OpenError> {
+        let session_id = common::secure::peek_session_id(datagram).ok_or(OpenError::Truncated)?;
+        let session = self
+            .sessions
+            .get_mut(&session_id)
+            .ok_or(OpenError::WrongSession)?;
+        let payload = session.opener.open(datagram)?;
+        session.last_seen = Instant::now();
+
+        if session.addr != addr {
+            self.by_addr.remove(&session.addr);
+            self.dropped.push(session.addr);
+            session.addr = addr;
+            self.by_addr.insert(addr, session_id);
+        }
+        Ok((session.player_id, session.kind, payload))
+    }
+
+    /// Seals `payload` for every session.
+    pub fn seal_for_all(&mut self, payload: &[u8]) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.sessions
+            .values_mut()
+            .map(|session| (session.addr, session.sealer.seal(payload)))
+            .collect()
+    }
+
+    /// Ends the session behind `addr`, returning its player id.
+    pub fn remove_addr(&mut self, addr: &SocketAddr) -> Option<u32> {
+        let session_id = self.by_addr.remove(addr)?;
+        self.dropped.push(*addr);
+        self.sessions
+            .remove(&session_id)
+            .map(|session| session.player_id)
+    }
+
+    /// Ends every session bound to `ip`, returning their player ids.
+    pub fn remove_ip(&mut self, ip: IpAddr) -> Vec<u32> {
+        let addrs: Vec<SocketAddr> = self
+            .by_addr
+            .keys()
+            .filter(|addr| addr.ip() == ip)
+            .cloned()
+            .collect();
+        addrs
+            .iter()
+            .filter_map(|addr| self.remove_addr(addr))
+            .collect()
+    }
+
+    pub fn remove_player(&mut self, player_id: u32) {
+        let by_addr = &mut self.by_addr;
+        let dropped = &mut self.dropped;
+        self.sessions.retain(|_, session| {
+            if session.player_id != player_id {
+                return true;
+            }
+            by_addr.remove(&session.addr);
+            dropped.push(session.addr);
+            false
+        });
+    }
+
+    /// Ends sessions that sent nothing for `timeout`, returning their player ids and addresses.
+    pub fn expire(&mut self, timeout: Duration) -> Vec<(u32, SocketAddr)> {
+        let mut expired = Vec::new();
+        let by_addr = &mut self.by_addr;
+        self.sessions.retain(|_, session| {
+            if session.last_seen.elapsed() < timeout {
+                return true;
+            }
+            by_addr.remove(&session.addr);
+            expired.push((session.player_id, session.addr));
+            false
+        });
+        self.dropped.extend(expired.iter().map(|&(_, addr)| addr));
+        expired
+    }
+
+    /// Addresses sessions stopped using since the last call, however they ended or moved.
+    pub fn take_dropped(&mut self) -> Vec<SocketAddr> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Player sessions that stay if `player_id` logs in from `addr`, spectators don't take a slot.
+    pub fn other_players(&self, player_id: u32, addr: SocketAddr) -> usize {
+        self.sessions
+            .values()
+            .filter(|session| {
+                session.kind == SessionKind::Player
+                    && session.player_id != player_id
+                    && session.addr != addr
+            })
+            .count()
+    }
+
+    pub fn has_player(&self, player_id: u32) -> bool {
+        self.sessions
+            .values()
+            .any(|session| session.player_id == player_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: SessionKey = SessionKey([3; 32]);
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn every_way_a_session_ends_or_moves_drops_its_address() {
+        let mut table = SessionTable::default();
+        let session_id = table.create(1, SessionKind::Player, addr(1), &KEY, true);
+        table.create(2, SessionKind::Spectator, addr(2), &KEY, true);
+        table.create(3, SessionKind::Player, addr(3), &KEY, true);
+        table.create(
+            4,
+            SessionKind::Player,
+            SocketAddr::from(([10, 0, 0, 2], 4)),
+            &KEY,
+            true,
+        );
+        assert!(table.take_dropped().is_empty());
+
+        // the client's NAT rebinds
+        let mut sealer = PacketSealer::new(&KEY, session_id, Direction::ClientToServer, true);
+        table.open(addr(11), &sealer.seal(b"input")).unwrap();
+        assert_eq!(table.take_dropped(), [addr(1)]);
+
+        table.remove_player(1);
+        assert_eq!(table.take_dropped(), [addr(11)]);
+        table.remove_addr(&addr(2));
+        assert_eq!(table.take_dropped(), [addr(2)]);
+        table.remove_ip(addr(3).ip());
+        assert_eq!(table.take_dropped(), [addr(3)]);
+        table.expire(Duration::ZERO);
+        assert_eq!(table.take_dropped(), [SocketAddr::from(([10, 0, 0, 2], 4))]);
+        assert!(table.is_empty());
+    }
+}
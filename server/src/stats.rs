@@ -0,0 +1,95 @@
+//! Per-account stats of the running match, reported to the auth service when it ends.
+
+use common::game_world::GameWorld;
+use common::player::Player;
+use common::registry::MatchPlayerResult;
+use std::collections::HashMap;
+
+/// Accounts that played in the current match, including the ones that already left.
+/// Times are simulated world time, like the match clock.
+#[derive(Default)]
+pub struct MatchLedger {
+    players: HashMap<u32, Entry>,
+}
+
+#[derive(Default)]
+struct Entry {
+    username: String,
+    /// When the player's current ship spawned, unset while the player is away.
+    joined_at_ms: Option<u64>,
+    played_ms: u64,
+    result: MatchPlayerResult,
+}
+
+/// What a finished match comes down to.
+pub struct MatchOutcome {
+    pub players: Vec<MatchPlayerResult>,
+    /// Usernames of the players with the top score, none if nobody scored.
+    pub winners: Vec<String>,
+}
+
+impl Entry {
+    /// Adds what `ship` did to the totals, the ship is about to go away.
+    fn bank(&mut self, ship: &Player, now_ms: u64) {
+        if let Some(joined_at_ms) = self.joined_at_ms.take() {
+            self.played_ms += now_ms.saturating_sub(joined_at_ms);
+        }
+        self.result.kills += ship.kills;
+        self.result.deaths += ship.deaths;
+        self.result.asteroids_destroyed += ship.asteroids_destroyed;
+        self.result.score += ship.score;
+    }
+}
+
+impl MatchLedger {
+    pub fn join(&mut self, player_id: u32, username: &str, now_ms: u64) {
+        let entry = self.players.entry(player_id).or_insert_with(|| Entry {
+            result: MatchPlayerResult {
+                user_id: player_id,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        entry.username = username.to_string();
+        entry.joined_at_ms.get_or_insert(now_ms);
+    }
+
+    /// Keeps the stats of a ship that leaves the world, or gets replaced by a new one.
+    pub fn leave(&mut self, ship: &Player, now_ms: u64) {
+        if let Some(entry) = self.players.get_mut(&ship.id) {
+            entry.bank(ship, now_ms);
+        }
+    }
+
+    /// Totals of everyone who played, with the ships still in `world`.
+    /// Players that were there for less than a second didn't play.
+    pub fn finish(self, world: &GameWorld) -> MatchOutcome {
+        let mut entries: Vec<Entry> = self
+            .players
+            .into_iter()
+            .map(|(player_id, mut entry)| {
+                if let Some(ship) = world.players.get(&player_id) {
+                    entry.bank(ship, world.time_ms);
+                }
+                entry.result.seconds_played = entry.played_ms / 1000;
+                entry
+            })
+            .filter(|entry| entry.result.seconds_played > 0)
+            .collect();
+
+        let top_score = entries.iter().map(|entry| entry.result.score).max();
+        let mut winners = Vec::new();
+        for entry in &mut entries {
+            if entry.result.score > 0 && Some(entry.result.score) == top_score {
+                entry.result.won = true;
+                winners.push(entry.username.clone());
+            }
+        }
+        winners.sort();
+
+        MatchOutcome {
+            players: entries.into_iter().map(|entry| entry.result).collect(),
+            winners,
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use common::auth::Claims;
+use common::handshake::RejectReason;
+use common::utils::current_time_ms;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{DecodingKey, Validation, decode};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+/// Checks auth service tokens offline, with the secret both services share.
+/// Join tickets are only accepted when addressed to this server.
+pub struct TokenVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+/// Who a verified token belongs to.
+#[derive(Debug, Clone)]
+pub struct PlayerIdentity {
+    pub player_id: u32,
+    pub username: String,
+    /// Set when the player came in with a join ticket from matchmaking.
+    pub ticket: Option<Ticket>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ticket {
+    pub id: String,
+    pub expires_at: u64,
+}
+
+impl TokenVerifier {
+    /// `audience` is the public address tickets must name, plain access tokens carry none.
+    pub fn new(secret: &str, audience: &str) -> Self {
+        let mut validation = Validation::default();
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        validation.set_audience(&[audience]);
+        Self {
+            key: DecodingKey::from_secret(secret.as_bytes()),
+            validation,
+        }
+    }
+
+    pub fn verify(&self, token: &str) -> Result<PlayerIdentity, RejectReason> {
+        let claims = decode::<Claims>(token, &self.key, &self.validation)
+            .map_err(|e| match e.kind() {
+                ErrorKind::ExpiredSignature => RejectReason::ExpiredToken,
+                _ => RejectReason::InvalidToken,
+            })?
+            .claims;
+
+        let player_id = claims.player_id().ok_or(RejectReason::InvalidToken)?;
+        let ticket = match (claims.aud, claims.jti) {
+            (None, None) => None,
+            (Some(_), Some(id)) => Some(Ticket {
+                id,
+                expires_at: claims.exp,
+            }),
+            _ => return Err(RejectReason::InvalidToken),
+        };
+        Ok(PlayerIdentity {
+            player_id,
+            username: claims.username,
+            ticket,
+        })
+    }
+}
+
+/// Join tickets this server accepted, so each is used once and the auth service
+/// learns which reservations were filled.
+#[derive(Default)]
+pub struct TicketBook {
+    /// Ticket ids with their expiry and the address that redeemed them, kept until
+    /// a replay would fail on expiry anyway.
+    redeemed: Mutex<HashMap<String, (u64, SocketAddr)>>,
+    unreported: Mutex<Vec<String>>,
+}
+
+impl TicketBook {
+    /// False if the ticket was already used from another address. The same address
+    /// may redeem it again, a client resending its login after a lost reply isn't turned away.
+    pub fn redeem(&self, ticket: &Ticket, addr: SocketAddr) -> bool {
+        let now = current_time_ms() / 1000;
+        let mut redeemed = self.redeemed.lock().unwrap();
+        redeemed.retain(|_, (expires_at, _)| *expires_at >= now);
+        if let Some((_, redeemed_by)) = redeemed.get(&ticket.id) {
+            return *redeemed_by == addr;
+        }
+        redeemed.insert(ticket.id.clone(), (ticket.expires_at, addr));
+        self.unreported.lock().unwrap().push(ticket.id.clone());
+        true
+    }
+
+    /// Tickets redeemed since the last call, for the next heartbeat.
+    pub fn take_unreported(&self) -> Vec<String> {
+        std::mem::take(&mut *self.unreported.lock().unwrap())
+    }
+}